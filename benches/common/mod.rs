@@ -0,0 +1,120 @@
+/*
+ * This file is part of Astarte.
+ *
+ * Copyright 2021 SECO Mind Srl
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *    http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Client connected to a local broker, shared by the benchmarks
+
+use astarte_sdk::builder::AstarteBuilder;
+use astarte_sdk::AstarteSdk;
+use openssl::{
+    asn1::Asn1Time,
+    bn::BigNum,
+    ec::{EcGroup, EcKey},
+    hash::MessageDigest,
+    nid::Nid,
+    pkey::PKey,
+    x509::{X509Builder, X509NameBuilder},
+};
+use tokio::io::AsyncWriteExt;
+use tokio::net::TcpListener;
+
+/// CONNACK accepting the connection
+const CONNACK: [u8; 4] = [0x20, 0x02, 0x00, 0x00];
+
+/// Accept the clients on a local port, acknowledge their connection and discard everything
+/// they send
+async fn discarding_broker() -> u16 {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+
+    tokio::spawn(async move {
+        while let Ok((mut socket, _)) = listener.accept().await {
+            tokio::spawn(async move {
+                if socket.write_all(&CONNACK).await.is_ok() {
+                    let _ = tokio::io::copy(&mut socket, &mut tokio::io::sink()).await;
+                }
+            });
+        }
+    });
+
+    port
+}
+
+/// Self-signed client certificate and its key, PEM encoded
+fn client_certificate() -> (String, String) {
+    let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).unwrap();
+    let pkey = PKey::from_ec_key(EcKey::generate(&group).unwrap()).unwrap();
+
+    let mut name = X509NameBuilder::new().unwrap();
+    name.append_entry_by_nid(Nid::COMMONNAME, "bench/bench")
+        .unwrap();
+    let name = name.build();
+
+    let mut cert = X509Builder::new().unwrap();
+    cert.set_version(2).unwrap();
+    cert.set_serial_number(&BigNum::from_u32(1).unwrap().to_asn1_integer().unwrap())
+        .unwrap();
+    cert.set_subject_name(&name).unwrap();
+    cert.set_issuer_name(&name).unwrap();
+    cert.set_pubkey(&pkey).unwrap();
+    cert.set_not_before(&Asn1Time::days_from_now(0).unwrap())
+        .unwrap();
+    cert.set_not_after(&Asn1Time::days_from_now(1).unwrap())
+        .unwrap();
+    cert.sign(&pkey, MessageDigest::sha256()).unwrap();
+
+    let cert_pem = String::from_utf8(cert.build().to_pem().unwrap()).unwrap();
+    let key_pem = String::from_utf8(pkey.private_key_to_pem_pkcs8().unwrap()).unwrap();
+
+    (cert_pem, key_pem)
+}
+
+/// Client with the example interfaces, connected to a local broker discarding the messages
+///
+/// The broker url and the client certificate are set on the builder, so the Pairing API is
+/// never contacted. `configure` can change the builder before connecting, and a task polls the
+/// client in the background.
+pub async fn connected_device<F>(configure: F) -> AstarteSdk
+where
+    F: FnOnce(&mut AstarteBuilder),
+{
+    let port = discarding_broker().await;
+    let (cert_pem, key_pem) = client_certificate();
+
+    let mut options = AstarteBuilder::new("bench", "bench", "bench", "https://localhost/pairing");
+    options
+        .add_interface_files("examples/interfaces/")
+        .unwrap()
+        .broker_url(&format!("mqtt://127.0.0.1:{}", port))
+        .unwrap()
+        .with_client_certificate_from_pem(cert_pem, key_pem)
+        .unwrap();
+    configure(&mut options);
+
+    options.build().await.unwrap();
+    let device = options.connect().await.unwrap();
+
+    let mut poller = device.clone();
+    tokio::spawn(async move {
+        // the broker never answers the pings, reconnect when the keepalive expires
+        loop {
+            let _ = poller.poll().await;
+        }
+    });
+
+    device
+}
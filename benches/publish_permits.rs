@@ -0,0 +1,108 @@
+/*
+ * This file is part of Astarte.
+ *
+ * Copyright 2021 SECO Mind Srl
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *    http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Time and peak memory of many concurrent sends, with and without
+//! `max_concurrent_publishes`
+//!
+//! The peak memory used by a batch is printed before measuring its time.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use astarte_sdk::AstarteSdk;
+use criterion::{criterion_group, criterion_main, Criterion};
+
+mod common;
+
+/// Sends of a batch
+const SENDS: usize = 1000;
+
+/// Size of each string sent
+const VALUE_SIZE: usize = 16 * 1024;
+
+/// System allocator keeping track of the peak of the allocated memory
+struct PeakAllocator {
+    current: AtomicUsize,
+    peak: AtomicUsize,
+}
+
+unsafe impl GlobalAlloc for PeakAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc(layout);
+        if !ptr.is_null() {
+            let current = self.current.fetch_add(layout.size(), Ordering::Relaxed) + layout.size();
+            self.peak.fetch_max(current, Ordering::Relaxed);
+        }
+
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout);
+        self.current.fetch_sub(layout.size(), Ordering::Relaxed);
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: PeakAllocator = PeakAllocator {
+    current: AtomicUsize::new(0),
+    peak: AtomicUsize::new(0),
+};
+
+/// Send the batch concurrently, the values are copied by the client once it starts sending
+async fn send_all(device: &AstarteSdk, value: &str) {
+    let sends = (0..SENDS).map(|_| device.send("com.test.Everything", "/string", value));
+
+    for res in futures::future::join_all(sends).await {
+        res.unwrap();
+    }
+}
+
+fn concurrent_sends(c: &mut Criterion) {
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let value = "x".repeat(VALUE_SIZE);
+    let value = value.as_str();
+
+    let mut group = c.benchmark_group("concurrent_sends");
+    group.sample_size(10);
+    for (name, limit) in [("unlimited", None), ("limit_8", Some(8))] {
+        let device = runtime.block_on(common::connected_device(|options| {
+            if let Some(limit) = limit {
+                options.max_concurrent_publishes(limit).unwrap();
+            }
+        }));
+        let device = &device;
+
+        let baseline = ALLOCATOR.current.load(Ordering::Relaxed);
+        ALLOCATOR.peak.store(baseline, Ordering::Relaxed);
+        runtime.block_on(send_all(device, value));
+        let peak = ALLOCATOR
+            .peak
+            .load(Ordering::Relaxed)
+            .saturating_sub(baseline);
+        println!("{}: peak memory of a batch {} KiB", name, peak / 1024);
+
+        group.bench_function(name, |b| {
+            b.to_async(&runtime).iter(move || send_all(device, value))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, concurrent_sends);
+criterion_main!(benches);
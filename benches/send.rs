@@ -0,0 +1,84 @@
+/*
+ * This file is part of Astarte.
+ *
+ * Copyright 2021 SECO Mind Srl
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *    http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Cost of the validation of the values sent to Astarte, compared with
+//! `send_individual_no_validate`
+
+use astarte_sdk::serializer::{AstarteSerializer, BsonSerializer};
+use astarte_sdk::types::AstarteType;
+use astarte_sdk::{Aggregation, Interface};
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+
+mod common;
+
+const EVERYTHING: &str = include_str!("../examples/interfaces/com.test.Everything.json");
+
+/// The steps of a send that don't need a client
+fn payload(c: &mut Criterion) {
+    let interface: Interface = EVERYTHING.parse().unwrap();
+    let value = AstarteType::Integer(42);
+    let aggregation = Aggregation::Individual(value.clone());
+
+    let mut group = c.benchmark_group("payload");
+    group.bench_function("validate", |b| {
+        b.iter(|| {
+            let mapping = interface.mapping(black_box("/integer")).unwrap();
+            assert!(*black_box(&value) == mapping.mapping_type());
+        })
+    });
+    group.bench_function("serialize", |b| {
+        b.iter(|| {
+            BsonSerializer
+                .serialize(black_box(&aggregation), None)
+                .unwrap()
+        })
+    });
+    group.finish();
+}
+
+fn send(c: &mut Criterion) {
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let device = runtime.block_on(common::connected_device(|_| {}));
+    let device = &device;
+
+    let mut group = c.benchmark_group("send");
+    group.bench_function("validated", |b| {
+        b.to_async(&runtime).iter(move || async move {
+            device
+                .send("com.test.Everything", "/integer", 42)
+                .await
+                .unwrap()
+        })
+    });
+    group.bench_function("no_validate", |b| {
+        b.to_async(&runtime).iter(move || async move {
+            device
+                .send_individual_no_validate(
+                    "com.test.Everything",
+                    "/integer",
+                    AstarteType::Integer(42),
+                )
+                .await
+                .unwrap()
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, payload, send);
+criterion_main!(benches);
@@ -0,0 +1,106 @@
+/*
+ * This file is part of Astarte.
+ *
+ * Copyright 2021 SECO Mind Srl
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *    http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::Duration;
+
+use rand::Rng;
+
+/// Exponential backoff between the attempts to reconnect to the broker
+///
+/// Shared by all the clones of the client
+#[derive(Debug)]
+pub(crate) struct Backoff {
+    min: Duration,
+    max: Duration,
+    failures: AtomicU32,
+}
+
+impl Backoff {
+    pub(crate) fn new(min: Duration, max: Duration) -> Self {
+        Backoff {
+            min,
+            max,
+            failures: AtomicU32::new(0),
+        }
+    }
+
+    /// Delay before the reconnection following `failures` consecutive failed connections,
+    /// doubling from `min` up to `max`
+    fn delay(&self, failures: u32) -> Duration {
+        if failures == 0 {
+            return Duration::ZERO;
+        }
+
+        2u32.checked_pow(failures - 1)
+            .and_then(|factor| self.min.checked_mul(factor))
+            .map_or(self.max, |delay| delay.min(self.max))
+    }
+
+    /// Time to wait before connecting again, `None` if the connection didn't fail
+    ///
+    /// The delay is randomly reduced up to half, so devices disconnected together don't
+    /// reconnect all at the same time.
+    pub(crate) fn next_delay(&self) -> Option<Duration> {
+        let delay = self.delay(self.failures.load(Ordering::Relaxed));
+        if delay.is_zero() {
+            return None;
+        }
+
+        Some(rand::thread_rng().gen_range(delay / 2..=delay))
+    }
+
+    pub(crate) fn failed(&self) {
+        self.failures.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn connected(&self) {
+        self.failures.store(0, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::Backoff;
+
+    #[test]
+    fn test_backoff_delay() {
+        let backoff = Backoff::new(Duration::from_millis(100), Duration::from_secs(1));
+
+        assert_eq!(backoff.delay(0), Duration::ZERO);
+        assert_eq!(backoff.delay(1), Duration::from_millis(100));
+        assert_eq!(backoff.delay(2), Duration::from_millis(200));
+        assert_eq!(backoff.delay(4), Duration::from_millis(800));
+        assert_eq!(backoff.delay(5), Duration::from_secs(1));
+        assert_eq!(backoff.delay(u32::MAX), Duration::from_secs(1));
+
+        assert_eq!(backoff.next_delay(), None);
+
+        backoff.failed();
+        backoff.failed();
+        for _ in 0..100 {
+            let delay = backoff.next_delay().unwrap();
+            assert!(delay >= Duration::from_millis(100) && delay <= Duration::from_millis(200));
+        }
+
+        backoff.connected();
+        assert_eq!(backoff.next_delay(), None);
+    }
+}
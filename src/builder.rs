@@ -16,26 +16,46 @@
  * limitations under the License.
  */
 
-use log::debug;
 use openssl::error::ErrorStack;
+use openssl::pkey::PKey;
+use openssl::x509::X509;
 use pairing::PairingError;
 use rumqttc::{AsyncClient, ClientConfig, MqttOptions, Transport};
 use rustls::ServerCertVerifier;
 use rustls::{internal::pemfile, Certificate, PrivateKey};
 use std::collections::HashMap;
 use std::fmt::Debug;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Semaphore;
 use url::Url;
 
 use interface::traits::Interface as InterfaceTrait;
 pub use interface::Interface;
 
+use crate::backoff::Backoff;
 use crate::crypto::Bundle;
 use crate::database::AstarteDatabase;
+use crate::handler::InterfaceHandler;
 use crate::interface::{self, Ownership};
 use crate::interfaces::Interfaces;
-use crate::{pairing, AstarteSdk};
+use crate::observability::{NoOpObservabilityHook, ObservabilityHook};
+use crate::recorder::EventRecorder;
+use crate::serializer::AstarteSerializer;
+use crate::{pairing, AstarteSdk, SyncStrategy};
+
+/// Default maximum size of the mqtt messages
+const DEFAULT_MAX_MESSAGE_SIZE: usize = 128 * 1024;
+
+/// Default maximum size of the payloads published by the client
+const DEFAULT_MAX_PAYLOAD_SIZE: usize = 64 * 1024;
+
+/// Maximum length in bytes of a client id that every MQTT 3.1 broker accepts
+const MAX_CLIENT_ID_LEN: usize = 23;
+
+/// Default size of the channels returned by [`AstarteSdk::subscribe_all`]
+const DEFAULT_EVENT_CHANNEL_SIZE: usize = 50;
 
 /// Options for astarte builder
 #[derive(Debug, Clone)]
@@ -43,10 +63,78 @@ pub struct BuildOptions {
     private_key: PrivateKey,
     csr: String,
     certificate_pem: Vec<Certificate>,
-    broker_url: Url,
+    pub(crate) broker_url: Url,
     mqtt_opts: MqttOptions,
 }
 
+/// Accepts any server certificate, used when ssl errors are ignored
+struct OkVerifier {}
+
+impl ServerCertVerifier for OkVerifier {
+    fn verify_server_cert(
+        &self,
+        _: &rustls::RootCertStore,
+        _: &[Certificate],
+        _: webpki::DNSNameRef,
+        _: &[u8],
+    ) -> Result<rustls::ServerCertVerified, rustls::TLSError> {
+        Ok(rustls::ServerCertVerified::assertion())
+    }
+}
+
+/// Message of the error returned by [`PinnedCertVerifier`], to recognize it among the
+/// connection errors
+const PIN_MISMATCH: &str = "broker certificate doesn't match the pinned fingerprint";
+
+/// Checks the fingerprint of the broker leaf certificate before handing
+/// the verification to the inner verifier
+struct PinnedCertVerifier {
+    fingerprint: [u8; 32],
+    inner: Arc<dyn ServerCertVerifier>,
+}
+
+impl ServerCertVerifier for PinnedCertVerifier {
+    fn verify_server_cert(
+        &self,
+        roots: &rustls::RootCertStore,
+        presented_certs: &[Certificate],
+        dns_name: webpki::DNSNameRef,
+        ocsp_response: &[u8],
+    ) -> Result<rustls::ServerCertVerified, rustls::TLSError> {
+        let leaf = presented_certs
+            .first()
+            .ok_or(rustls::TLSError::NoCertificatesPresented)?;
+
+        if openssl::sha::sha256(&leaf.0) != self.fingerprint {
+            return Err(rustls::TLSError::General(PIN_MISMATCH.into()));
+        }
+
+        self.inner
+            .verify_server_cert(roots, presented_certs, dns_name, ocsp_response)
+    }
+}
+
+/// Whether `err` or one of its sources was returned by [`PinnedCertVerifier`]
+pub(crate) fn is_pin_mismatch(err: &(dyn std::error::Error + 'static)) -> bool {
+    let mut source = Some(err);
+    while let Some(err) = source {
+        let tls_error = match err.downcast_ref::<std::io::Error>() {
+            // the tls stream wraps the handshake errors in io errors
+            Some(io_error) => io_error
+                .get_ref()
+                .and_then(|inner| inner.downcast_ref::<rustls::TLSError>()),
+            None => err.downcast_ref::<rustls::TLSError>(),
+        };
+        if matches!(tls_error, Some(rustls::TLSError::General(msg)) if msg == PIN_MISMATCH) {
+            return true;
+        }
+
+        source = err.source();
+    }
+
+    false
+}
+
 /// Builder for Astarte client
 ///
 /// ```
@@ -68,6 +156,7 @@ pub struct BuildOptions {
 pub struct AstarteBuilder {
     pub(crate) realm: String,
     pub(crate) device_id: String,
+    pub(crate) device_alias: Option<String>,
     pub(crate) credentials_secret: String,
     pub(crate) pairing_url: String,
     pub(crate) interfaces: HashMap<String, Interface>,
@@ -75,6 +164,26 @@ pub struct AstarteBuilder {
     pub(crate) database: Option<Arc<dyn AstarteDatabase + Sync + Send>>,
     pub(crate) ignore_ssl_errors: bool,
     pub(crate) keepalive: std::time::Duration,
+    pub(crate) max_message_size: usize,
+    pub(crate) max_payload_size: usize,
+    pub(crate) property_resync_timeout: Duration,
+    pub(crate) max_concurrent_publishes: Option<usize>,
+    pub(crate) dead_letter_queue: bool,
+    pub(crate) broker_url: Option<Url>,
+    pub(crate) event_channel_size: usize,
+    pub(crate) record_events: Option<PathBuf>,
+    pub(crate) persistent_session: bool,
+    pub(crate) pinned_certificate: Option<[u8; 32]>,
+    pub(crate) tls_roots: Vec<Certificate>,
+    pub(crate) client_certificate: Option<(Vec<Certificate>, PrivateKey)>,
+    pub(crate) await_puback: bool,
+    pub(crate) reconnect_backoff: Option<(Duration, Duration)>,
+    pub(crate) observability_hook: Arc<dyn ObservabilityHook>,
+    pub(crate) handlers: HashMap<String, Arc<dyn InterfaceHandler>>,
+    pub(crate) serializer: Option<Arc<dyn AstarteSerializer>>,
+    pub(crate) sync_strategy: SyncStrategy,
+    pub(crate) topic_prefix: Option<String>,
+    pub(crate) client_id: Option<String>,
 }
 
 #[derive(thiserror::Error, Debug)]
@@ -94,6 +203,28 @@ pub enum AstarteBuilderError {
     #[error("configuration error")]
     ConfigError(String),
 
+    #[error("interface {name} is already registered")]
+    DuplicateInterface { name: String },
+
+    #[error("interface {name} is already registered with major version {existing}, got {added}")]
+    MajorVersionConflict {
+        name: String,
+        existing: i32,
+        added: i32,
+    },
+
+    #[error("invalid CA certificate")]
+    InvalidCaCertificate,
+
+    #[error("invalid client certificate or private key")]
+    InvalidClientCertificate,
+
+    #[error("invalid broker url {0}")]
+    InvalidBrokerUrl(String),
+
+    #[error("missing environment variable {0}")]
+    MissingEnvVar(&'static str),
+
     #[error("mqtt error")]
     MqttError(#[from] rumqttc::ClientError),
 
@@ -102,6 +233,9 @@ pub enum AstarteBuilderError {
 
     #[error("database error")]
     DbError(#[from] sqlx::Error),
+
+    #[error("astarte error")]
+    AstarteError(#[from] crate::AstarteError),
 }
 
 impl AstarteBuilder {
@@ -109,6 +243,7 @@ impl AstarteBuilder {
         AstarteBuilder {
             realm: realm.to_owned(),
             device_id: device_id.to_owned(),
+            device_alias: None,
             credentials_secret: credentials_secret.to_owned(),
             pairing_url: pairing_url.to_owned(),
             interfaces: HashMap::new(),
@@ -116,33 +251,386 @@ impl AstarteBuilder {
             database: None,
             ignore_ssl_errors: false,
             keepalive: std::time::Duration::from_secs(30),
+            max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+            max_payload_size: DEFAULT_MAX_PAYLOAD_SIZE,
+            property_resync_timeout: crate::SERVER_PROPERTIES_TIMEOUT,
+            max_concurrent_publishes: None,
+            dead_letter_queue: false,
+            broker_url: None,
+            event_channel_size: DEFAULT_EVENT_CHANNEL_SIZE,
+            record_events: None,
+            persistent_session: false,
+            pinned_certificate: None,
+            tls_roots: Vec::new(),
+            client_certificate: None,
+            await_puback: false,
+            reconnect_backoff: None,
+            observability_hook: Arc::new(NoOpObservabilityHook),
+            handlers: HashMap::new(),
+            serializer: None,
+            sync_strategy: SyncStrategy::default(),
+            topic_prefix: None,
+            client_id: None,
         }
     }
 
+    /// Read the pairing url from the `ASTARTE_PAIRING_URL` environment variable
+    pub fn pairing_url_from_env(&mut self) -> Result<&mut Self, AstarteBuilderError> {
+        const PAIRING_URL_VAR: &str = "ASTARTE_PAIRING_URL";
+
+        let pairing_url = std::env::var(PAIRING_URL_VAR)
+            .map_err(|_| AstarteBuilderError::MissingEnvVar(PAIRING_URL_VAR))?;
+
+        Url::parse(&pairing_url).map_err(|err| {
+            AstarteBuilderError::ConfigError(format!("invalid {}: {}", PAIRING_URL_VAR, err))
+        })?;
+
+        self.pairing_url = pairing_url;
+        Ok(self)
+    }
+
     pub fn with_database<T: AstarteDatabase + 'static + Sync + Send>(&mut self, database: T) {
         self.database = Some(Arc::new(database));
     }
 
+    /// Set a human-readable name for the device, included in the client logs
+    ///
+    /// The alias is only used locally, it's not sent to Astarte.
+    pub fn device_alias(&mut self, alias: &str) {
+        self.device_alias = Some(alias.to_owned());
+    }
+
+    /// Use a custom prefix for the mqtt topics instead of `realm/device_id`
+    ///
+    /// Only the topics change, the mqtt client id is still `realm/device_id` or the one set with
+    /// [`with_client_id`](Self::with_client_id).
+    pub fn with_custom_topic_prefix(&mut self, prefix: &str) {
+        self.topic_prefix = Some(prefix.trim_end_matches('/').to_owned());
+    }
+
+    fn topic_prefix(&self) -> String {
+        self.topic_prefix
+            .clone()
+            .unwrap_or_else(|| format!("{}/{}", self.realm, self.device_id))
+    }
+
+    /// Use a custom mqtt client id instead of `realm/device_id`
+    ///
+    /// Astarte tracks the device session by its client id, so this is meant for tests or
+    /// setups with multiple connections. The id must be at most 23 bytes long.
+    pub fn with_client_id(&mut self, client_id: &str) -> Result<&mut Self, AstarteBuilderError> {
+        if client_id.is_empty() || client_id.len() > MAX_CLIENT_ID_LEN {
+            return Err(AstarteBuilderError::ConfigError(format!(
+                "client id must be between 1 and {} bytes long",
+                MAX_CLIENT_ID_LEN
+            )));
+        }
+
+        warn!(
+            "using client id {} instead of {}/{}, Astarte may not track the session",
+            client_id, self.realm, self.device_id
+        );
+        self.client_id = Some(client_id.to_owned());
+        Ok(self)
+    }
+
     /// Set time after which client should ping the broker
     /// if there is no other data exchange
     pub fn set_keep_alive(&mut self, duration: std::time::Duration) {
         self.keepalive = duration;
     }
 
+    /// Connect to the broker at `url` instead of the one returned by the pairing api
+    ///
+    /// The url must be `mqtt://host:port`, or `mqtts://host:port` to connect with TLS
+    pub fn broker_url(&mut self, url: &str) -> Result<&mut Self, AstarteBuilderError> {
+        let broker_url =
+            Url::parse(url).map_err(|_| AstarteBuilderError::InvalidBrokerUrl(url.to_owned()))?;
+
+        let supported_scheme = matches!(broker_url.scheme(), "mqtt" | "mqtts");
+        if !supported_scheme || broker_url.host_str().is_none() || broker_url.port().is_none() {
+            return Err(AstarteBuilderError::InvalidBrokerUrl(url.to_owned()));
+        }
+
+        self.broker_url = Some(broker_url);
+        Ok(self)
+    }
+
+    /// Set the maximum size in bytes of the mqtt messages sent and received by the client,
+    /// 128 KiB by default
+    ///
+    /// It also bounds the size set with [`set_max_payload_size`](Self::set_max_payload_size),
+    /// a bigger payload limit is lowered to it when connecting.
+    pub fn set_max_message_size(&mut self, size: usize) {
+        self.max_message_size = size;
+    }
+
+    /// Set the maximum size in bytes of the payloads published by the client, 64 KiB by default
+    ///
+    /// Bigger payloads fail with [`AstarteError::PayloadTooLarge`](crate::AstarteError::PayloadTooLarge)
+    /// before reaching the broker, which may otherwise close the connection. It can't exceed the
+    /// size set with [`set_max_message_size`](Self::set_max_message_size).
+    pub fn set_max_payload_size(&mut self, size: usize) {
+        self.max_payload_size = size;
+    }
+
+    /// Limit the number of individual values being serialized and published at the same time
+    ///
+    /// The other sends wait for a free slot, which bounds the memory used under heavy load.
+    /// The slot is released once the message is handed to the mqtt client, without waiting
+    /// for the broker acknowledgement.
+    pub fn max_concurrent_publishes(&mut self, n: usize) -> Result<&mut Self, AstarteBuilderError> {
+        if n == 0 {
+            return Err(AstarteBuilderError::ConfigError(
+                "max concurrent publishes must be greater than zero".into(),
+            ));
+        }
+
+        self.max_concurrent_publishes = Some(n);
+
+        Ok(self)
+    }
+
+    /// Set how long [`trigger_property_resync`](AstarteSdk::trigger_property_resync) waits for
+    /// new values before returning, 5 seconds by default
+    pub fn set_property_resync_timeout(&mut self, timeout: Duration) {
+        self.property_resync_timeout = timeout;
+    }
+
+    /// Set the number of events buffered by the channels returned by
+    /// [`subscribe_all`](AstarteSdk::subscribe_all)
+    pub fn set_event_channel_size(&mut self, size: usize) {
+        self.event_channel_size = size;
+    }
+
+    /// Append the data received from Astarte to a JSONL file, for debugging
+    pub fn record_events_to<P: AsRef<Path>>(&mut self, path: P) {
+        self.record_events = Some(path.as_ref().to_owned());
+    }
+
+    /// Ask the broker to keep the session between connections
+    ///
+    /// The broker keeps the subscriptions and queues the messages for the device while it's
+    /// offline, and when the session is resumed the introspection, the empty cache and the
+    /// device-owned properties are not sent again. The session is identified by the mqtt
+    /// client id, `realm/device_id` unless another one is set with
+    /// [`with_client_id`](Self::with_client_id).
+    pub fn persistent_session(&mut self, enabled: bool) {
+        self.persistent_session = enabled;
+    }
+
     pub fn ignore_ssl_errors(&mut self) {
         self.ignore_ssl_errors = true;
     }
 
+    /// Pin the broker certificate to the SHA-256 fingerprint of its DER encoding
+    ///
+    /// The TLS handshake with the broker fails if the leaf certificate it presents
+    /// doesn't match the fingerprint, with
+    /// [`AstarteError::CertificatePinMismatch`](crate::AstarteError::CertificatePinMismatch)
+    pub fn pin_broker_certificate(&mut self, fingerprint: [u8; 32]) {
+        self.pinned_certificate = Some(fingerprint);
+    }
+
+    /// Trust the PEM encoded CA certificates in `pem`, in addition to the ones of the platform
+    ///
+    /// The certificates are used to verify both the Pairing API and the broker, e.g. when they
+    /// are signed by a private CA. Can be called multiple times to add more certificates.
+    pub fn with_tls_roots(&mut self, pem: &[u8]) -> Result<&mut Self, AstarteBuilderError> {
+        let certs =
+            pemfile::certs(&mut &pem[..]).map_err(|_| AstarteBuilderError::InvalidCaCertificate)?;
+        if certs.is_empty() {
+            return Err(AstarteBuilderError::InvalidCaCertificate);
+        }
+
+        let mut root_store = rustls::RootCertStore::empty();
+        for cert in &certs {
+            root_store
+                .add(cert)
+                .map_err(|_| AstarteBuilderError::InvalidCaCertificate)?;
+        }
+
+        self.tls_roots.extend(certs);
+        Ok(self)
+    }
+
+    /// Connect with a certificate issued beforehand, instead of getting one from the Pairing API
+    ///
+    /// `cert_pem` is the PEM encoded client certificate, optionally followed by its chain, and
+    /// `key_pem` its PEM encoded private key. The Pairing API is still used to get the broker
+    /// url, unless it is set with [`broker_url`](AstarteBuilder::broker_url).
+    pub fn with_client_certificate_from_pem(
+        &mut self,
+        cert_pem: String,
+        key_pem: String,
+    ) -> Result<&mut Self, AstarteBuilderError> {
+        let chain = X509::stack_from_pem(cert_pem.as_bytes())
+            .map_err(|_| AstarteBuilderError::InvalidClientCertificate)?;
+        let key = PKey::private_key_from_pem(key_pem.as_bytes())
+            .map_err(|_| AstarteBuilderError::InvalidClientCertificate)?;
+
+        let cert = chain
+            .first()
+            .ok_or(AstarteBuilderError::InvalidClientCertificate)?;
+        let public_key = cert
+            .public_key()
+            .map_err(|_| AstarteBuilderError::InvalidClientCertificate)?;
+        if !public_key.public_eq(&key) {
+            return Err(AstarteBuilderError::InvalidClientCertificate);
+        }
+
+        let certs = chain
+            .iter()
+            .map(|cert| cert.to_der().map(Certificate))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| AstarteBuilderError::InvalidClientCertificate)?;
+        let private_key = key
+            .private_key_to_pkcs8()
+            .map(PrivateKey)
+            .map_err(|_| AstarteBuilderError::InvalidClientCertificate)?;
+
+        self.client_certificate = Some((certs, private_key));
+        Ok(self)
+    }
+
+    /// Store the messages that can't be published in the database, instead of failing
+    ///
+    /// The messages are sent again by a background task every time the client connects to the
+    /// broker, or with [`drain_dead_letter_queue`](AstarteSdk::drain_dead_letter_queue). Needs a
+    /// database.
+    pub fn enable_dead_letter_queue(&mut self) {
+        self.dead_letter_queue = true;
+    }
+
+    /// Make the send methods wait for the broker to acknowledge QoS 1 and 2 messages
+    ///
+    /// Acknowledgements are received by [`poll`](AstarteSdk::poll), so another task must be
+    /// polling the client while sending. The send fails with
+    /// [`AstarteError::PublishTimeout`](crate::AstarteError::PublishTimeout) if the broker doesn't
+    /// acknowledge the message within a minute.
+    pub fn await_puback(&mut self, await_puback: bool) {
+        self.await_puback = await_puback;
+    }
+
+    /// Wait before reconnecting after the connection to the broker fails
+    ///
+    /// The delay starts from `min` and doubles at every consecutive failure, up to `max`.
+    /// It's jittered with `rand::thread_rng`, randomly reducing it up to half, so that devices
+    /// disconnected together don't reconnect all at the same time. By default
+    /// [`poll`](AstarteSdk::poll) reconnects right away.
+    pub fn reconnect_backoff(
+        &mut self,
+        min: Duration,
+        max: Duration,
+    ) -> Result<&mut Self, AstarteBuilderError> {
+        if min.is_zero() || min >= max {
+            return Err(AstarteBuilderError::ConfigError(
+                "reconnect backoff must be 0 < min < max".into(),
+            ));
+        }
+
+        self.reconnect_backoff = Some((min, max));
+        Ok(self)
+    }
+
+    /// Report the messages exchanged with Astarte and the errors to `hook`, e.g. to export them
+    /// to OpenTelemetry
+    pub fn with_observability_hook<H: ObservabilityHook>(&mut self, hook: H) {
+        self.observability_hook = Arc::new(hook);
+    }
+
+    /// Pass the values received on `interface` to `handler`, replacing the previous one
+    ///
+    /// The interface must be a server-owned interface already added to the builder. The values
+    /// are still returned by [`poll`](AstarteSdk::poll) after the handler is done.
+    pub fn register_interface_handler<H: InterfaceHandler>(
+        &mut self,
+        interface: &str,
+        handler: H,
+    ) -> Result<&mut Self, AstarteBuilderError> {
+        match self.interfaces.get(interface) {
+            Some(registered) if registered.get_ownership() == Ownership::Server => {}
+            Some(_) => {
+                return Err(AstarteBuilderError::ConfigError(format!(
+                    "interface {} is not server-owned",
+                    interface
+                )))
+            }
+            None => {
+                return Err(AstarteBuilderError::ConfigError(format!(
+                    "interface {} is not registered",
+                    interface
+                )))
+            }
+        }
+
+        self.handlers
+            .insert(interface.to_owned(), Arc::new(handler));
+        Ok(self)
+    }
+
+    /// Encode the payloads exchanged with Astarte with `serializer` instead of BSON
+    ///
+    /// The Astarte MQTT v1 protocol uses BSON, so this needs a broker supporting the other
+    /// format. The properties are still stored in the database as BSON.
+    pub fn with_serializer<S: AstarteSerializer>(&mut self, serializer: S) {
+        self.serializer = Some(Arc::new(serializer));
+    }
+
+    /// Choose whether [`force_property_sync`](AstarteSdk::force_property_sync) keeps the values
+    /// on Astarte or the cached ones when a server-owned property differs
+    pub fn set_sync_strategy(&mut self, strategy: SyncStrategy) {
+        self.sync_strategy = strategy;
+    }
+
     /// Add an interface from a json file
     pub fn add_interface_file(
         &mut self,
         file_path: &Path,
     ) -> Result<&mut Self, AstarteBuilderError> {
         let interface = Interface::from_file(file_path)?;
+        self.add_interface(interface)?;
+        Ok(self)
+    }
+
+    /// Add the interfaces from their json descriptions, e.g. embedded with `include_str!`
+    pub fn interfaces_from_slice(
+        &mut self,
+        jsons: &[&str],
+    ) -> Result<&mut Self, AstarteBuilderError> {
+        for json in jsons {
+            let interface: Interface = json.parse()?;
+            self.add_interface(interface)?;
+        }
+
+        Ok(self)
+    }
+
+    /// Registers an interface, failing if another one with the same name is already registered
+    fn add_interface(&mut self, interface: Interface) -> Result<(), AstarteBuilderError> {
         let name = interface.name();
+
+        if let Some(existing) = self.interfaces.get(name) {
+            let (existing_major, _) = existing.version();
+            let (added_major, _) = interface.version();
+
+            if existing_major == added_major {
+                return Err(AstarteBuilderError::DuplicateInterface {
+                    name: name.to_owned(),
+                });
+            }
+
+            return Err(AstarteBuilderError::MajorVersionConflict {
+                name: name.to_owned(),
+                existing: existing_major,
+                added: added_major,
+            });
+        }
+
         debug!("Added interface {}", name);
         self.interfaces.insert(name.to_owned(), interface);
-        Ok(self)
+
+        Ok(())
     }
 
     /// Add all json interface description inside a specified directory
@@ -166,7 +654,7 @@ impl AstarteBuilder {
         Ok(self)
     }
 
-    async fn populate_credentials(&mut self, csr: &str) -> Result<Vec<Certificate>, PairingError> {
+    async fn populate_credentials(&self, csr: &str) -> Result<Vec<Certificate>, PairingError> {
         let cert_pem = pairing::fetch_credentials(self, csr).await?;
         let mut cert_pem_bytes = cert_pem.as_bytes();
         let certs =
@@ -180,30 +668,22 @@ impl AstarteBuilder {
         Ok(parsed_broker_url)
     }
 
-    fn build_mqtt_opts(
-        &self,
-        certificate_pem: &[Certificate],
-        broker_url: &Url,
-        private_key: &PrivateKey,
-    ) -> Result<MqttOptions, AstarteBuilderError> {
+    /// Mqtt options without the transport
+    fn base_mqtt_opts(&self, broker_url: &Url) -> Result<MqttOptions, AstarteBuilderError> {
         let AstarteBuilder {
             realm, device_id, ..
         } = self;
 
-        let client_id = format!("{}/{}", realm, device_id);
+        let client_id = self
+            .client_id
+            .clone()
+            .unwrap_or_else(|| format!("{}/{}", realm, device_id));
         let host = broker_url
             .host_str()
             .ok_or_else(|| AstarteBuilderError::ConfigError("bad broker url".into()))?;
         let port = broker_url
             .port()
             .ok_or_else(|| AstarteBuilderError::ConfigError("bad broker url".into()))?;
-        let mut tls_client_config = ClientConfig::new();
-        tls_client_config.root_store = rustls_native_certs::load_native_certs().map_err(|_| {
-            AstarteBuilderError::ConfigError("could not load platform certs".into())
-        })?;
-        tls_client_config
-            .set_single_client_cert(certificate_pem.to_owned(), private_key.to_owned())
-            .map_err(|_| AstarteBuilderError::ConfigError("cannot setup client auth".into()))?;
 
         let mut mqtt_opts = MqttOptions::new(client_id, host, port);
 
@@ -214,23 +694,58 @@ impl AstarteBuilder {
         }
 
         mqtt_opts.set_keep_alive(self.keepalive);
+        mqtt_opts.set_clean_session(!self.persistent_session);
+        mqtt_opts.set_max_packet_size(self.max_message_size, self.max_message_size);
+
+        Ok(mqtt_opts)
+    }
+
+    fn build_mqtt_opts(
+        &self,
+        certificate_pem: &[Certificate],
+        broker_url: &Url,
+        private_key: &PrivateKey,
+    ) -> Result<MqttOptions, AstarteBuilderError> {
+        let mut mqtt_opts = self.base_mqtt_opts(broker_url)?;
+
+        if broker_url.scheme() == "mqtt" {
+            // plain tcp, the default transport
+            return Ok(mqtt_opts);
+        }
 
-        if self.ignore_ssl_errors || std::env::var("IGNORE_SSL_ERRORS") == Ok("true".to_string()) {
-            struct OkVerifier {}
-            impl ServerCertVerifier for OkVerifier {
-                fn verify_server_cert(
-                    &self,
-                    _: &rustls::RootCertStore,
-                    _: &[Certificate],
-                    _: webpki::DNSNameRef,
-                    _: &[u8],
-                ) -> Result<rustls::ServerCertVerified, rustls::TLSError> {
-                    Ok(rustls::ServerCertVerified::assertion())
-                }
+        let mut tls_client_config = ClientConfig::new();
+        tls_client_config.root_store = rustls_native_certs::load_native_certs().map_err(|_| {
+            AstarteBuilderError::ConfigError("could not load platform certs".into())
+        })?;
+        for cert in &self.tls_roots {
+            tls_client_config
+                .root_store
+                .add(cert)
+                .map_err(|_| AstarteBuilderError::InvalidCaCertificate)?;
+        }
+        tls_client_config
+            .set_single_client_cert(certificate_pem.to_owned(), private_key.to_owned())
+            .map_err(|_| AstarteBuilderError::ConfigError("cannot setup client auth".into()))?;
+
+        let ignore_ssl_errors =
+            self.ignore_ssl_errors || std::env::var("IGNORE_SSL_ERRORS") == Ok("true".to_string());
+
+        if ignore_ssl_errors || self.pinned_certificate.is_some() {
+            let mut verifier: Arc<dyn ServerCertVerifier> = if ignore_ssl_errors {
+                Arc::new(OkVerifier {})
+            } else {
+                Arc::new(rustls::WebPKIVerifier::new())
+            };
+
+            if let Some(fingerprint) = self.pinned_certificate {
+                verifier = Arc::new(PinnedCertVerifier {
+                    fingerprint,
+                    inner: verifier,
+                });
             }
 
             let mut clientconfig = tls_client_config.dangerous();
-            clientconfig.set_certificate_verifier(Arc::new(OkVerifier {}));
+            clientconfig.set_certificate_verifier(verifier);
 
             let tls_config =
                 rumqttc::TlsConfiguration::Rustls(Arc::new(clientconfig.cfg.to_owned()));
@@ -247,7 +762,7 @@ impl AstarteBuilder {
     async fn subscribe(
         &mut self,
         client: &AsyncClient,
-        cn: &str,
+        topic_prefix: &str,
     ) -> Result<(), AstarteBuilderError> {
         let ifaces = self
             .interfaces
@@ -257,7 +772,7 @@ impl AstarteBuilder {
 
         client
             .subscribe(
-                cn.to_owned() + "/control/consumer/properties",
+                topic_prefix.to_owned() + "/control/consumer/properties",
                 rumqttc::QoS::ExactlyOnce,
             )
             .await?;
@@ -265,7 +780,7 @@ impl AstarteBuilder {
         for i in ifaces {
             client
                 .subscribe(
-                    cn.to_owned() + "/" + i.1.name() + "/#",
+                    interface::topic_pattern(topic_prefix, &i.1),
                     rumqttc::QoS::ExactlyOnce,
                 )
                 .await?;
@@ -274,14 +789,12 @@ impl AstarteBuilder {
         Ok(())
     }
 
-    /// build Astarte client, call this before `connect`
-    pub async fn build(&mut self) -> Result<(), AstarteBuilderError> {
+    /// Generate a new private key and get its certificate from the Pairing API
+    async fn fetch_client_credentials(
+        &self,
+    ) -> Result<(PrivateKey, String, Vec<Certificate>), AstarteBuilderError> {
         let cn = format!("{}/{}", self.realm, self.device_id);
 
-        if self.interfaces.is_empty() {
-            return Err(AstarteBuilderError::MissingInterfaces);
-        }
-
         let Bundle(pkey_bytes, csr_bytes) = Bundle::new(&cn)?;
 
         let private_key = pemfile::pkcs8_private_keys(&mut pkey_bytes.as_slice())
@@ -293,7 +806,53 @@ impl AstarteBuilder {
 
         let certificate_pem = self.populate_credentials(&csr).await?;
 
-        let broker_url = self.populate_broker_url().await?;
+        Ok((private_key, csr, certificate_pem))
+    }
+
+    /// The certificate set with [`with_client_certificate_from_pem`](AstarteBuilder::with_client_certificate_from_pem),
+    /// or new credentials from the Pairing API
+    async fn client_credentials(
+        &self,
+    ) -> Result<(PrivateKey, String, Vec<Certificate>), AstarteBuilderError> {
+        match &self.client_certificate {
+            Some((certificate_pem, private_key)) => {
+                debug!("using the pre-issued client certificate");
+                Ok((private_key.clone(), String::new(), certificate_pem.clone()))
+            }
+            None => self.fetch_client_credentials().await,
+        }
+    }
+
+    /// Mqtt options with new credentials from the Pairing API, used to reset the device
+    ///
+    /// A pre-issued client certificate is used again.
+    pub(crate) async fn pair_again(
+        &self,
+        broker_url: &Url,
+    ) -> Result<MqttOptions, AstarteBuilderError> {
+        let (private_key, _, certificate_pem) = self.client_credentials().await?;
+
+        self.build_mqtt_opts(&certificate_pem, broker_url, &private_key)
+    }
+
+    /// build Astarte client, call this before `connect`
+    pub async fn build(&mut self) -> Result<(), AstarteBuilderError> {
+        if self.interfaces.is_empty() {
+            return Err(AstarteBuilderError::MissingInterfaces);
+        }
+
+        if let Some(database) = &self.database {
+            // properties of interfaces removed from the device are never going to be used again
+            let registered: Vec<&str> = self.interfaces.keys().map(|name| name.as_str()).collect();
+            database.prune_stale_interfaces(&registered).await?;
+        }
+
+        let (private_key, csr, certificate_pem) = self.client_credentials().await?;
+
+        let broker_url = match &self.broker_url {
+            Some(broker_url) => broker_url.clone(),
+            None => self.populate_broker_url().await?,
+        };
 
         let mqtt_opts = self.build_mqtt_opts(&certificate_pem, &broker_url, &private_key)?;
 
@@ -309,31 +868,472 @@ impl AstarteBuilder {
     }
 
     /// Creates and connects an Astarte client
+    ///
+    /// The client doesn't create its own runtime and only spawns tokio tasks for the timers of
+    /// [`set_property_expiry`](AstarteSdk::set_property_expiry) and, with
+    /// [`enable_dead_letter_queue`](Self::enable_dead_letter_queue), to send the stored messages
+    /// after each connection. Everything else runs in the tasks calling its methods, and the
+    /// connection progresses only while [`poll`](AstarteSdk::poll) is called. It can be embedded in any existing tokio runtime,
+    /// including a current thread one, as long as the runtime has the io and time drivers
+    /// enabled. With a current thread runtime, sending and polling from different tasks works
+    /// but they never run in parallel.
     pub async fn connect(&mut self) -> Result<AstarteSdk, AstarteBuilderError> {
-        let cn = format!("{}/{}", self.realm, self.device_id);
+        let topic_prefix = self.topic_prefix();
 
         let build_options = self
             .build_options
             .clone()
             .ok_or_else(|| AstarteBuilderError::ConfigError("Missing or failed build".into()))?;
 
+        if self.event_channel_size == 0 {
+            return Err(AstarteBuilderError::ConfigError(
+                "event channel size must be greater than zero".into(),
+            ));
+        }
+
+        if self.dead_letter_queue && self.database.is_none() {
+            return Err(AstarteBuilderError::ConfigError(
+                "the dead letter queue needs a database".into(),
+            ));
+        }
+
         // TODO: make cap configurable
         let (client, eventloop) = AsyncClient::new(build_options.mqtt_opts.clone(), 50);
 
-        self.subscribe(&client, &cn).await?;
+        self.subscribe(&client, &topic_prefix).await?;
+
+        let requests = eventloop.handle();
+
+        let recorder = match &self.record_events {
+            Some(path) => Some(Arc::new(EventRecorder::open(path).await?)),
+            None => None,
+        };
+
+        let (connection_enabled, connection_enabled_rx) = tokio::sync::watch::channel(true);
 
         let device = AstarteSdk {
             realm: self.realm.to_owned(),
             device_id: self.device_id.to_owned(),
+            device_alias: self.device_alias.clone(),
             credentials_secret: self.credentials_secret.to_owned(),
             pairing_url: self.pairing_url.to_owned(),
             build_options,
             client,
             eventloop: Arc::new(tokio::sync::Mutex::new(eventloop)),
+            requests,
+            pings: Default::default(),
+            property_waiters: Default::default(),
+            event_senders: Default::default(),
+            property_senders: Default::default(),
+            datastream_senders: Default::default(),
+            property_expiries: Default::default(),
+            error_sender: Default::default(),
+            event_channel_size: self.event_channel_size,
+            pubacks: Default::default(),
+            publish_lock: Default::default(),
+            await_puback: self.await_puback,
+            backoff: self
+                .reconnect_backoff
+                .map(|(min, max)| Arc::new(Backoff::new(min, max))),
+            sync_strategy: self.sync_strategy,
+            topic_prefix,
+            stats: Default::default(),
+            observability_hook: self.observability_hook.clone(),
+            handlers: Arc::new(self.handlers.clone()),
+            created_at: std::time::Instant::now(),
+            recorder,
+            connection_enabled: Arc::new(connection_enabled),
+            connection_enabled_rx,
+            max_message_size: self.max_message_size,
+            // a payload can't be bigger than the message carrying it
+            max_payload_size: self.max_payload_size.min(self.max_message_size),
+            builder: Arc::new(self.clone()),
+            property_resync_timeout: self.property_resync_timeout,
+            publish_permits: self
+                .max_concurrent_publishes
+                .map(|permits| Arc::new(Semaphore::new(permits))),
+            dead_letter_queue: self.dead_letter_queue,
+            dead_letter_lock: Default::default(),
+            serializer: self.serializer.clone(),
             interfaces: Interfaces::new(self.interfaces.clone()),
             database: self.database.clone(),
+            sink: Default::default(),
+            stream: Default::default(),
         };
 
         Ok(device)
     }
 }
+
+#[cfg(test)]
+impl AstarteBuilder {
+    /// Creates a client with placeholder credentials, without going through pairing
+    ///
+    /// Nothing is sent until the client is polled, so requests are just queued
+    pub(crate) async fn connect_offline(&mut self) -> AstarteSdk {
+        let cn = format!("{}/{}", self.realm, self.device_id);
+
+        self.build_options = Some(BuildOptions {
+            private_key: PrivateKey(Vec::new()),
+            csr: String::new(),
+            certificate_pem: Vec::new(),
+            broker_url: Url::parse("mqtts://localhost:8883").unwrap(),
+            mqtt_opts: MqttOptions::new(cn, "localhost", 8883),
+        });
+
+        self.connect().await.unwrap()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use rustls::{Certificate, PrivateKey, RootCertStore, ServerCertVerifier};
+    use std::sync::Arc;
+
+    use super::{AstarteBuilder, AstarteBuilderError, OkVerifier, PinnedCertVerifier};
+
+    #[test]
+    fn test_pinned_certificate() {
+        let cert = Certificate(b"not really a certificate".to_vec());
+        let dns_name = webpki::DNSNameRef::try_from_ascii_str("broker.example.com").unwrap();
+
+        let verifier = PinnedCertVerifier {
+            fingerprint: openssl::sha::sha256(&cert.0),
+            inner: Arc::new(OkVerifier {}),
+        };
+        assert!(verifier
+            .verify_server_cert(&RootCertStore::empty(), &[cert.clone()], dns_name, &[])
+            .is_ok());
+
+        let verifier = PinnedCertVerifier {
+            fingerprint: [0; 32],
+            inner: Arc::new(OkVerifier {}),
+        };
+        let err = verifier
+            .verify_server_cert(&RootCertStore::empty(), &[cert], dns_name, &[])
+            .unwrap_err();
+
+        // the handshake error reaches the client wrapped in the connection error
+        let err =
+            rumqttc::ConnectionError::Io(std::io::Error::new(std::io::ErrorKind::InvalidData, err));
+        assert!(matches!(
+            crate::AstarteError::from(err),
+            crate::AstarteError::CertificatePinMismatch
+        ));
+        let err = rumqttc::ConnectionError::Io(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            rustls::TLSError::NoCertificatesPresented,
+        ));
+        assert!(matches!(
+            crate::AstarteError::from(err),
+            crate::AstarteError::ConnectionError(_)
+        ));
+    }
+
+    #[test]
+    fn test_pairing_url_from_env() {
+        let mut options = AstarteBuilder::new("test", "test", "test", "test");
+
+        temp_env::with_var(
+            "ASTARTE_PAIRING_URL",
+            Some("https://api.example.com/pairing"),
+            || {
+                options.pairing_url_from_env().unwrap();
+            },
+        );
+        assert_eq!(options.pairing_url, "https://api.example.com/pairing");
+
+        temp_env::with_var("ASTARTE_PAIRING_URL", Some("not a url"), || {
+            assert!(matches!(
+                options.pairing_url_from_env(),
+                Err(AstarteBuilderError::ConfigError(_))
+            ));
+        });
+
+        temp_env::with_var("ASTARTE_PAIRING_URL", None::<&str>, || {
+            assert!(matches!(
+                options.pairing_url_from_env(),
+                Err(AstarteBuilderError::MissingEnvVar("ASTARTE_PAIRING_URL"))
+            ));
+        });
+    }
+
+    #[test]
+    fn test_interfaces_from_slice() {
+        let mut options = AstarteBuilder::new("test", "test", "test", "test");
+
+        options
+            .interfaces_from_slice(&[
+                include_str!("../examples/interfaces/com.test.Everything.json"),
+                include_str!(
+                    "../examples/interfaces/org.astarte-platform.genericsensors.SamplingRate.json"
+                ),
+            ])
+            .unwrap();
+
+        assert_eq!(options.interfaces.len(), 2);
+        assert!(options.interfaces.contains_key("com.test.Everything"));
+        assert!(options
+            .interfaces
+            .contains_key("org.astarte-platform.genericsensors.SamplingRate"));
+
+        assert!(options.interfaces_from_slice(&["{}"]).is_err());
+    }
+
+    #[test]
+    fn test_duplicate_interface() {
+        let json = include_str!("../examples/interfaces/com.test.Everything.json");
+
+        let mut options = AstarteBuilder::new("test", "test", "test", "test");
+        options.interfaces_from_slice(&[json]).unwrap();
+
+        let res = options.interfaces_from_slice(&[json]);
+        assert!(matches!(
+            res,
+            Err(AstarteBuilderError::DuplicateInterface { name }) if name == "com.test.Everything"
+        ));
+
+        let new_major = json.replace("\"version_major\": 0", "\"version_major\": 1");
+        let res = options.interfaces_from_slice(&[&new_major]);
+        assert!(matches!(
+            res,
+            Err(AstarteBuilderError::MajorVersionConflict {
+                existing: 0,
+                added: 1,
+                ..
+            })
+        ));
+
+        assert_eq!(options.interfaces.len(), 1);
+
+        let res = options.add_interface_files("examples/interfaces/");
+        assert!(matches!(
+            res,
+            Err(AstarteBuilderError::DuplicateInterface { .. })
+        ));
+    }
+
+    #[test]
+    fn test_broker_url() {
+        let mut options = AstarteBuilder::new("test", "test", "test", "test");
+
+        options.broker_url("mqtt://localhost:1883").unwrap();
+        let broker_url = options.broker_url.clone().unwrap();
+        let mqtt_opts = options
+            .build_mqtt_opts(&[], &broker_url, &PrivateKey(Vec::new()))
+            .unwrap();
+        assert_eq!(mqtt_opts.broker_address(), ("localhost".to_owned(), 1883));
+
+        // the client certificate is needed to setup TLS
+        options
+            .broker_url("mqtts://broker.example.com:8883")
+            .unwrap();
+        let broker_url = options.broker_url.clone().unwrap();
+        assert_eq!(
+            options
+                .base_mqtt_opts(&broker_url)
+                .unwrap()
+                .broker_address(),
+            ("broker.example.com".to_owned(), 8883)
+        );
+        assert!(options
+            .build_mqtt_opts(&[], &broker_url, &PrivateKey(Vec::new()))
+            .is_err());
+
+        for url in ["http://localhost:1883", "mqtt://localhost", "not a url"] {
+            assert!(matches!(
+                options.broker_url(url),
+                Err(AstarteBuilderError::InvalidBrokerUrl(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_persistent_session() {
+        let mut options = AstarteBuilder::new("test", "test", "test", "test");
+        let broker_url = url::Url::parse("mqtt://localhost:1883").unwrap();
+
+        assert!(options.base_mqtt_opts(&broker_url).unwrap().clean_session());
+
+        options.persistent_session(true);
+        let mqtt_opts = options.base_mqtt_opts(&broker_url).unwrap();
+        assert!(!mqtt_opts.clean_session());
+        assert_eq!(mqtt_opts.client_id(), "test/test");
+    }
+
+    #[test]
+    fn test_with_client_id() {
+        let mut options = AstarteBuilder::new("test", "test", "test", "test");
+        let broker_url = url::Url::parse("mqtt://localhost:1883").unwrap();
+
+        assert!(options.with_client_id("").is_err());
+        assert!(options
+            .with_client_id("a-client-id-longer-than-23")
+            .is_err());
+        assert_eq!(
+            options.base_mqtt_opts(&broker_url).unwrap().client_id(),
+            "test/test"
+        );
+
+        options.with_client_id("a-client-id-of-23-bytes").unwrap();
+        assert_eq!(
+            options.base_mqtt_opts(&broker_url).unwrap().client_id(),
+            "a-client-id-of-23-bytes"
+        );
+    }
+
+    #[test]
+    fn test_reconnect_backoff() {
+        let mut options = AstarteBuilder::new("test", "test", "test", "test");
+        let second = std::time::Duration::from_secs(1);
+
+        assert!(options.reconnect_backoff(second * 2, second).is_err());
+        assert!(options.reconnect_backoff(second, second).is_err());
+        assert!(options
+            .reconnect_backoff(std::time::Duration::ZERO, second)
+            .is_err());
+        assert_eq!(options.reconnect_backoff, None);
+
+        options.reconnect_backoff(second, second * 60).unwrap();
+        assert_eq!(options.reconnect_backoff, Some((second, second * 60)));
+    }
+
+    #[test]
+    fn test_with_tls_roots() {
+        use openssl::{
+            asn1::Asn1Time,
+            bn::BigNum,
+            ec::{EcGroup, EcKey},
+            hash::MessageDigest,
+            nid::Nid,
+            pkey::PKey,
+            x509::{extension::BasicConstraints, X509Builder, X509NameBuilder},
+        };
+
+        let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).unwrap();
+        let pkey = PKey::from_ec_key(EcKey::generate(&group).unwrap()).unwrap();
+
+        let mut name = X509NameBuilder::new().unwrap();
+        name.append_entry_by_nid(Nid::COMMONNAME, "Test CA")
+            .unwrap();
+        let name = name.build();
+
+        let mut ca = X509Builder::new().unwrap();
+        ca.set_version(2).unwrap();
+        ca.set_serial_number(&BigNum::from_u32(1).unwrap().to_asn1_integer().unwrap())
+            .unwrap();
+        ca.set_subject_name(&name).unwrap();
+        ca.set_issuer_name(&name).unwrap();
+        ca.set_pubkey(&pkey).unwrap();
+        ca.set_not_before(&Asn1Time::days_from_now(0).unwrap())
+            .unwrap();
+        ca.set_not_after(&Asn1Time::days_from_now(365).unwrap())
+            .unwrap();
+        ca.append_extension(BasicConstraints::new().critical().ca().build().unwrap())
+            .unwrap();
+        ca.sign(&pkey, MessageDigest::sha256()).unwrap();
+        let pem = ca.build().to_pem().unwrap();
+
+        let mut options = AstarteBuilder::new("test", "test", "test", "test");
+        assert!(matches!(
+            options.with_tls_roots(b"not a certificate"),
+            Err(AstarteBuilderError::InvalidCaCertificate)
+        ));
+        assert!(matches!(
+            options.with_tls_roots(
+                b"-----BEGIN CERTIFICATE-----\nbm90IGEgY2VydGlmaWNhdGU=\n-----END CERTIFICATE-----\n"
+            ),
+            Err(AstarteBuilderError::InvalidCaCertificate)
+        ));
+        assert!(options.tls_roots.is_empty());
+
+        options.with_tls_roots(&pem).unwrap();
+        assert_eq!(options.tls_roots.len(), 1);
+        assert!(super::pairing::http_client(&options).is_ok());
+    }
+
+    #[test]
+    fn test_current_thread_runtime() {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+
+        runtime.block_on(async {
+            let mut options = AstarteBuilder::new("test", "test", "test", "test");
+            options.add_interface_files("examples/interfaces/").unwrap();
+            let device = options.connect_offline().await;
+
+            device
+                .send_with_timestamp("com.test.Everything", "/integer", 42, chrono::Utc::now())
+                .await
+                .unwrap();
+            assert_eq!(device.connection_stats().total_publishes, 1);
+        });
+    }
+
+    #[tokio::test]
+    async fn test_with_client_certificate_from_pem() {
+        use openssl::{
+            asn1::Asn1Time,
+            bn::BigNum,
+            ec::{EcGroup, EcKey},
+            hash::MessageDigest,
+            nid::Nid,
+            pkey::PKey,
+            x509::{X509Builder, X509NameBuilder},
+        };
+
+        let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).unwrap();
+        let pkey = PKey::from_ec_key(EcKey::generate(&group).unwrap()).unwrap();
+        let other_pkey = PKey::from_ec_key(EcKey::generate(&group).unwrap()).unwrap();
+
+        let mut name = X509NameBuilder::new().unwrap();
+        name.append_entry_by_nid(Nid::COMMONNAME, "test/test")
+            .unwrap();
+        let name = name.build();
+
+        let mut cert = X509Builder::new().unwrap();
+        cert.set_version(2).unwrap();
+        cert.set_serial_number(&BigNum::from_u32(1).unwrap().to_asn1_integer().unwrap())
+            .unwrap();
+        cert.set_subject_name(&name).unwrap();
+        cert.set_issuer_name(&name).unwrap();
+        cert.set_pubkey(&pkey).unwrap();
+        cert.set_not_before(&Asn1Time::days_from_now(0).unwrap())
+            .unwrap();
+        cert.set_not_after(&Asn1Time::days_from_now(365).unwrap())
+            .unwrap();
+        cert.sign(&pkey, MessageDigest::sha256()).unwrap();
+        let cert_pem = String::from_utf8(cert.build().to_pem().unwrap()).unwrap();
+        let key_pem = String::from_utf8(pkey.private_key_to_pem_pkcs8().unwrap()).unwrap();
+        let other_key_pem =
+            String::from_utf8(other_pkey.private_key_to_pem_pkcs8().unwrap()).unwrap();
+
+        let mut options = AstarteBuilder::new("test", "test", "test", "test");
+        assert!(matches!(
+            options.with_client_certificate_from_pem("not a certificate".into(), key_pem.clone()),
+            Err(AstarteBuilderError::InvalidClientCertificate)
+        ));
+        assert!(matches!(
+            options.with_client_certificate_from_pem(cert_pem.clone(), "not a key".into()),
+            Err(AstarteBuilderError::InvalidClientCertificate)
+        ));
+        // the key must be the one of the certificate
+        assert!(matches!(
+            options.with_client_certificate_from_pem(cert_pem.clone(), other_key_pem),
+            Err(AstarteBuilderError::InvalidClientCertificate)
+        ));
+        assert!(options.client_certificate.is_none());
+
+        options
+            .with_client_certificate_from_pem(cert_pem, key_pem)
+            .unwrap();
+        assert_eq!(options.client_certificate.as_ref().unwrap().0.len(), 1);
+
+        // the pairing url is not valid, the build works only if the Pairing API is not used
+        options.broker_url("mqtt://localhost:1883").unwrap();
+        options.add_interface_files("examples/interfaces").unwrap();
+        options.build().await.unwrap();
+    }
+}
@@ -17,18 +17,33 @@
  */
 
 use async_trait::async_trait;
+use bson::{spec::BinarySubtype, Binary, Bson};
+use std::convert::TryFrom;
+use std::fmt;
+use std::path::Path;
 use std::str::FromStr;
+use std::time::Duration;
 
-use log::{debug, trace};
-use sqlx::sqlite::{SqliteConnectOptions, SqlitePoolOptions};
+use sqlx::sqlite::{SqliteConnectOptions, SqliteJournalMode, SqlitePoolOptions};
 use sqlx::FromRow;
 
 use crate::{types::AstarteType, AstarteError, AstarteSdk};
 
+mod caching;
+
+pub use caching::CachingDatabase;
+
+/// Default maximum number of connections to an sqlite database
+const DEFAULT_POOL_SIZE: u32 = 5;
+
+/// How long a connection waits for the database to be unlocked before failing with `SQLITE_BUSY`
+const DEFAULT_BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
 /// Implementation of the [AstarteDatabase] trait for an sqlite database backend
 #[derive(Clone, Debug)]
 pub struct AstarteSqliteDatabase {
     db_conn: sqlx::Pool<sqlx::Sqlite>,
+    in_memory: bool,
 }
 
 /// This struct represents a property stored in the database
@@ -40,6 +55,166 @@ pub struct StoredProp {
     pub interface_major: i32,
 }
 
+impl StoredProp {
+    /// Converts the property to a bson document, used to export the database state
+    pub(crate) fn into_bson(self) -> Bson {
+        Bson::Document(bson::doc! {
+            "interface": self.interface,
+            "path": self.path,
+            "value": Binary {
+                subtype: BinarySubtype::Generic,
+                bytes: self.value,
+            },
+            "interface_major": self.interface_major,
+        })
+    }
+
+    /// Formats the property as a csv row with the interface, path, hex encoded value and interface major
+    pub fn to_csv_row(&self) -> String {
+        let value: String = self.value.iter().map(|b| format!("{:02x}", b)).collect();
+
+        format!(
+            "{},{},{},{}",
+            csv_field(&self.interface),
+            csv_field(&self.path),
+            value,
+            self.interface_major
+        )
+    }
+
+    /// Reads back a property converted with [StoredProp::into_bson]
+    pub(crate) fn from_bson(bson: &Bson) -> Option<Self> {
+        let doc = bson.as_document()?;
+
+        Some(StoredProp {
+            interface: doc.get_str("interface").ok()?.to_owned(),
+            path: doc.get_str("path").ok()?.to_owned(),
+            value: doc.get_binary_generic("value").ok()?.clone(),
+            interface_major: doc.get_i32("interface_major").ok()?,
+        })
+    }
+}
+
+/// A message that couldn't be published, see
+/// [`enable_dead_letter_queue`](crate::builder::AstarteBuilder::enable_dead_letter_queue)
+#[derive(FromRow, Debug, Clone, PartialEq)]
+pub struct DeadLetter {
+    pub id: i64,
+    pub topic: String,
+    pub qos: u8,
+    pub retain: bool,
+    pub payload: Vec<u8>,
+}
+
+/// A property stored in the database, with its value deserialized
+#[derive(Debug, Clone, PartialEq)]
+pub struct PropertyInfo {
+    pub interface: String,
+    pub path: String,
+    pub value: AstarteType,
+    pub interface_major: i32,
+}
+
+impl TryFrom<StoredProp> for PropertyInfo {
+    type Error = AstarteError;
+
+    fn try_from(prop: StoredProp) -> Result<Self, Self::Error> {
+        let value = match AstarteSdk::deserialize(&prop.value)? {
+            crate::Aggregation::Individual(value) => value,
+            crate::Aggregation::Object(_) => {
+                return Err(AstarteError::Reported(
+                    "BUG: extracting an object from the database".into(),
+                ))
+            }
+        };
+
+        Ok(PropertyInfo {
+            interface: prop.interface,
+            path: prop.path,
+            value,
+            interface_major: prop.interface_major,
+        })
+    }
+}
+
+/// Copy of all the properties stored in the database at a point in time
+#[derive(Debug, Clone, PartialEq)]
+pub struct PropertySnapshot {
+    pub timestamp: chrono::DateTime<chrono::Utc>,
+    pub properties: Vec<PropertyInfo>,
+}
+
+impl fmt::Display for PropertySnapshot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let header = [
+            "interface".to_owned(),
+            "path".to_owned(),
+            "value".to_owned(),
+            "major".to_owned(),
+        ];
+        let rows: Vec<[String; 4]> = self
+            .properties
+            .iter()
+            .map(|prop| {
+                [
+                    prop.interface.clone(),
+                    prop.path.clone(),
+                    format!("{:?}", prop.value),
+                    prop.interface_major.to_string(),
+                ]
+            })
+            .collect();
+
+        let mut widths = [0; 4];
+        for row in std::iter::once(&header).chain(&rows) {
+            for (width, cell) in widths.iter_mut().zip(row) {
+                *width = (*width).max(cell.len());
+            }
+        }
+
+        let write_row = |f: &mut fmt::Formatter<'_>, row: &[String; 4]| {
+            writeln!(
+                f,
+                "{:<w0$} | {:<w1$} | {:<w2$} | {:<w3$}",
+                row[0],
+                row[1],
+                row[2],
+                row[3],
+                w0 = widths[0],
+                w1 = widths[1],
+                w2 = widths[2],
+                w3 = widths[3]
+            )
+        };
+
+        writeln!(f, "properties at {}", self.timestamp.to_rfc3339())?;
+        write_row(f, &header)?;
+        writeln!(
+            f,
+            "{}",
+            widths
+                .iter()
+                .map(|w| "-".repeat(*w))
+                .collect::<Vec<_>>()
+                .join("-+-")
+        )?;
+        for row in &rows {
+            write_row(f, row)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Quotes a csv field if it contains separators or quotes
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_owned()
+    }
+}
+
 /// Database backend for the astarte client can be made by implementing this trait
 #[async_trait]
 pub trait AstarteDatabase {
@@ -58,11 +233,75 @@ pub trait AstarteDatabase {
     ) -> Result<Option<AstarteType>, AstarteError>;
     async fn delete_prop(&self, interface: &str, path: &str) -> Result<(), AstarteError>;
 
+    /// Checks if a property is stored, without deserializing its value
+    async fn contains_prop(&self, interface: &str, path: &str) -> Result<bool, AstarteError> {
+        let props = self.load_all_props().await?;
+
+        Ok(props
+            .iter()
+            .any(|prop| prop.interface == interface && prop.path == path))
+    }
+
     /// Removes all saved properties from the database
     async fn clear(&self) -> Result<(), AstarteError>;
 
     /// Retrieves all property values in the database, together with their interface name, path and major version
     async fn load_all_props(&self) -> Result<Vec<StoredProp>, AstarteError>;
+
+    /// Moves the properties of an interface from `old_major` to `new_major`, returns the number of updated properties
+    async fn update_prop_major(
+        &self,
+        interface: &str,
+        old_major: i32,
+        new_major: i32,
+    ) -> Result<u64, AstarteError> {
+        let mut updated = 0;
+        for prop in self.load_all_props().await? {
+            if prop.interface == interface && prop.interface_major == old_major {
+                self.store_prop(&prop.interface, &prop.path, &prop.value, new_major)
+                    .await?;
+                updated += 1;
+            }
+        }
+
+        Ok(updated)
+    }
+
+    /// Removes the properties of the interfaces not in `registered`, returns the number of removed properties
+    async fn prune_stale_interfaces(&self, registered: &[&str]) -> Result<u64, AstarteError> {
+        let mut pruned = 0;
+        for prop in self.load_all_props().await? {
+            if !registered.contains(&prop.interface.as_str()) {
+                self.delete_prop(&prop.interface, &prop.path).await?;
+                pruned += 1;
+            }
+        }
+
+        Ok(pruned)
+    }
+
+    /// Queues a message that couldn't be published, to send it again later
+    async fn store_dead_letter(
+        &self,
+        _topic: &str,
+        _qos: u8,
+        _retain: bool,
+        _payload: &[u8],
+    ) -> Result<(), AstarteError> {
+        Err(AstarteError::Reported(
+            "the database doesn't support the dead letter queue".into(),
+        ))
+    }
+
+    /// Retrieves the queued messages, oldest first
+    async fn load_dead_letters(&self) -> Result<Vec<DeadLetter>, AstarteError> {
+        Ok(Vec::new())
+    }
+
+    /// Removes a queued message once it has been published
+    async fn delete_dead_letter(&self, _id: i64) -> Result<(), AstarteError> {
+        Ok(())
+    }
 }
 
 #[async_trait]
@@ -133,6 +372,17 @@ impl AstarteDatabase for AstarteSqliteDatabase {
         }
     }
 
+    async fn contains_prop(&self, interface: &str, path: &str) -> Result<bool, AstarteError> {
+        let res: Option<(i32,)> =
+            sqlx::query_as("select 1 from propcache where interface=? and path=?")
+                .bind(interface)
+                .bind(path)
+                .fetch_optional(&self.db_conn)
+                .await?;
+
+        Ok(res.is_some())
+    }
+
     async fn delete_prop(&self, interface: &str, path: &str) -> Result<(), AstarteError> {
         sqlx::query("delete from propcache where interface=? and path=?")
             .bind(interface)
@@ -158,27 +408,388 @@ impl AstarteDatabase for AstarteSqliteDatabase {
 
         return Ok(res);
     }
+
+    async fn update_prop_major(
+        &self,
+        interface: &str,
+        old_major: i32,
+        new_major: i32,
+    ) -> Result<u64, AstarteError> {
+        let updated = sqlx::query(
+            "update propcache set interface_major=? where interface=? and interface_major=?",
+        )
+        .bind(new_major)
+        .bind(interface)
+        .bind(old_major)
+        .execute(&self.db_conn)
+        .await?
+        .rows_affected();
+
+        debug!(
+            "Updated {} properties of {} from major {} to {}",
+            updated, interface, old_major, new_major
+        );
+
+        Ok(updated)
+    }
+
+    async fn prune_stale_interfaces(&self, registered: &[&str]) -> Result<u64, AstarteError> {
+        let placeholders = vec!["?"; registered.len()].join(",");
+        let sql = format!(
+            "delete from propcache where interface not in ({})",
+            placeholders
+        );
+
+        let mut query = sqlx::query(&sql);
+        for interface in registered {
+            query = query.bind(*interface);
+        }
+
+        let pruned = query.execute(&self.db_conn).await?.rows_affected();
+        debug!("Pruned {} properties of stale interfaces", pruned);
+
+        Ok(pruned)
+    }
+
+    async fn store_dead_letter(
+        &self,
+        topic: &str,
+        qos: u8,
+        retain: bool,
+        payload: &[u8],
+    ) -> Result<(), AstarteError> {
+        debug!("Queueing message on {} in the dead letter queue", topic);
+
+        sqlx::query("INSERT INTO deadletter (topic, qos, retain, payload) VALUES (?, ?, ?, ?)")
+            .bind(topic)
+            .bind(qos)
+            .bind(retain)
+            .bind(payload)
+            .execute(&self.db_conn)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn load_dead_letters(&self) -> Result<Vec<DeadLetter>, AstarteError> {
+        let letters =
+            sqlx::query_as("SELECT id, topic, qos, retain, payload FROM deadletter ORDER BY id")
+                .fetch_all(&self.db_conn)
+                .await?;
+
+        Ok(letters)
+    }
+
+    async fn delete_dead_letter(&self, id: i64) -> Result<(), AstarteError> {
+        sqlx::query("DELETE FROM deadletter WHERE id = ?")
+            .bind(id)
+            .execute(&self.db_conn)
+            .await?;
+
+        Ok(())
+    }
 }
 
 impl AstarteSqliteDatabase {
     /// Creates an sqlite database for the astarte client
     /// URI should follow sqlite's convention, read [SqliteConnectOptions] for more details
     pub async fn new(uri: &str) -> Result<Self, crate::builder::AstarteBuilderError> {
-        let options = SqliteConnectOptions::from_str(uri)?.create_if_missing(true);
+        AstarteSqliteDatabase::with_pool_size(uri, DEFAULT_POOL_SIZE).await
+    }
+
+    /// Creates an sqlite database kept in memory, the stored properties are lost when the
+    /// client is dropped
+    pub async fn with_in_memory() -> Result<Self, crate::builder::AstarteBuilderError> {
+        let options =
+            SqliteConnectOptions::from_str("sqlite::memory:")?.busy_timeout(DEFAULT_BUSY_TIMEOUT);
+        // the database is dropped with its last connection, so one is always kept open
+        let pool_options = SqlitePoolOptions::new()
+            .max_connections(DEFAULT_POOL_SIZE)
+            .min_connections(1)
+            .idle_timeout(None)
+            .max_lifetime(None);
+
+        let mut db = AstarteSqliteDatabase::connect(options, pool_options).await?;
+        db.in_memory = true;
+
+        Ok(db)
+    }
+
+    /// Returns `true` if the database was created with [`with_in_memory`](Self::with_in_memory)
+    pub fn is_in_memory(&self) -> bool {
+        self.in_memory
+    }
+
+    /// Creates an sqlite database using at most `max_connections` concurrent connections
+    pub async fn with_pool_size(
+        uri: &str,
+        max_connections: u32,
+    ) -> Result<Self, crate::builder::AstarteBuilderError> {
+        if max_connections < 1 {
+            return Err(crate::builder::AstarteBuilderError::ConfigError(
+                "sqlite pool needs at least a connection".into(),
+            ));
+        }
+
+        let options = SqliteConnectOptions::from_str(uri)?
+            .create_if_missing(true)
+            .busy_timeout(DEFAULT_BUSY_TIMEOUT);
+        let pool_options = SqlitePoolOptions::new().max_connections(max_connections);
+
+        AstarteSqliteDatabase::connect(options, pool_options).await
+    }
+
+    /// Creates an sqlite database using the write-ahead log journal, that lets readers access
+    /// the database while another connection is writing
+    pub async fn with_wal(uri: &str) -> Result<Self, crate::builder::AstarteBuilderError> {
+        let options = SqliteConnectOptions::from_str(uri)?
+            .create_if_missing(true)
+            .journal_mode(SqliteJournalMode::Wal)
+            .busy_timeout(DEFAULT_BUSY_TIMEOUT);
+        let pool_options = SqlitePoolOptions::new().max_connections(DEFAULT_POOL_SIZE);
+
+        AstarteSqliteDatabase::connect(options, pool_options).await
+    }
+
+    /// Creates an sqlite database where a connection waits up to `timeout` for the other ones
+    /// to release the lock, instead of failing with `SQLITE_BUSY`
+    ///
+    /// The other constructors wait up to 5 seconds.
+    pub async fn with_busy_timeout(
+        uri: &str,
+        timeout: Duration,
+    ) -> Result<Self, crate::builder::AstarteBuilderError> {
+        let options = SqliteConnectOptions::from_str(uri)?
+            .create_if_missing(true)
+            .busy_timeout(timeout);
+        let pool_options = SqlitePoolOptions::new().max_connections(DEFAULT_POOL_SIZE);
 
-        let conn = SqlitePoolOptions::new().connect_with(options).await?;
+        AstarteSqliteDatabase::connect(options, pool_options).await
+    }
+
+    /// Creates an sqlite database with pages of `page_size` bytes, e.g. to match the erase
+    /// blocks of a flash storage
+    ///
+    /// The size must be a power of 2 between 512 and 65536. It only applies to new databases,
+    /// an existing one keeps its page size.
+    pub async fn with_page_size(
+        uri: &str,
+        page_size: u32,
+    ) -> Result<Self, crate::builder::AstarteBuilderError> {
+        if !page_size.is_power_of_two() || !(512..=65536).contains(&page_size) {
+            return Err(crate::builder::AstarteBuilderError::ConfigError(
+                "sqlite page size must be a power of 2 between 512 and 65536".into(),
+            ));
+        }
+
+        // the page size is set when connecting, before the table is created
+        let options = SqliteConnectOptions::from_str(uri)?
+            .create_if_missing(true)
+            .page_size(page_size)
+            .busy_timeout(DEFAULT_BUSY_TIMEOUT);
+        let pool_options = SqlitePoolOptions::new().max_connections(DEFAULT_POOL_SIZE);
+
+        AstarteSqliteDatabase::connect(options, pool_options).await
+    }
+
+    /// Creates an sqlite database and checks its integrity, failing with
+    /// [`AstarteError::DatabaseCorrupted`] if it's damaged
+    pub async fn with_integrity_check(
+        uri: &str,
+    ) -> Result<Self, crate::builder::AstarteBuilderError> {
+        let db = AstarteSqliteDatabase::new(uri).await?;
+
+        let errors = db.integrity_errors().await?;
+        if !errors.is_empty() {
+            return Err(AstarteError::DatabaseCorrupted(errors.join("\n")).into());
+        }
+
+        Ok(db)
+    }
+
+    /// Runs the sqlite integrity check, returns `false` if the database is corrupted
+    ///
+    /// The whole database is read, so this can take a while on big databases.
+    pub async fn integrity_check(&self) -> Result<bool, AstarteError> {
+        let errors = self.integrity_errors().await?;
+        for error in &errors {
+            debug!("integrity check failed: {}", error);
+        }
+
+        Ok(errors.is_empty())
+    }
+
+    /// Problems found by the integrity check, empty if the database is fine
+    async fn integrity_errors(&self) -> Result<Vec<String>, AstarteError> {
+        const SQLITE_CORRUPT: i32 = 11;
+        const SQLITE_NOTADB: i32 = 26;
+
+        let rows: Result<Vec<(String,)>, _> = sqlx::query_as("PRAGMA integrity_check")
+            .fetch_all(&self.db_conn)
+            .await;
+
+        match rows {
+            Ok(rows) => Ok(rows
+                .into_iter()
+                .map(|(row,)| row)
+                .filter(|row| row != "ok")
+                .collect()),
+            // the check itself fails on badly damaged pages
+            Err(sqlx::Error::Database(err))
+                if matches!(
+                    err.code().and_then(|code| code.parse::<i32>().ok()),
+                    Some(code) if code & 0xff == SQLITE_CORRUPT || code & 0xff == SQLITE_NOTADB
+                ) =>
+            {
+                Ok(vec![err.message().to_owned()])
+            }
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Creates an sqlite database encrypted with SQLCipher
+    ///
+    /// `key` is the raw 256 bit AES key, the database file can't be read without it.
+    /// Fails if sqlite wasn't built with SQLCipher support.
+    #[cfg(feature = "sqlcipher")]
+    pub async fn with_encryption(
+        uri: &str,
+        key: &[u8],
+    ) -> Result<Self, crate::builder::AstarteBuilderError> {
+        if key.len() != 32 {
+            return Err(crate::builder::AstarteBuilderError::ConfigError(
+                "sqlcipher key must be 32 bytes".into(),
+            ));
+        }
+
+        let key: String = key.iter().map(|b| format!("{:02x}", b)).collect();
+
+        // sqlx sets the key before any other pragma, as required by sqlcipher
+        let options = SqliteConnectOptions::from_str(uri)?
+            .create_if_missing(true)
+            .pragma("key", format!("\"x'{}'\"", key));
+        let conn = SqlitePoolOptions::new()
+            .max_connections(DEFAULT_POOL_SIZE)
+            .connect_with(options)
+            .await?;
+
+        // plain sqlite ignores the key pragma, make sure we are not storing data in clear
+        let cipher_version: Option<(String,)> = sqlx::query_as("PRAGMA cipher_version")
+            .fetch_optional(&conn)
+            .await?;
+        if cipher_version.is_none() {
+            return Err(crate::builder::AstarteBuilderError::ConfigError(
+                "sqlite is not built with sqlcipher".into(),
+            ));
+        }
+
+        AstarteSqliteDatabase::init(conn).await
+    }
+
+    /// Copies the database to `dest_path` while it's in use
+    ///
+    /// An existing file at `dest_path` is overwritten.
+    pub async fn backup(&self, dest_path: &Path) -> Result<(), AstarteError> {
+        let dest = dest_path
+            .to_str()
+            .ok_or_else(|| AstarteError::Reported("backup path is not valid utf-8".into()))?;
+
+        // the destination of VACUUM INTO must be empty, this also checks it is writable
+        tokio::fs::File::create(dest_path).await?;
+
+        sqlx::query("VACUUM INTO ?")
+            .bind(dest)
+            .execute(&self.db_conn)
+            .await?;
+
+        debug!("Database backed up to {}", dest);
+
+        Ok(())
+    }
 
+    /// Copies the properties stored in the database at `source_path` into this one, returns the
+    /// number of imported properties
+    ///
+    /// The properties already stored are kept, so it can be used to merge a backup.
+    pub async fn attach_and_import(&self, source_path: &Path) -> Result<u64, AstarteError> {
+        let source = source_path
+            .to_str()
+            .ok_or_else(|| AstarteError::Reported("source path is not valid utf-8".into()))?;
+
+        // attaching a missing file would create an empty database
+        tokio::fs::metadata(source_path).await?;
+
+        // the attached database is only visible to the connection attaching it
+        let mut conn = self.db_conn.acquire().await?;
+
+        sqlx::query("ATTACH DATABASE ? AS source")
+            .bind(source)
+            .execute(&mut *conn)
+            .await?;
+
+        let imported = sqlx::query(
+            "INSERT OR IGNORE INTO propcache (interface, path, value, interface_major) SELECT interface, path, value, interface_major FROM source.propcache",
+        )
+        .execute(&mut *conn)
+        .await
+        .map(|res| res.rows_affected());
+
+        // detach even if the import failed, the connection goes back to the pool
+        sqlx::query("DETACH DATABASE source")
+            .execute(&mut *conn)
+            .await?;
+
+        let imported = imported?;
+        debug!("Imported {} properties from {}", imported, source);
+
+        Ok(imported)
+    }
+
+    /// Exports all the stored properties as csv, starting with an header row
+    pub async fn export_csv(&self) -> Result<String, AstarteError> {
+        let mut csv = String::from("interface,path,value,interface_major\n");
+
+        for prop in self.load_all_props().await? {
+            csv += &prop.to_csv_row();
+            csv.push('\n');
+        }
+
+        Ok(csv)
+    }
+
+    async fn connect(
+        options: SqliteConnectOptions,
+        pool_options: SqlitePoolOptions,
+    ) -> Result<Self, crate::builder::AstarteBuilderError> {
+        let conn = pool_options.connect_with(options).await?;
+
+        AstarteSqliteDatabase::init(conn).await
+    }
+
+    /// Creates the properties and dead letter tables if they don't exist
+    async fn init(
+        conn: sqlx::Pool<sqlx::Sqlite>,
+    ) -> Result<Self, crate::builder::AstarteBuilderError> {
         sqlx::query("CREATE TABLE if not exists propcache (interface TEXT, path TEXT, value BLOB NOT NULL, interface_major INTEGER NOT NULL, PRIMARY KEY (interface, path))").execute(&conn).await?;
+        sqlx::query("CREATE TABLE if not exists deadletter (id INTEGER PRIMARY KEY AUTOINCREMENT, topic TEXT NOT NULL, qos INTEGER NOT NULL, retain BOOLEAN NOT NULL, payload BLOB NOT NULL)").execute(&conn).await?;
 
-        Ok(AstarteSqliteDatabase { db_conn: conn })
+        Ok(AstarteSqliteDatabase {
+            db_conn: conn,
+            in_memory: false,
+        })
     }
 }
 
 #[cfg(test)]
 mod test {
     use crate::database::AstarteDatabase;
-    use crate::AstarteSdk;
     use crate::{database::AstarteSqliteDatabase, database::StoredProp, types::AstarteType};
+    use crate::{AstarteError, AstarteSdk};
+    use std::time::Duration;
+
+    fn temp_db_path() -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("astarte-test-{}.sqlite", uuid::Uuid::new_v4()))
+    }
 
     #[tokio::test]
     async fn test_db() {
@@ -263,4 +874,440 @@ mod test {
             ]
         );
     }
+
+    #[tokio::test]
+    async fn test_contains_prop() {
+        let db = AstarteSqliteDatabase::new("sqlite::memory:").await.unwrap();
+
+        let ser = AstarteSdk::serialize_individual(AstarteType::Integer(23), None).unwrap();
+        db.store_prop("com.test", "/test", &ser, 1).await.unwrap();
+
+        for (interface, path) in [
+            ("com.test", "/test"),
+            ("com.test", "/other"),
+            ("com.other", "/test"),
+        ] {
+            let loaded = db.load_prop(interface, path, 1).await.unwrap().is_some();
+            assert_eq!(db.contains_prop(interface, path).await.unwrap(), loaded);
+        }
+
+        assert!(db.contains_prop("com.test", "/test").await.unwrap());
+
+        db.delete_prop("com.test", "/test").await.unwrap();
+        assert!(!db.contains_prop("com.test", "/test").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_update_prop_major() {
+        let db = AstarteSqliteDatabase::new("sqlite::memory:").await.unwrap();
+
+        let ty = AstarteType::Integer(23);
+        let ser = AstarteSdk::serialize_individual(ty.clone(), None).unwrap();
+        db.store_prop("com.test", "/test", &ser, 1).await.unwrap();
+        db.store_prop("com.test", "/test2", &ser, 1).await.unwrap();
+        db.store_prop("com.other", "/test", &ser, 1).await.unwrap();
+
+        assert_eq!(db.update_prop_major("com.test", 1, 2).await.unwrap(), 2);
+        assert_eq!(db.update_prop_major("com.test", 1, 2).await.unwrap(), 0);
+
+        assert_eq!(
+            db.load_prop("com.test", "/test", 2).await.unwrap(),
+            Some(ty.clone())
+        );
+        assert_eq!(
+            db.load_prop("com.test", "/test2", 2).await.unwrap(),
+            Some(ty)
+        );
+
+        // other interfaces are untouched
+        assert!(db.load_all_props().await.unwrap().contains(&StoredProp {
+            interface: "com.other".into(),
+            path: "/test".into(),
+            value: ser,
+            interface_major: 1,
+        }));
+    }
+
+    #[tokio::test]
+    async fn test_prune_stale_interfaces() {
+        let db = AstarteSqliteDatabase::new("sqlite::memory:").await.unwrap();
+
+        let ser = AstarteSdk::serialize_individual(AstarteType::Integer(23), None).unwrap();
+
+        db.store_prop("com.test", "/test", &ser, 1).await.unwrap();
+        db.store_prop("com.test", "/test2", &ser, 1).await.unwrap();
+        db.store_prop("com.stale", "/test", &ser, 1).await.unwrap();
+        db.store_prop("com.stale2", "/test", &ser, 1).await.unwrap();
+
+        assert_eq!(db.prune_stale_interfaces(&["com.test"]).await.unwrap(), 2);
+
+        let props = db.load_all_props().await.unwrap();
+        assert_eq!(props.len(), 2);
+        assert!(props.iter().all(|prop| prop.interface == "com.test"));
+
+        // nothing left to prune
+        assert_eq!(db.prune_stale_interfaces(&["com.test"]).await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_in_memory() {
+        let db = AstarteSqliteDatabase::with_in_memory().await.unwrap();
+        assert!(db.is_in_memory());
+
+        let ser = AstarteSdk::serialize_individual(AstarteType::Integer(23), None).unwrap();
+        db.store_prop("com.test", "/test", &ser, 1).await.unwrap();
+        assert_eq!(
+            db.load_prop("com.test", "/test", 1).await.unwrap(),
+            Some(AstarteType::Integer(23))
+        );
+
+        let path = temp_db_path();
+        let db = AstarteSqliteDatabase::new(&format!("sqlite://{}", path.display()))
+            .await
+            .unwrap();
+        assert!(!db.is_in_memory());
+
+        db.db_conn.close().await;
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_pool_size() {
+        AstarteSqliteDatabase::with_pool_size("sqlite::memory:", 0)
+            .await
+            .unwrap_err();
+
+        let db = AstarteSqliteDatabase::with_pool_size("sqlite::memory:", 1)
+            .await
+            .unwrap();
+
+        let ser = AstarteSdk::serialize_individual(AstarteType::Integer(23), None).unwrap();
+
+        // concurrent operations have to wait for the only connection
+        let tasks: Vec<_> = (0..10)
+            .map(|i| {
+                let db = db.clone();
+                let ser = ser.clone();
+                tokio::spawn(async move {
+                    let path = format!("/test{}", i);
+                    db.store_prop("com.test", &path, &ser, 1).await.unwrap();
+                    db.load_prop("com.test", &path, 1).await.unwrap()
+                })
+            })
+            .collect();
+
+        for task in tasks {
+            assert_eq!(task.await.unwrap(), Some(AstarteType::Integer(23)));
+        }
+
+        assert_eq!(db.load_all_props().await.unwrap().len(), 10);
+    }
+
+    #[tokio::test]
+    async fn test_busy_timeout() {
+        let path = temp_db_path();
+        let uri = format!("sqlite://{}", path.display());
+
+        let db = AstarteSqliteDatabase::with_busy_timeout(&uri, Duration::from_millis(2000))
+            .await
+            .unwrap();
+        let (timeout,): (i64,) = sqlx::query_as("PRAGMA busy_timeout")
+            .fetch_one(&db.db_conn)
+            .await
+            .unwrap();
+        assert_eq!(timeout, 2000);
+
+        let ser = AstarteSdk::serialize_individual(AstarteType::Integer(23), None).unwrap();
+
+        // two pools on the same file compete for the database lock
+        let writers: Vec<_> = (0..2)
+            .map(|w| {
+                let uri = uri.clone();
+                let ser = ser.clone();
+                tokio::spawn(async move {
+                    let db = AstarteSqliteDatabase::with_busy_timeout(&uri, Duration::from_secs(5))
+                        .await
+                        .unwrap();
+                    for i in 0..50 {
+                        db.store_prop("com.test", &format!("/test{}/{}", w, i), &ser, 1)
+                            .await
+                            .unwrap();
+                    }
+                    db.db_conn.close().await;
+                })
+            })
+            .collect();
+
+        for writer in writers {
+            writer.await.unwrap();
+        }
+
+        assert_eq!(db.load_all_props().await.unwrap().len(), 100);
+
+        db.db_conn.close().await;
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_page_size() {
+        assert!(
+            AstarteSqliteDatabase::with_page_size("sqlite::memory:", 4000)
+                .await
+                .is_err()
+        );
+        assert!(
+            AstarteSqliteDatabase::with_page_size("sqlite::memory:", 256)
+                .await
+                .is_err()
+        );
+        assert!(
+            AstarteSqliteDatabase::with_page_size("sqlite::memory:", 131072)
+                .await
+                .is_err()
+        );
+
+        let path = temp_db_path();
+        let db =
+            AstarteSqliteDatabase::with_page_size(&format!("sqlite://{}", path.display()), 8192)
+                .await
+                .unwrap();
+
+        let (page_size,): (i64,) = sqlx::query_as("PRAGMA page_size")
+            .fetch_one(&db.db_conn)
+            .await
+            .unwrap();
+        assert_eq!(page_size, 8192);
+
+        db.db_conn.close().await;
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_wal() {
+        let path = temp_db_path();
+        let db = AstarteSqliteDatabase::with_wal(&format!("sqlite://{}", path.display()))
+            .await
+            .unwrap();
+
+        let (journal_mode,): (String,) = sqlx::query_as("PRAGMA journal_mode")
+            .fetch_one(&db.db_conn)
+            .await
+            .unwrap();
+        assert_eq!(journal_mode, "wal");
+
+        let ser = AstarteSdk::serialize_individual(AstarteType::Integer(23), None).unwrap();
+
+        let writer = {
+            let db = db.clone();
+            tokio::spawn(async move {
+                for i in 0..50 {
+                    db.store_prop("com.test", &format!("/test{}", i), &ser, 1)
+                        .await
+                        .unwrap();
+                }
+            })
+        };
+
+        let reader = {
+            let db = db.clone();
+            tokio::spawn(async move {
+                for _ in 0..50 {
+                    db.load_all_props().await.unwrap();
+                }
+            })
+        };
+
+        writer.await.unwrap();
+        reader.await.unwrap();
+
+        assert_eq!(db.load_all_props().await.unwrap().len(), 50);
+
+        db.db_conn.close().await;
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_export_csv() {
+        let db = AstarteSqliteDatabase::new("sqlite::memory:").await.unwrap();
+
+        db.store_prop("com.test", "/test", &[0x01, 0xab], 1)
+            .await
+            .unwrap();
+        db.store_prop("com.test2", "/a,b", &[], 2).await.unwrap();
+
+        assert_eq!(
+            db.export_csv().await.unwrap(),
+            "interface,path,value,interface_major\n\
+             com.test,/test,01ab,1\n\
+             com.test2,\"/a,b\",,2\n"
+        );
+    }
+
+    #[cfg(feature = "sqlcipher")]
+    #[tokio::test]
+    async fn test_encryption() {
+        let path = temp_db_path();
+        let uri = format!("sqlite://{}", path.display());
+
+        let db = AstarteSqliteDatabase::with_encryption(&uri, &[1; 32])
+            .await
+            .unwrap();
+        db.store_prop("com.test", "/test", &[0x01, 0xab], 1)
+            .await
+            .unwrap();
+        db.db_conn.close().await;
+
+        assert!(AstarteSqliteDatabase::new(&uri).await.is_err());
+        assert!(AstarteSqliteDatabase::with_encryption(&uri, &[2; 32])
+            .await
+            .is_err());
+        assert!(AstarteSqliteDatabase::with_encryption(&uri, &[1; 16])
+            .await
+            .is_err());
+
+        let db = AstarteSqliteDatabase::with_encryption(&uri, &[1; 32])
+            .await
+            .unwrap();
+        assert_eq!(db.load_all_props().await.unwrap().len(), 1);
+        db.db_conn.close().await;
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_integrity_check() {
+        let path = temp_db_path();
+        let uri = format!("sqlite://{}", path.display());
+
+        let db = AstarteSqliteDatabase::new(&uri).await.unwrap();
+        let ser =
+            AstarteSdk::serialize_individual(AstarteType::BinaryBlob(vec![0; 100]), None).unwrap();
+        for i in 0..100 {
+            db.store_prop("com.test", &format!("/test{}", i), &ser, 1)
+                .await
+                .unwrap();
+        }
+        assert!(db.integrity_check().await.unwrap());
+        db.db_conn.close().await;
+
+        AstarteSqliteDatabase::with_integrity_check(&uri)
+            .await
+            .unwrap()
+            .db_conn
+            .close()
+            .await;
+
+        // overwrite everything after the first page, which holds the schema
+        let mut content = std::fs::read(&path).unwrap();
+        assert!(content.len() > 4096);
+        for byte in content.iter_mut().skip(4096) {
+            *byte = 0;
+        }
+        std::fs::write(&path, content).unwrap();
+
+        let db = AstarteSqliteDatabase::new(&uri).await.unwrap();
+        assert!(!db.integrity_check().await.unwrap());
+        db.db_conn.close().await;
+
+        assert!(matches!(
+            AstarteSqliteDatabase::with_integrity_check(&uri).await,
+            Err(crate::builder::AstarteBuilderError::AstarteError(
+                AstarteError::DatabaseCorrupted(_)
+            ))
+        ));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_backup() {
+        let db = AstarteSqliteDatabase::new("sqlite::memory:").await.unwrap();
+        let ser = AstarteSdk::serialize_individual(AstarteType::Integer(23), None).unwrap();
+        db.store_prop("com.test", "/test", &ser, 1).await.unwrap();
+
+        let path = temp_db_path();
+        db.backup(&path).await.unwrap();
+
+        let backup = AstarteSqliteDatabase::new(&format!("sqlite://{}", path.display()))
+            .await
+            .unwrap();
+        assert_eq!(
+            backup.load_prop("com.test", "/test", 1).await.unwrap(),
+            Some(AstarteType::Integer(23))
+        );
+        backup.db_conn.close().await;
+        std::fs::remove_file(&path).unwrap();
+
+        let unwritable = std::env::temp_dir()
+            .join(uuid::Uuid::new_v4().to_string())
+            .join("backup.sqlite");
+        assert!(matches!(
+            db.backup(&unwritable).await,
+            Err(AstarteError::IoError(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_attach_and_import() {
+        let path = temp_db_path();
+        let source = AstarteSqliteDatabase::new(&format!("sqlite://{}", path.display()))
+            .await
+            .unwrap();
+        let one = AstarteSdk::serialize_individual(AstarteType::Integer(1), None).unwrap();
+        let two = AstarteSdk::serialize_individual(AstarteType::Integer(2), None).unwrap();
+        source.store_prop("com.test", "/a", &one, 1).await.unwrap();
+        source.store_prop("com.test", "/b", &one, 1).await.unwrap();
+        source.db_conn.close().await;
+
+        let db = AstarteSqliteDatabase::new("sqlite::memory:").await.unwrap();
+        db.store_prop("com.test", "/b", &two, 1).await.unwrap();
+        db.store_prop("com.test", "/c", &two, 1).await.unwrap();
+
+        assert_eq!(db.attach_and_import(&path).await.unwrap(), 1);
+
+        // the stored properties are kept
+        assert_eq!(
+            db.load_prop("com.test", "/a", 1).await.unwrap(),
+            Some(AstarteType::Integer(1))
+        );
+        assert_eq!(
+            db.load_prop("com.test", "/b", 1).await.unwrap(),
+            Some(AstarteType::Integer(2))
+        );
+        assert_eq!(db.load_all_props().await.unwrap().len(), 3);
+
+        std::fs::remove_file(&path).unwrap();
+        assert!(matches!(
+            db.attach_and_import(&path).await,
+            Err(AstarteError::IoError(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_dead_letters() {
+        let db = AstarteSqliteDatabase::new("sqlite::memory:").await.unwrap();
+
+        assert!(db.load_dead_letters().await.unwrap().is_empty());
+
+        db.store_dead_letter("test/test/com.test/a", 1, false, &[1, 2])
+            .await
+            .unwrap();
+        db.store_dead_letter("test/test/com.test/b", 2, true, &[3])
+            .await
+            .unwrap();
+
+        let letters = db.load_dead_letters().await.unwrap();
+        assert_eq!(letters.len(), 2);
+        assert_eq!(letters[0].topic, "test/test/com.test/a");
+        assert_eq!(letters[0].qos, 1);
+        assert!(!letters[0].retain);
+        assert_eq!(letters[0].payload, [1, 2]);
+        assert_eq!(letters[1].topic, "test/test/com.test/b");
+
+        db.delete_dead_letter(letters[0].id).await.unwrap();
+        let letters = db.load_dead_letters().await.unwrap();
+        assert_eq!(letters.len(), 1);
+        assert_eq!(letters[0].qos, 2);
+        assert!(letters[0].retain);
+    }
 }
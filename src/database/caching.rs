@@ -0,0 +1,310 @@
+/*
+ * This file is part of Astarte.
+ *
+ * Copyright 2021 SECO Mind Srl
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *    http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::collections::HashMap;
+
+use async_trait::async_trait;
+use tokio::sync::Mutex;
+
+use super::{AstarteDatabase, DeadLetter, StoredProp};
+use crate::{types::AstarteType, Aggregation, AstarteError, AstarteSdk};
+
+/// Write-through in-memory cache of the properties stored in another database
+///
+/// The properties are read from memory once they have been stored or loaded, the writes go to
+/// both the cache and the wrapped database. [`load_all_props`](AstarteDatabase::load_all_props)
+/// always reads the wrapped database.
+///
+/// The cache is locked while the wrapped database is accessed, so that a load can't cache a
+/// value older than the one of a concurrent store.
+#[derive(Debug)]
+pub struct CachingDatabase<D> {
+    inner: D,
+    // values by interface and path, with the interface major version
+    cache: Mutex<HashMap<(String, String), (AstarteType, i32)>>,
+}
+
+impl<D> CachingDatabase<D>
+where
+    D: AstarteDatabase + Send + Sync,
+{
+    pub fn new(inner: D) -> Self {
+        CachingDatabase {
+            inner,
+            cache: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// The wrapped database
+    pub fn inner(&self) -> &D {
+        &self.inner
+    }
+
+    fn key(interface: &str, path: &str) -> (String, String) {
+        (interface.to_owned(), path.to_owned())
+    }
+}
+
+#[async_trait]
+impl<D> AstarteDatabase for CachingDatabase<D>
+where
+    D: AstarteDatabase + Send + Sync,
+{
+    async fn store_prop(
+        &self,
+        interface: &str,
+        path: &str,
+        value: &[u8],
+        interface_major: i32,
+    ) -> Result<(), AstarteError> {
+        let key = CachingDatabase::<D>::key(interface, path);
+        let mut cache = self.cache.lock().await;
+
+        // a failed write leaves the database in an unknown state
+        cache.remove(&key);
+        self.inner
+            .store_prop(interface, path, value, interface_major)
+            .await?;
+
+        // let the wrapped database handle the invalid values
+        if let Ok(Aggregation::Individual(data)) = AstarteSdk::deserialize(value) {
+            cache.insert(key, (data, interface_major));
+        }
+
+        Ok(())
+    }
+
+    async fn load_prop(
+        &self,
+        interface: &str,
+        path: &str,
+        interface_major: i32,
+    ) -> Result<Option<AstarteType>, AstarteError> {
+        let key = CachingDatabase::<D>::key(interface, path);
+        let mut cache = self.cache.lock().await;
+
+        if let Some((data, major)) = cache.get(&key).cloned() {
+            trace!("Loaded property {} {} from cache", interface, path);
+
+            //if version mismatch, delete
+            if major != interface_major {
+                cache.remove(&key);
+                self.inner.delete_prop(interface, path).await?;
+                return Ok(None);
+            }
+
+            return Ok(Some(data));
+        }
+
+        let data = self
+            .inner
+            .load_prop(interface, path, interface_major)
+            .await?;
+        if let Some(data) = &data {
+            cache.insert(key, (data.clone(), interface_major));
+        }
+
+        Ok(data)
+    }
+
+    async fn delete_prop(&self, interface: &str, path: &str) -> Result<(), AstarteError> {
+        let mut cache = self.cache.lock().await;
+        cache.remove(&CachingDatabase::<D>::key(interface, path));
+
+        self.inner.delete_prop(interface, path).await
+    }
+
+    async fn contains_prop(&self, interface: &str, path: &str) -> Result<bool, AstarteError> {
+        let key = CachingDatabase::<D>::key(interface, path);
+        let cache = self.cache.lock().await;
+        if cache.contains_key(&key) {
+            return Ok(true);
+        }
+
+        self.inner.contains_prop(interface, path).await
+    }
+
+    async fn clear(&self) -> Result<(), AstarteError> {
+        let mut cache = self.cache.lock().await;
+        cache.clear();
+
+        self.inner.clear().await
+    }
+
+    async fn load_all_props(&self) -> Result<Vec<StoredProp>, AstarteError> {
+        self.inner.load_all_props().await
+    }
+
+    async fn update_prop_major(
+        &self,
+        interface: &str,
+        old_major: i32,
+        new_major: i32,
+    ) -> Result<u64, AstarteError> {
+        let mut cache = self.cache.lock().await;
+        cache.retain(|(prop_interface, _), _| prop_interface != interface);
+
+        self.inner
+            .update_prop_major(interface, old_major, new_major)
+            .await
+    }
+
+    async fn prune_stale_interfaces(&self, registered: &[&str]) -> Result<u64, AstarteError> {
+        let mut cache = self.cache.lock().await;
+        cache.retain(|(interface, _), _| registered.contains(&interface.as_str()));
+
+        self.inner.prune_stale_interfaces(registered).await
+    }
+
+    async fn store_dead_letter(
+        &self,
+        topic: &str,
+        qos: u8,
+        retain: bool,
+        payload: &[u8],
+    ) -> Result<(), AstarteError> {
+        self.inner
+            .store_dead_letter(topic, qos, retain, payload)
+            .await
+    }
+
+    async fn load_dead_letters(&self) -> Result<Vec<DeadLetter>, AstarteError> {
+        self.inner.load_dead_letters().await
+    }
+
+    async fn delete_dead_letter(&self, id: i64) -> Result<(), AstarteError> {
+        self.inner.delete_dead_letter(id).await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use async_trait::async_trait;
+
+    use super::CachingDatabase;
+    use crate::database::{AstarteDatabase, AstarteSqliteDatabase, StoredProp};
+    use crate::{types::AstarteType, AstarteError, AstarteSdk};
+
+    /// Counts the properties loaded from the wrapped database
+    struct CountingDatabase {
+        inner: AstarteSqliteDatabase,
+        loads: AtomicUsize,
+    }
+
+    #[async_trait]
+    impl AstarteDatabase for CountingDatabase {
+        async fn store_prop(
+            &self,
+            interface: &str,
+            path: &str,
+            value: &[u8],
+            interface_major: i32,
+        ) -> Result<(), AstarteError> {
+            self.inner
+                .store_prop(interface, path, value, interface_major)
+                .await
+        }
+
+        async fn load_prop(
+            &self,
+            interface: &str,
+            path: &str,
+            interface_major: i32,
+        ) -> Result<Option<AstarteType>, AstarteError> {
+            self.loads.fetch_add(1, Ordering::Relaxed);
+            self.inner.load_prop(interface, path, interface_major).await
+        }
+
+        async fn delete_prop(&self, interface: &str, path: &str) -> Result<(), AstarteError> {
+            self.inner.delete_prop(interface, path).await
+        }
+
+        async fn clear(&self) -> Result<(), AstarteError> {
+            self.inner.clear().await
+        }
+
+        async fn load_all_props(&self) -> Result<Vec<StoredProp>, AstarteError> {
+            self.inner.load_all_props().await
+        }
+    }
+
+    #[tokio::test]
+    async fn test_caching_database() {
+        let db = CachingDatabase::new(CountingDatabase {
+            inner: AstarteSqliteDatabase::new("sqlite::memory:").await.unwrap(),
+            loads: AtomicUsize::new(0),
+        });
+
+        let value = AstarteSdk::serialize_individual(42, None).unwrap();
+        db.store_prop("com.test", "/test", &value, 1).await.unwrap();
+
+        // the value is read from the cache
+        assert_eq!(
+            db.load_prop("com.test", "/test", 1).await.unwrap(),
+            Some(AstarteType::Integer(42))
+        );
+        assert_eq!(db.inner().loads.load(Ordering::Relaxed), 0);
+        assert_eq!(db.load_all_props().await.unwrap().len(), 1);
+
+        // a deleted value is read again from the database
+        db.delete_prop("com.test", "/test").await.unwrap();
+        assert_eq!(db.load_prop("com.test", "/test", 1).await.unwrap(), None);
+        assert_eq!(db.inner().loads.load(Ordering::Relaxed), 1);
+
+        // a value stored only in the database is cached once loaded
+        db.inner()
+            .store_prop("com.test", "/test", &value, 1)
+            .await
+            .unwrap();
+        for _ in 0..2 {
+            assert_eq!(
+                db.load_prop("com.test", "/test", 1).await.unwrap(),
+                Some(AstarteType::Integer(42))
+            );
+        }
+        assert_eq!(db.inner().loads.load(Ordering::Relaxed), 2);
+
+        // a different major version is deleted
+        assert_eq!(db.load_prop("com.test", "/test", 2).await.unwrap(), None);
+        assert!(db.load_all_props().await.unwrap().is_empty());
+
+        db.store_prop("com.test", "/test", &value, 1).await.unwrap();
+        db.clear().await.unwrap();
+        assert_eq!(db.load_prop("com.test", "/test", 1).await.unwrap(), None);
+        assert!(!db.contains_prop("com.test", "/test").await.unwrap());
+
+        // the default pruning goes through the wrapped database
+        db.store_prop("com.test", "/test", &value, 1).await.unwrap();
+        db.store_prop("com.stale", "/test", &value, 1)
+            .await
+            .unwrap();
+        assert_eq!(db.prune_stale_interfaces(&["com.test"]).await.unwrap(), 1);
+        assert_eq!(db.load_prop("com.stale", "/test", 1).await.unwrap(), None);
+        assert!(db.contains_prop("com.test", "/test").await.unwrap());
+
+        // and so does the default major update
+        assert_eq!(db.update_prop_major("com.test", 1, 2).await.unwrap(), 1);
+        assert_eq!(db.update_prop_major("com.test", 1, 2).await.unwrap(), 0);
+        assert_eq!(
+            db.load_prop("com.test", "/test", 2).await.unwrap(),
+            Some(AstarteType::Integer(42))
+        );
+    }
+}
@@ -0,0 +1,180 @@
+/*
+ * This file is part of Astarte.
+ *
+ * Copyright 2021 SECO Mind Srl
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *    http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Handlers receiving the values sent by Astarte on a single interface
+
+use async_trait::async_trait;
+
+use crate::{AstarteError, Clientbound};
+
+/// Handles the values received on an interface, see
+/// [`register_interface_handler`](crate::builder::AstarteBuilder::register_interface_handler)
+///
+/// The handler is awaited by [`poll`](crate::AstarteSdk::poll) before returning the value, so
+/// it should return quickly.
+#[async_trait]
+pub trait InterfaceHandler: Send + Sync + 'static {
+    /// A value was received on the interface of the handler
+    async fn on_event(&self, event: Clientbound) -> Result<(), AstarteError>;
+}
+
+/// Handler calling a closure
+///
+/// ```
+/// use astarte_sdk::handler::FnHandler;
+///
+/// let handler = FnHandler::new(|event| {
+///     println!("{}{}: {:?}", event.interface, event.path, event.data);
+///     Ok(())
+/// });
+/// ```
+pub struct FnHandler<F> {
+    f: F,
+}
+
+impl<F> FnHandler<F>
+where
+    F: Fn(Clientbound) -> Result<(), AstarteError> + Send + Sync + 'static,
+{
+    pub fn new(f: F) -> Self {
+        FnHandler { f }
+    }
+}
+
+#[async_trait]
+impl<F> InterfaceHandler for FnHandler<F>
+where
+    F: Fn(Clientbound) -> Result<(), AstarteError> + Send + Sync + 'static,
+{
+    async fn on_event(&self, event: Clientbound) -> Result<(), AstarteError> {
+        (self.f)(event)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{Arc, Mutex};
+
+    use async_trait::async_trait;
+
+    use super::{FnHandler, InterfaceHandler};
+    use crate::builder::{AstarteBuilder, AstarteBuilderError};
+    use crate::types::AstarteType;
+    use crate::{Aggregation, AstarteError, AstarteSdk, Clientbound};
+
+    const COMMANDS: &str = r#"
+    {
+        "interface_name": "com.test.Commands",
+        "version_major": 0,
+        "version_minor": 1,
+        "type": "datastream",
+        "ownership": "server",
+        "mappings": [
+            {
+                "endpoint": "/command",
+                "type": "string"
+            }
+        ]
+    }
+    "#;
+
+    #[derive(Default, Clone)]
+    struct RecordingHandler {
+        events: Arc<Mutex<Vec<String>>>,
+    }
+
+    #[async_trait]
+    impl InterfaceHandler for RecordingHandler {
+        async fn on_event(&self, event: Clientbound) -> Result<(), AstarteError> {
+            self.events.lock().unwrap().push(event.path);
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_interface_handlers() {
+        let sampling_rate = RecordingHandler::default();
+        let commands = Arc::new(Mutex::new(Vec::new()));
+
+        let mut options = AstarteBuilder::new("test", "test", "test", "test");
+        options
+            .add_interface_files("examples/interfaces/")
+            .unwrap()
+            .interfaces_from_slice(&[COMMANDS])
+            .unwrap();
+        options
+            .register_interface_handler(
+                "org.astarte-platform.genericsensors.SamplingRate",
+                sampling_rate.clone(),
+            )
+            .unwrap();
+        let received = Arc::clone(&commands);
+        options
+            .register_interface_handler(
+                "com.test.Commands",
+                FnHandler::new(move |event| {
+                    if let Aggregation::Individual(value) = event.data {
+                        received.lock().unwrap().push(value);
+                    }
+                    Ok(())
+                }),
+            )
+            .unwrap();
+        let device = options.connect_offline().await;
+
+        let payload = AstarteSdk::serialize_individual(true, None).unwrap();
+        let event = device
+            .process_publish(
+                "test/test/org.astarte-platform.genericsensors.SamplingRate/1/enable",
+                &payload,
+            )
+            .await
+            .unwrap();
+        // the value is still returned by poll
+        assert!(event.is_some());
+
+        let payload = AstarteSdk::serialize_individual("reboot", None).unwrap();
+        device
+            .process_publish("test/test/com.test.Commands/command", &payload)
+            .await
+            .unwrap();
+
+        assert_eq!(*sampling_rate.events.lock().unwrap(), ["/1/enable"]);
+        assert_eq!(
+            *commands.lock().unwrap(),
+            [AstarteType::String("reboot".into())]
+        );
+    }
+
+    #[test]
+    fn test_handler_of_missing_interface() {
+        let mut options = AstarteBuilder::new("test", "test", "test", "test");
+        options.add_interface_files("examples/interfaces/").unwrap();
+
+        assert!(matches!(
+            options.register_interface_handler("com.test.Missing", RecordingHandler::default()),
+            Err(AstarteBuilderError::ConfigError(_))
+        ));
+        // a device-owned interface never receives values
+        assert!(matches!(
+            options.register_interface_handler("com.test.Everything", RecordingHandler::default()),
+            Err(AstarteBuilderError::ConfigError(_))
+        ));
+        assert!(options.handlers.is_empty());
+    }
+}
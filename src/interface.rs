@@ -19,9 +19,11 @@
 pub(crate) mod traits;
 
 use serde::{Deserialize, Serialize};
+use std::fmt;
 use std::fs::File;
 use std::io::{self, BufReader};
 use std::path::Path;
+use std::sync::Arc;
 
 use traits::Interface as InterfaceTrait;
 use traits::Mapping as MappingTrait;
@@ -91,12 +93,22 @@ pub enum Aggregation {
     Object,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 pub enum Mapping<'a> {
     Datastream(&'a DatastreamMapping),
     Properties(&'a PropertiesMapping),
 }
 
+/// Mapping of a registered interface, returned by
+/// [`AstarteSdk::get_mapping`](crate::AstarteSdk::get_mapping)
+///
+/// Keeps the interface alive, the mapping is borrowed from it.
+#[derive(Debug, Clone)]
+pub struct MappingRef {
+    interface: Arc<Interface>,
+    path: String,
+}
+
 #[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
 pub(crate) struct BaseMapping {
     endpoint: String,
@@ -205,6 +217,69 @@ impl Default for DatabaseRetentionPolicy {
     }
 }
 
+impl fmt::Display for Ownership {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Ownership::Device => write!(f, "device"),
+            Ownership::Server => write!(f, "server"),
+        }
+    }
+}
+
+impl fmt::Display for Aggregation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Aggregation::Individual => write!(f, "individual"),
+            Aggregation::Object => write!(f, "object"),
+        }
+    }
+}
+
+impl fmt::Display for InterfaceType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InterfaceType::Datastream => write!(f, "datastream"),
+            InterfaceType::Properties => write!(f, "properties"),
+        }
+    }
+}
+
+/// Metadata of a registered interface
+#[derive(Debug, Clone, PartialEq)]
+pub struct InterfaceInfo {
+    pub name: String,
+    pub version_major: i32,
+    pub version_minor: i32,
+    pub ownership: Ownership,
+    pub aggregation: Aggregation,
+    pub interface_type: InterfaceType,
+}
+
+impl fmt::Display for InterfaceInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} v{}.{} ({}/{}/{})",
+            self.name,
+            self.version_major,
+            self.version_minor,
+            self.ownership,
+            self.interface_type,
+            self.aggregation
+        )
+    }
+}
+
+/// MQTT topic filter matching all the paths of a server-owned interface of a device
+pub fn incoming_topic_pattern(interface: &Interface, realm: &str, device_id: &str) -> String {
+    topic_pattern(&format!("{}/{}", realm, device_id), interface)
+}
+
+/// MQTT topic filter matching all the paths of an interface under `prefix`
+pub(crate) fn topic_pattern(prefix: &str, interface: &Interface) -> String {
+    format!("{}/{}/#", prefix, interface.name())
+}
+
 fn is_default<T: Default + PartialEq>(t: &T) -> bool {
     t == &T::default()
 }
@@ -247,6 +322,13 @@ impl Interface {
         None
     }
 
+    pub fn interface_type(&self) -> InterfaceType {
+        match &self {
+            Self::Datastream(_) => InterfaceType::Datastream,
+            Self::Properties(_) => InterfaceType::Properties,
+        }
+    }
+
     pub fn mappings_len(&self) -> usize {
         match &self {
             Self::Datastream(d) => d.mappings.len(),
@@ -261,6 +343,78 @@ impl Interface {
         }
     }
 
+    /// Endpoints of the mappings with their type
+    pub(crate) fn mapping_types(&self) -> Vec<(&str, MappingType)> {
+        match &self {
+            Self::Datastream(d) => d
+                .mappings
+                .iter()
+                .map(|m| (m.endpoint(), m.mapping_type()))
+                .collect(),
+            Self::Properties(p) => p
+                .mappings
+                .iter()
+                .map(|m| (m.endpoint(), m.mapping_type()))
+                .collect(),
+        }
+    }
+
+    /// Describes the changes from this version of the interface to `new` that make the data
+    /// sent with this version invalid, empty if `new` is backward compatible
+    pub(crate) fn breaking_changes(&self, new: &Interface) -> Vec<String> {
+        let mut changes = Vec::new();
+
+        let (major, minor) = self.version();
+        let (new_major, new_minor) = new.version();
+        if new_major != major {
+            changes.push(format!(
+                "major version changed from {} to {}",
+                major, new_major
+            ));
+        } else if new_minor < minor {
+            changes.push(format!(
+                "minor version decreased from {} to {}",
+                minor, new_minor
+            ));
+        }
+
+        if new.interface_type() != self.interface_type() {
+            changes.push(format!(
+                "type changed from {} to {}",
+                self.interface_type(),
+                new.interface_type()
+            ));
+        }
+        if new.get_ownership() != self.get_ownership() {
+            changes.push(format!(
+                "ownership changed from {} to {}",
+                self.get_ownership(),
+                new.get_ownership()
+            ));
+        }
+        if new.aggregation() != self.aggregation() {
+            changes.push(format!(
+                "aggregation changed from {} to {}",
+                self.aggregation(),
+                new.aggregation()
+            ));
+        }
+
+        let new_mappings = new.mapping_types();
+        for (endpoint, mapping_type) in self.mapping_types() {
+            match new_mappings.iter().find(|(e, _)| *e == endpoint) {
+                None => changes.push(format!("mapping {} removed", endpoint)),
+                Some((_, new_type)) if *new_type != mapping_type => changes.push(format!(
+                    "mapping {} type changed from {:?} to {:?}",
+                    endpoint, mapping_type, new_type
+                )),
+                Some(_) => {}
+            }
+        }
+
+        changes
+    }
+
     pub fn get_properties_paths(&self) -> Vec<(String, i32)> {
         if let Interface::Properties(iface) = self {
             let name = iface.base.interface_name.clone();
@@ -296,6 +450,42 @@ impl InterfaceTrait for Interface {
     }
 }
 
+impl MappingRef {
+    /// `None` if no mapping of `interface` matches `path`
+    pub(crate) fn new(interface: Arc<Interface>, path: &str) -> Option<Self> {
+        interface.mapping(path)?;
+
+        Some(MappingRef {
+            interface,
+            path: path.to_owned(),
+        })
+    }
+
+    /// The mapping matching the path
+    pub fn mapping(&self) -> Mapping<'_> {
+        self.interface
+            .mapping(&self.path)
+            .expect("the path matched a mapping when created")
+    }
+
+    /// The interface the mapping belongs to
+    pub fn interface(&self) -> &Interface {
+        &self.interface
+    }
+}
+
+impl Mapping<'_> {
+    /// Endpoint of the mapping, parameters included
+    pub fn endpoint(&self) -> &str {
+        MappingTrait::endpoint(self)
+    }
+
+    /// Type of the values of the mapping
+    pub fn mapping_type(&self) -> MappingType {
+        MappingTrait::mapping_type(self)
+    }
+}
+
 impl MappingTrait for Mapping<'_> {
     fn base_mapping(&self) -> &BaseMapping {
         match &self {
@@ -420,4 +610,27 @@ mod tests {
         assert_eq!(interface.description(), Some("Interface description"));
         assert_eq!(interface.doc(), Some("Interface doc"));
     }
+
+    #[test]
+    fn test_incoming_topic_pattern() {
+        let datastream: Interface = include_str!(
+            "../examples/interfaces/org.astarte-platform.genericsensors.Geolocation.json"
+        )
+        .parse()
+        .unwrap();
+        assert_eq!(
+            super::incoming_topic_pattern(&datastream, "test", "device"),
+            "test/device/org.astarte-platform.genericsensors.Geolocation/#"
+        );
+
+        let properties: Interface = include_str!(
+            "../examples/interfaces/org.astarte-platform.genericsensors.SamplingRate.json"
+        )
+        .parse()
+        .unwrap();
+        assert_eq!(
+            super::incoming_topic_pattern(&properties, "test", "device"),
+            "test/device/org.astarte-platform.genericsensors.SamplingRate/#"
+        );
+    }
 }
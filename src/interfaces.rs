@@ -16,25 +16,98 @@
  * limitations under the License.
  */
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex, RwLock};
 
-use crate::{interface::traits::Mapping, types::AstarteType, AstarteError, Interface};
+use crate::interface::traits::Interface as InterfaceTrait;
+use crate::{types::AstarteType, AstarteError, Interface};
 
 #[derive(Clone)]
 pub struct Interfaces {
-    pub interfaces: HashMap<String, Interface>,
+    // shared by the clones of the client, updated by AstarteSdk::apply_interface_patch
+    interfaces: Arc<RwLock<HashMap<String, Arc<Interface>>>>,
+    // shared by the clones of the client
+    disabled: Arc<Mutex<HashSet<String>>>,
 }
 
 impl Interfaces {
     pub fn new(interfaces: HashMap<String, Interface>) -> Self {
-        Interfaces { interfaces }
+        Interfaces {
+            interfaces: Arc::new(RwLock::new(
+                interfaces
+                    .into_iter()
+                    .map(|(name, interface)| (name, Arc::new(interface)))
+                    .collect(),
+            )),
+            disabled: Default::default(),
+        }
     }
 
-    pub fn get_introspection_string(&self) -> String {
-        use crate::interface::traits::Interface;
+    /// returns the interface if it's in device introspection
+    pub fn get(&self, interface: &str) -> Option<Arc<Interface>> {
+        self.interfaces.read().unwrap().get(interface).cloned()
+    }
+
+    /// calls `f` on the interface if it's in device introspection, without cloning it
+    fn with_interface<F, R>(&self, interface: &str, f: F) -> Option<R>
+    where
+        F: FnOnce(&Interface) -> R,
+    {
+        self.interfaces.read().unwrap().get(interface).map(|i| f(i))
+    }
+
+    /// returns true if the interface is in device introspection
+    pub fn contains(&self, interface: &str) -> bool {
+        self.interfaces.read().unwrap().contains_key(interface)
+    }
+
+    /// number of interfaces in device introspection
+    pub fn count(&self) -> usize {
+        self.interfaces.read().unwrap().len()
+    }
+
+    /// returns all the interfaces in device introspection
+    pub fn all(&self) -> Vec<Arc<Interface>> {
+        self.interfaces.read().unwrap().values().cloned().collect()
+    }
+
+    /// adds an interface to device introspection, replacing the one with the same name
+    pub fn replace(&self, interface: Interface) {
+        self.interfaces
+            .write()
+            .unwrap()
+            .insert(interface.name().to_owned(), Arc::new(interface));
+    }
+
+    /// enables or disables an interface in device introspection, error if it's not present
+    pub fn set_enabled(&self, interface: &str, enabled: bool) -> Result<(), AstarteError> {
+        if !self.contains(interface) {
+            return Err(AstarteError::Reported(format!(
+                "Interface '{}' does not exists",
+                interface
+            )));
+        }
+
+        let mut disabled = self.disabled.lock().unwrap();
+        if enabled {
+            disabled.remove(interface);
+        } else {
+            disabled.insert(interface.to_owned());
+        }
 
+        Ok(())
+    }
+
+    /// returns false if the interface has been disabled
+    pub fn is_enabled(&self, interface: &str) -> bool {
+        !self.disabled.lock().unwrap().contains(interface)
+    }
+
+    pub fn get_introspection_string(&self) -> String {
         let mut introspection: String = self
             .interfaces
+            .read()
+            .unwrap()
             .iter()
             .map(|f| format!("{}:{}:{};", f.0, f.1.version().0, f.1.version().1))
             .collect();
@@ -47,22 +120,30 @@ impl Interfaces {
         &self,
         interface_name: &str,
         interface_path: &str,
-    ) -> Option<crate::interface::Mapping> {
-        self.interfaces
-            .iter()
-            .find(|i| i.0 == interface_name)
-            .and_then(|f| f.1.mapping(interface_path))
+    ) -> Option<crate::interface::MappingRef> {
+        crate::interface::MappingRef::new(self.get(interface_name)?, interface_path)
+    }
+
+    /// calls `f` on the mapping matching the path, without cloning the interface
+    pub fn with_mapping<F, R>(&self, interface_name: &str, interface_path: &str, f: F) -> Option<R>
+    where
+        F: FnOnce(crate::interface::Mapping) -> R,
+    {
+        self.with_interface(interface_name, |interface| {
+            interface.mapping(interface_path).map(f)
+        })
+        .flatten()
     }
 
     pub fn get_mqtt_reliability(&self, interface_name: &str, interface_path: &str) -> rumqttc::QoS {
         use rumqttc::QoS;
 
-        let mapping = self.get_mapping(interface_name, interface_path);
-
-        let reliability = match mapping {
-            Some(crate::interface::Mapping::Datastream(m)) => m.reliability,
-            _ => Default::default(),
-        };
+        let reliability = self
+            .with_mapping(interface_name, interface_path, |mapping| match mapping {
+                crate::interface::Mapping::Datastream(m) => m.reliability,
+                _ => Default::default(),
+            })
+            .unwrap_or_default();
 
         match reliability {
             crate::interface::Reliability::Unreliable => QoS::AtMostOnce,
@@ -71,20 +152,58 @@ impl Interfaces {
         }
     }
 
+    /// MQTT retain flag of the mapping, set only for the `stored` retention
+    ///
+    /// The Astarte retention is about how long the device keeps the data it could not send,
+    /// the retain flag asks the broker to keep the last value published on the topic.
+    pub fn get_mqtt_retain(&self, interface_name: &str, interface_path: &str) -> bool {
+        let retention = self
+            .with_mapping(interface_name, interface_path, |mapping| match mapping {
+                crate::interface::Mapping::Datastream(m) => m.retention,
+                _ => Default::default(),
+            })
+            .unwrap_or_default();
+
+        match retention {
+            crate::interface::Retention::Discard | crate::interface::Retention::Volatile => false,
+            crate::interface::Retention::Stored => true,
+        }
+    }
+
+    /// returns major and minor version if the interface is in device introspection, None otherwise
+    pub fn get_version(&self, interface: &str) -> Option<(i32, i32)> {
+        self.with_interface(interface, |interface| interface.version())
+    }
+
     /// returns major version if the property exists, None otherwise
     pub fn get_property_major(&self, interface: &str, path: &str) -> Option<i32> {
-        use crate::interface::traits::Interface;
+        self.with_interface(interface, |interface| {
+            interface.mapping(path)?;
 
-        let iface = self.interfaces.get(interface)?;
-        iface.mapping(path)?;
+            Some(interface.version().0)
+        })
+        .flatten()
+    }
 
-        Some(iface.version().0)
+    /// returns the metadata of the interface, None if it's not in the device introspection
+    pub fn get_interface_info(&self, interface: &str) -> Option<crate::interface::InterfaceInfo> {
+        self.with_interface(interface, |iface| {
+            let (version_major, version_minor) = iface.version();
+
+            crate::interface::InterfaceInfo {
+                name: iface.name().to_owned(),
+                version_major,
+                version_minor,
+                ownership: iface.ownership(),
+                aggregation: iface.aggregation(),
+                interface_type: iface.interface_type(),
+            }
+        })
     }
 
     /// returns ownership if the interface is present in device introspection, None otherwise
     pub fn get_ownership(&self, interface: &str) -> Option<crate::interface::Ownership> {
-        let iface = self.interfaces.get(interface)?;
-        Some(iface.get_ownership())
+        self.with_interface(interface, |iface| iface.get_ownership())
     }
 
     pub fn validate_float(data: &AstarteType) -> Result<(), AstarteError> {
@@ -113,6 +232,25 @@ impl Interfaces {
         Ok(())
     }
 
+    /// checks that the value has the type declared by the mapping, unset values are always valid
+    pub fn validate_type(
+        &self,
+        interface_name: &str,
+        interface_path: &str,
+        data: &AstarteType,
+    ) -> Result<(), AstarteError> {
+        let expected = self
+            .with_mapping(interface_name, interface_path, |mapping| {
+                mapping.mapping_type()
+            })
+            .ok_or_else(|| AstarteError::SendError("Mapping doesn't exist".into()))?;
+
+        match data.mapping_type() {
+            Some(got) if got != expected => Err(AstarteError::TypeMismatch { expected, got }),
+            _ => Ok(()),
+        }
+    }
+
     pub fn validate_send(
         &self,
         interface_name: &str,
@@ -123,14 +261,13 @@ impl Interfaces {
         let data_deserialized = crate::AstarteSdk::deserialize(data)?;
 
         let interface = self
-            .interfaces
             .get(interface_name)
             .ok_or_else(|| AstarteError::SendError("Interface does not exists".into()))?;
 
         match data_deserialized {
             crate::Aggregation::Individual(individual) => {
-                let mapping = self
-                    .get_mapping(interface_name, interface_path)
+                let mapping = interface
+                    .mapping(interface_path)
                     .ok_or_else(|| AstarteError::SendError("Mapping doesn't exist".into()))?;
 
                 if individual != mapping.mapping_type() {
@@ -165,8 +302,8 @@ impl Interfaces {
                 for obj in &object {
                     Interfaces::validate_float(obj.1)?;
 
-                    let mapping = self
-                        .get_mapping(interface_name, &format!("{}{}", interface_path, obj.0))
+                    let mapping = interface
+                        .mapping(&format!("{}{}", interface_path, obj.0))
                         .ok_or_else(|| AstarteError::SendError("Mapping doesn't exist".into()))?;
 
                     if *obj.1 != mapping.mapping_type() {
@@ -213,7 +350,7 @@ impl Interfaces {
             return Ok(());
         }
 
-        self.interfaces.get(interface_name).ok_or_else(|| {
+        let interface = self.get(interface_name).ok_or_else(|| {
             AstarteError::ReceiveError(format!("Interface '{}' does not exists", interface_name))
         })?;
 
@@ -221,14 +358,12 @@ impl Interfaces {
 
         match data {
             crate::Aggregation::Individual(individual) => {
-                let mapping = self
-                    .get_mapping(interface_name, interface_path)
-                    .ok_or_else(|| {
-                        AstarteError::ReceiveError(format!(
-                            "Mapping '{}' doesn't exist",
-                            interface_path
-                        ))
-                    })?;
+                let mapping = interface.mapping(interface_path).ok_or_else(|| {
+                    AstarteError::ReceiveError(format!(
+                        "Mapping '{}' doesn't exist",
+                        interface_path
+                    ))
+                })?;
 
                 match mapping {
                     crate::interface::Mapping::Datastream(_) => {}
@@ -253,8 +388,8 @@ impl Interfaces {
                 for obj in &object {
                     Interfaces::validate_float(obj.1)?;
 
-                    let mapping = self
-                        .get_mapping(interface_name, &format!("{}{}", interface_path, obj.0))
+                    let mapping = interface
+                        .mapping(&format!("{}{}", interface_path, obj.0))
                         .ok_or_else(|| {
                             AstarteError::ReceiveError("Mapping doesn't exist".into())
                         })?;
@@ -337,6 +472,61 @@ mod test {
             .unwrap_err();
     }
 
+    #[test]
+    fn test_get_interface_info() {
+        use crate::interface::{Aggregation, InterfaceType, Ownership};
+
+        let mut options = AstarteBuilder::new("test", "test", "test", "test");
+        options.add_interface_files("examples/interfaces/").unwrap();
+        let ifa = super::Interfaces::new(options.interfaces);
+
+        let info = ifa
+            .get_interface_info("org.astarte-platform.genericsensors.SamplingRate")
+            .unwrap();
+
+        assert_eq!(
+            info.name,
+            "org.astarte-platform.genericsensors.SamplingRate"
+        );
+        assert_eq!(info.version_major, 1);
+        assert_eq!(info.version_minor, 0);
+        assert_eq!(info.ownership, Ownership::Server);
+        assert_eq!(info.aggregation, Aggregation::Individual);
+        assert_eq!(info.interface_type, InterfaceType::Properties);
+        assert_eq!(
+            info.to_string(),
+            "org.astarte-platform.genericsensors.SamplingRate v1.0 (server/properties/individual)"
+        );
+
+        assert!(ifa.get_interface_info("com.test.Missing").is_none());
+    }
+
+    #[test]
+    fn test_validate_type() {
+        let mut options = AstarteBuilder::new("test", "test", "test", "test");
+        options.add_interface_files("examples/interfaces/").unwrap();
+        let ifa = super::Interfaces::new(options.interfaces);
+
+        ifa.validate_type(
+            "com.test.Everything",
+            "/boolean",
+            &AstarteType::Boolean(true),
+        )
+        .unwrap();
+        ifa.validate_type("com.test.Everything", "/boolean", &AstarteType::Unset)
+            .unwrap();
+        ifa.validate_type("com.test.Everything", "/fake", &AstarteType::Boolean(true))
+            .unwrap_err();
+
+        match ifa.validate_type("com.test.Everything", "/double", &AstarteType::Integer(3)) {
+            Err(crate::AstarteError::TypeMismatch { expected, got }) => {
+                assert_eq!(expected, crate::interface::MappingType::Double);
+                assert_eq!(got, crate::interface::MappingType::Integer);
+            }
+            res => panic!("expected a type mismatch, got {:?}", res),
+        }
+    }
+
     #[test]
     fn test_object() {
         let mut options = AstarteBuilder::new("test", "test", "test", "test");
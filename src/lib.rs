@@ -18,44 +18,187 @@
 
 #![doc = include_str!("../README.md")]
 
+#[macro_use]
+mod logging;
+
+mod backoff;
 pub mod builder;
 mod crypto;
 pub mod database;
-mod interface;
+pub mod handler;
+pub mod interface;
 mod interfaces;
+pub mod observability;
 mod pairing;
+mod ping;
+mod puback;
+pub mod recorder;
 pub mod registration;
+pub mod serializer;
+mod sink;
+pub mod stats;
+mod stream;
+#[cfg(feature = "system-info")]
+mod system_info;
 pub mod types;
 
 use bson::{to_document, Bson};
 use database::AstarteDatabase;
-use database::StoredProp;
+use database::{PropertyInfo, PropertySnapshot, StoredProp};
 use itertools::Itertools;
-use log::{debug, error, trace};
+use ping::PingTracker;
+use puback::{PubackTracker, QueuedPublish};
 use rumqttc::EventLoop;
 use rumqttc::{AsyncClient, Event};
+use serializer::AstarteSerializer;
+use stats::{ConnectionStats, StatsCounters};
 use std::collections::HashMap;
-use std::convert::TryInto;
+use std::convert::{TryFrom, TryInto};
 use std::fmt::{self, Debug};
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::{mpsc, oneshot, watch, Semaphore, SemaphorePermit};
 use types::AstarteType;
 
 pub use interface::Interface;
+pub use sink::SinkItem;
 
 /// Astarte client
 #[derive(Clone)]
 pub struct AstarteSdk {
     realm: String,
     device_id: String,
+    device_alias: Option<String>,
     credentials_secret: String,
     pairing_url: String,
     build_options: builder::BuildOptions,
+    // configuration to pair the device again
+    builder: Arc<builder::AstarteBuilder>,
     client: AsyncClient,
     eventloop: Arc<tokio::sync::Mutex<EventLoop>>,
+    requests: async_channel::Sender<rumqttc::Request>,
+    pings: Arc<Mutex<PingTracker>>,
+    property_waiters: Arc<Mutex<Vec<PropertyWaiter>>>,
+    event_senders: Arc<Mutex<Vec<mpsc::Sender<Clientbound>>>>,
+    property_senders: Arc<Mutex<Vec<mpsc::Sender<PropertyChange>>>>,
+    datastream_senders: Arc<Mutex<Vec<mpsc::Sender<DatastreamEvent>>>>,
+    property_expiries: Arc<Mutex<HashMap<(String, String), PropertyExpiry>>>,
+    error_sender: Arc<Mutex<Option<mpsc::Sender<AstarteError>>>>,
+    event_channel_size: usize,
+    pubacks: Arc<Mutex<PubackTracker>>,
+    publish_lock: Arc<tokio::sync::Mutex<()>>,
+    await_puback: bool,
+    backoff: Option<Arc<backoff::Backoff>>,
+    sync_strategy: SyncStrategy,
+    topic_prefix: String,
+    stats: Arc<StatsCounters>,
+    observability_hook: Arc<dyn observability::ObservabilityHook>,
+    handlers: Arc<HashMap<String, Arc<dyn handler::InterfaceHandler>>>,
+    created_at: Instant,
+    recorder: Option<Arc<recorder::EventRecorder>>,
+    connection_enabled: Arc<watch::Sender<bool>>,
+    connection_enabled_rx: watch::Receiver<bool>,
+    max_message_size: usize,
+    max_payload_size: usize,
+    property_resync_timeout: Duration,
+    publish_permits: Option<Arc<Semaphore>>,
+    dead_letter_queue: bool,
+    dead_letter_lock: Arc<tokio::sync::Mutex<()>>,
+    serializer: Option<Arc<dyn serializer::AstarteSerializer>>,
     interfaces: interfaces::Interfaces,
     database: Option<Arc<dyn AstarteDatabase + Sync + Send>>,
+    sink: sink::SinkState,
+    stream: stream::StreamState,
+}
+
+/// Size of the bson document of an individual value with a timestamp, without the value
+const INDIVIDUAL_DOCUMENT_SIZE: usize = 4 + 1 + (1 + 2) + (1 + 2 + 8);
+
+/// Interface used by [`AstarteSdk::report_error`]
+pub const ERROR_INTERFACE: &str = "org.astarte-platform.generic.errors.v1";
+
+/// Interface used by [`AstarteSdk::publish_product_info`]
+pub const PRODUCT_INFO_INTERFACE: &str = "org.astarte-platform.generic.ProductInfo.v1";
+
+/// Hardware and software description of the device, see [`AstarteSdk::publish_product_info`]
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ProductInfo {
+    pub hw_version: String,
+    pub sw_version: String,
+    pub model: String,
+}
+
+/// Pending unset of a property, see [`AstarteSdk::set_property_expiry`]
+struct PropertyExpiry {
+    // identifies the timer, since it may be replaced while it's unsetting the property
+    token: Arc<()>,
+    task: tokio::task::JoinHandle<()>,
+}
+
+/// Interface used by [`AstarteSdk::publish_system_info_with`]
+pub const SYSTEM_INFO_INTERFACE: &str = "org.astarte-platform.generic.SystemInfo.v1";
+
+/// Description of the system running the device, see [`AstarteSdk::publish_system_info_with`]
+///
+/// The values that are not available are not published.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SystemInfo {
+    pub hostname: Option<String>,
+    pub os_version: Option<String>,
+    /// Total RAM in bytes
+    pub total_ram: Option<i64>,
+    pub cpu_model: Option<String>,
+}
+
+/// A caller of [`AstarteSdk::wait_for_property`]
+struct PropertyWaiter {
+    interface: String,
+    path: String,
+    tx: oneshot::Sender<AstarteType>,
+}
+
+/// Time after which a send gives up waiting for the broker acknowledgement, see
+/// [`await_puback`](builder::AstarteBuilder::await_puback)
+const PUBACK_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// A message handed to the event loop by [`AstarteSdk::hand_over`]
+enum HandedOver {
+    /// Nothing to wait for, or stored in the dead letter queue
+    Done,
+    /// Waiting for the broker acknowledgement
+    Pending(oneshot::Receiver<u16>),
 }
 
+impl HandedOver {
+    /// Wait for the broker acknowledgement, returns its packet id, 0 if it's not awaited
+    async fn acknowledged(self) -> Result<u16, AstarteError> {
+        let rx = match self {
+            HandedOver::Done => return Ok(0),
+            HandedOver::Pending(rx) => rx,
+        };
+
+        let pkid = tokio::time::timeout(PUBACK_TIMEOUT, rx)
+            .await
+            .map_err(|_| AstarteError::PublishTimeout)?
+            .map_err(|_| AstarteError::SendError("puback waiter dropped".into()))?;
+        trace!("publish {} acknowledged", pkid);
+
+        Ok(pkid)
+    }
+}
+
+/// Time after which [`AstarteSdk::ping`] gives up waiting for the broker response
+const PING_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Time without new values after which [`AstarteSdk::force_property_sync`] stops waiting
+const SERVER_PROPERTIES_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Attempts of the background task draining the dead letter queue after a connection
+const DEAD_LETTER_ATTEMPTS: u32 = 5;
+
+/// Delays between the attempts to drain the dead letter queue, doubling from the first
+const DEAD_LETTER_BACKOFF: (Duration, Duration) = (Duration::from_secs(1), Duration::from_secs(30));
+
 #[derive(thiserror::Error, Debug)]
 pub enum AstarteError {
     #[error("bson serialize error")]
@@ -65,7 +208,10 @@ pub enum AstarteError {
     BsonClientError(#[from] rumqttc::ClientError),
 
     #[error("mqtt connection error")]
-    ConnectionError(#[from] rumqttc::ConnectionError),
+    ConnectionError(rumqttc::ConnectionError),
+
+    #[error("broker certificate doesn't match the pinned fingerprint")]
+    CertificatePinMismatch,
 
     #[error("malformed input from Astarte backend")]
     DeserializationError,
@@ -88,6 +234,71 @@ pub enum AstarteError {
     #[error("database error")]
     DbError(#[from] sqlx::Error),
 
+    #[error("io error")]
+    IoError(#[from] std::io::Error),
+
+    #[error("database is corrupted: {0}")]
+    DatabaseCorrupted(String),
+
+    #[error("type mismatch, expected {expected:?} got {got:?}")]
+    TypeMismatch {
+        expected: interface::MappingType,
+        got: interface::MappingType,
+    },
+
+    #[error("invalid interface")]
+    InterfaceError(#[from] interface::Error),
+
+    #[error("invalid object: {}", .errors.join(", "))]
+    AggregateValidation { errors: Vec<String> },
+
+    #[error("interface {0} is disabled")]
+    InterfaceDisabled(String),
+
+    #[error("no ping response from the broker")]
+    PingTimeout,
+
+    #[error("timed out")]
+    Timeout,
+
+    #[error("publish timed out")]
+    PublishTimeout,
+
+    #[error("burst reading of {size} bytes exceeds the maximum message size of {max} bytes")]
+    BurstTooLarge { size: usize, max: usize },
+
+    #[error("payload of {size} bytes exceeds the limit of {limit} bytes")]
+    PayloadTooLarge { size: usize, limit: usize },
+
+    #[error("interface {name} minor version {new} is not greater than {registered}")]
+    InterfaceVersionDowngrade {
+        name: String,
+        registered: i32,
+        new: i32,
+    },
+
+    #[error("interface {name} major version changed from {registered} to {new}")]
+    InterfaceMajorChanged {
+        name: String,
+        registered: i32,
+        new: i32,
+    },
+
+    #[error("interface {0} is an object interface, values must be sent with send_object")]
+    WrongAggregation(String),
+
+    #[error("{} of the values in the batch could not be sent", .failed.len())]
+    PartialBatch {
+        sent: usize,
+        failed: Vec<(String, AstarteError)>,
+    },
+
+    #[error("{context}: {source}")]
+    Contextual {
+        context: String,
+        source: Box<AstarteError>,
+    },
+
     #[error("generic error")]
     Reported(String),
 
@@ -95,28 +306,213 @@ pub enum AstarteError {
     Unreported,
 }
 
-#[derive(Debug)]
+impl AstarteError {
+    /// Whether the condition causing the error may go away on its own, so that retrying the
+    /// same operation later can succeed
+    pub fn is_transient(&self) -> bool {
+        match self {
+            AstarteError::Contextual { source, .. } => source.is_transient(),
+            AstarteError::PartialBatch { failed, .. } => {
+                failed.iter().all(|(_, err)| err.is_transient())
+            }
+            AstarteError::BsonClientError(_)
+            | AstarteError::ConnectionError(_)
+            | AstarteError::DbError(_)
+            | AstarteError::IoError(_)
+            | AstarteError::PingTimeout
+            | AstarteError::Timeout
+            | AstarteError::PublishTimeout => true,
+            AstarteError::BsonSerError(_)
+            | AstarteError::InterfaceError(_)
+            | AstarteError::InterfaceDisabled(_)
+            | AstarteError::AggregateValidation { .. }
+            | AstarteError::DatabaseCorrupted(_)
+            | AstarteError::DeserializationError
+            | AstarteError::FromBsonError
+            | AstarteError::FromBsonArrayError
+            | AstarteError::FloatError
+            | AstarteError::SendError(_)
+            | AstarteError::ReceiveError(_)
+            | AstarteError::TypeMismatch { .. }
+            | AstarteError::CertificatePinMismatch
+            | AstarteError::BurstTooLarge { .. }
+            | AstarteError::PayloadTooLarge { .. }
+            | AstarteError::InterfaceVersionDowngrade { .. }
+            | AstarteError::InterfaceMajorChanged { .. }
+            | AstarteError::WrongAggregation(_)
+            | AstarteError::Reported(_)
+            | AstarteError::Unreported => false,
+        }
+    }
+
+    /// Whether the sdk can heal from the error by itself, reconnecting to the broker or
+    /// retrying on the database
+    ///
+    /// Configuration errors, like a missing interface or a type mismatch, are never
+    /// recoverable and must be fixed by the caller.
+    pub fn is_recoverable(&self) -> bool {
+        match self {
+            AstarteError::Contextual { source, .. } => source.is_recoverable(),
+            AstarteError::PartialBatch { failed, .. } => {
+                failed.iter().all(|(_, err)| err.is_recoverable())
+            }
+            AstarteError::BsonClientError(_)
+            | AstarteError::ConnectionError(_)
+            | AstarteError::DbError(_)
+            | AstarteError::PingTimeout
+            | AstarteError::Timeout
+            | AstarteError::PublishTimeout => true,
+            AstarteError::IoError(_)
+            | AstarteError::BsonSerError(_)
+            | AstarteError::InterfaceError(_)
+            | AstarteError::InterfaceDisabled(_)
+            | AstarteError::AggregateValidation { .. }
+            | AstarteError::DatabaseCorrupted(_)
+            | AstarteError::DeserializationError
+            | AstarteError::FromBsonError
+            | AstarteError::FromBsonArrayError
+            | AstarteError::FloatError
+            | AstarteError::SendError(_)
+            | AstarteError::ReceiveError(_)
+            | AstarteError::TypeMismatch { .. }
+            | AstarteError::CertificatePinMismatch
+            | AstarteError::BurstTooLarge { .. }
+            | AstarteError::PayloadTooLarge { .. }
+            | AstarteError::InterfaceVersionDowngrade { .. }
+            | AstarteError::InterfaceMajorChanged { .. }
+            | AstarteError::WrongAggregation(_)
+            | AstarteError::Reported(_)
+            | AstarteError::Unreported => false,
+        }
+    }
+
+    /// Wrap the error with a message telling what was being done when it happened
+    ///
+    /// The message is shown before the error, e.g. after
+    /// `res.map_err(|err| err.context("while storing property foo/bar"))?`. The classification
+    /// is the one of the wrapped error.
+    pub fn context(self, msg: impl Into<String>) -> AstarteError {
+        AstarteError::Contextual {
+            context: msg.into(),
+            source: Box::new(self),
+        }
+    }
+
+    /// Both classifications of the error, as `(transient, recoverable)`
+    pub fn classify(&self) -> (bool, bool) {
+        (self.is_transient(), self.is_recoverable())
+    }
+}
+
+impl From<rumqttc::ConnectionError> for AstarteError {
+    fn from(err: rumqttc::ConnectionError) -> Self {
+        if builder::is_pin_mismatch(&err) {
+            return AstarteError::CertificatePinMismatch;
+        }
+
+        AstarteError::ConnectionError(err)
+    }
+}
+
+#[derive(Debug, Clone)]
 pub enum Aggregation {
     Individual(AstarteType),
     Object(HashMap<String, AstarteType>),
 }
 
 /// data from astarte to device
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Clientbound {
     pub interface: String,
     pub path: String,
     pub data: Aggregation,
 }
 
-fn parse_topic(topic: &str) -> Option<(String, String, String, String)> {
-    let mut parts = topic.split('/');
+/// New value of a server-owned property, see [`AstarteSdk::subscribe_property_changes`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct PropertyChange {
+    pub interface: String,
+    pub path: String,
+    /// `None` if the property was unset
+    pub new_value: Option<AstarteType>,
+}
+
+/// Value received on an individual datastream, see [`AstarteSdk::subscribe_datastream`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct DatastreamEvent {
+    pub interface: String,
+    pub path: String,
+    pub value: AstarteType,
+    /// Explicit timestamp sent with the value, if any
+    pub timestamp: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+/// Details of a value sent with [`AstarteSdk::send_individual`]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MessageReceipt {
+    /// Packet id acknowledged by the broker, 0 if the client doesn't wait for the ack
+    pub packet_id: u16,
+    /// Size of the published payload, 0 if a property already had the value and wasn't sent
+    pub serialized_bytes: usize,
+    /// When the message was handed to the mqtt client
+    pub publish_queued_at: Instant,
+}
+
+/// Summary of [`AstarteSdk::replay_stored_datastream`]
+#[derive(Debug, Default)]
+pub struct ReplayResult {
+    pub sent: u64,
+    pub failed: u64,
+    pub errors: Vec<AstarteError>,
+}
+
+/// Side whose values are kept by [`AstarteSdk::force_property_sync`]
+///
+/// The strategy only applies to the conflicts, the server-owned properties whose cached value
+/// differs from the one on Astarte. The device-owned properties are always published again.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SyncStrategy {
+    /// Keep the values on Astarte
+    ServerWins,
+    /// Keep the values cached by the device
+    DeviceWins,
+}
+
+impl Default for SyncStrategy {
+    fn default() -> Self {
+        SyncStrategy::ServerWins
+    }
+}
+
+/// Summary of [`AstarteSdk::force_property_sync`]
+#[derive(Debug, Default, PartialEq)]
+pub struct SyncReport {
+    /// Server-owned properties received from Astarte that weren't cached by the device
+    pub updated_from_server: u32,
+    /// Device-owned properties published to Astarte
+    pub pushed_to_server: u32,
+    /// Server-owned properties whose cached value differed from the one on Astarte
+    pub conflicts: u32,
+}
+
+/// Result of [`AstarteSdk::check_interface_compatibility`]
+#[derive(Debug, Default, PartialEq)]
+pub struct CompatibilityReport {
+    /// The new interface can replace the registered one without losing data
+    pub compatible: bool,
+    /// Stored properties that would be invalid with the new interface
+    pub stored_props_affected: u64,
+    /// Changes from the registered interface that break the data sent with it
+    pub breaking_changes: Vec<String>,
+}
+
+/// Split a topic under `prefix` into the interface and the path
+fn parse_topic(prefix: &str, topic: &str) -> Option<(String, String)> {
+    let mut parts = topic.strip_prefix(prefix)?.strip_prefix('/')?.split('/');
 
-    let realm = parts.next()?.to_owned();
-    let device = parts.next()?.to_owned();
     let interface = parts.next()?.to_owned();
     let path = String::from("/") + &parts.join("/");
-    Some((realm, device, interface, path))
+    Some((interface, path))
 }
 
 impl AstarteSdk {
@@ -136,571 +532,5547 @@ impl AstarteSdk {
     /// }
     /// ```
     pub async fn poll(&mut self) -> Result<Clientbound, AstarteError> {
+        self.poll_until(None).await
+    }
+
+    /// Poll until there is data for the user, or fail with [`AstarteError::Timeout`] once
+    /// `deadline` is reached
+    ///
+    /// Only the wait for the next event is interrupted by the deadline, an event already
+    /// received is always handled completely.
+    async fn poll_until(
+        &mut self,
+        deadline: Option<tokio::time::Instant>,
+    ) -> Result<Clientbound, AstarteError> {
         loop {
             // keep consuming and processing packets until we have data for the user
-            match self.eventloop.lock().await.poll().await? {
+            let event = match deadline {
+                Some(deadline) => tokio::time::timeout_at(deadline, self.next_event())
+                    .await
+                    .map_err(|_| AstarteError::Timeout)??,
+                None => self.next_event().await?,
+            };
+
+            match event {
                 Event::Incoming(i) => {
                     trace!("MQTT Incoming = {:?}", i);
 
                     match i {
                         rumqttc::Packet::ConnAck(p) => {
-                            if !p.session_present {
-                                self.send_introspection().await?;
-                                self.send_emptycache().await?;
-                                self.send_device_owned_properties().await?;
+                            self.stats.connected();
+                            self.pings.lock().unwrap().connected();
+                            if let Some(backoff) = &self.backoff {
+                                backoff.connected();
                             }
-                        }
-                        rumqttc::Packet::Publish(p) => {
-                            let topic = parse_topic(&p.topic);
 
-                            if let Some((_, _, interface, path)) = topic {
-                                if interface == "control" && path == "/consumer/properties" {
-                                    // TODO: implement consumer purge properties
-                                    continue;
-                                }
+                            if !p.session_present {
+                                self.publish_introspection().await?;
 
-                                let bdata = p.payload.to_vec();
-
-                                debug!("Incoming publish = {} {:?}", p.topic, bdata);
-
-                                if let Some(database) = &self.database {
-                                    //if database is loaded
-
-                                    if let Some(major_version) =
-                                        self.interfaces.get_property_major(&interface, &path)
-                                    //if it's a property
-                                    {
-                                        database
-                                            .store_prop(&interface, &path, &bdata, major_version)
-                                            .await?;
-
-                                        if cfg!(debug_assertions) {
-                                            // database selftest / sanity check for debug builds
-                                            let original = crate::AstarteSdk::deserialize(&bdata)?;
-                                            if let Aggregation::Individual(data) = original {
-                                                let db = database
-                                                .load_prop(&interface, &path, major_version)
-                                                .await
-                                                .expect("load_prop failed")
-                                                .expect(
-                                                    "property wasn't correctly saved in the database",
-                                                );
-                                                assert!(data == db);
-                                                let prop = self
-                                                .get_property(&interface, &path)
-                                                .await?
-                                                .expect(
-                                                "property wasn't correctly saved in the database",
-                                            );
-                                                assert!(data == prop);
-                                                trace!("database test ok");
-                                            } else {
-                                                panic!("This should be impossible, can't have object properties");
-                                            }
-                                        }
-                                    }
+                                if self.property_cache_is_empty().await? {
+                                    self.publish_empty_cache().await?;
                                 }
 
-                                if cfg!(debug_assertions) {
-                                    self.interfaces
-                                        .validate_receive(&interface, &path, &bdata)?;
-                                }
+                                self.send_device_owned_properties().await?;
+                            }
 
-                                let data = AstarteSdk::deserialize(&bdata)?;
-                                return Ok(Clientbound {
-                                    interface,
-                                    path,
-                                    data,
-                                });
+                            if self.dead_letter_queue {
+                                self.spawn_dead_letter_drain();
+                            }
+                        }
+                        rumqttc::Packet::PubAck(p) => {
+                            self.pubacks.lock().unwrap().acked(p.pkid);
+                        }
+                        rumqttc::Packet::PubComp(p) => {
+                            self.pubacks.lock().unwrap().acked(p.pkid);
+                        }
+                        rumqttc::Packet::PingResp => {
+                            self.pings.lock().unwrap().responded();
+                        }
+                        rumqttc::Packet::Publish(p) => {
+                            if let Some(data) = self.process_publish(&p.topic, &p.payload).await? {
+                                return Ok(data);
                             }
                         }
                         _ => {}
                     }
                 }
-                Event::Outgoing(o) => trace!("MQTT Outgoing = {:?}", o),
+                Event::Outgoing(o) => {
+                    trace!("MQTT Outgoing = {:?}", o);
+
+                    match o {
+                        rumqttc::Outgoing::Publish(pkid) => {
+                            self.pubacks.lock().unwrap().outgoing(pkid);
+                        }
+                        rumqttc::Outgoing::PingReq => {
+                            self.pings.lock().unwrap().outgoing();
+                        }
+                        _ => {}
+                    }
+                }
             }
         }
     }
 
-    fn client_id(&self) -> String {
-        format!("{}/{}", self.realm, self.device_id)
-    }
-
-    async fn send_emptycache(&self) -> Result<(), AstarteError> {
-        let url = self.client_id() + "/control/emptyCache";
-        debug!("sending emptyCache to {}", url);
+    /// Wait for the next event of the event loop, reconnecting if needed
+    async fn next_event(&self) -> Result<Event, AstarteError> {
+        // wait for reconnect if the client was disconnected by the user
+        let mut enabled = self.connection_enabled_rx.clone();
+        while !*enabled.borrow() {
+            enabled
+                .changed()
+                .await
+                .map_err(|_| AstarteError::Reported("connection state dropped".into()))?;
+        }
 
-        self.client
-            .publish(url, rumqttc::QoS::ExactlyOnce, false, "1")
-            .await?;
+        if let Some(delay) = self.backoff.as_ref().and_then(|b| b.next_delay()) {
+            debug!("reconnecting in {:?}", delay);
+            tokio::time::sleep(delay).await;
+        }
 
-        Ok(())
+        self.eventloop.lock().await.poll().await.map_err(|err| {
+            self.stats.disconnected();
+            if let Some(backoff) = &self.backoff {
+                backoff.failed();
+            }
+            let err = AstarteError::from(err);
+            self.observability_hook.on_error(&err);
+            err
+        })
     }
 
-    async fn send_introspection(&self) -> Result<(), AstarteError> {
-        let introspection = self.interfaces.get_introspection_string();
+    /// Poll until a value matching `predicate` is received, dropping the others
+    ///
+    /// Returns [`AstarteError::Timeout`] if no matching value arrives within `timeout`, the
+    /// errors of [`poll`](AstarteSdk::poll) are returned as they happen. The timeout only
+    /// interrupts the wait for new packets, a packet already received is always handled. Useful
+    /// in tests and simple scripts waiting for a specific value.
+    pub async fn recv_with_filter<F>(
+        &mut self,
+        predicate: F,
+        timeout: Duration,
+    ) -> Result<Clientbound, AstarteError>
+    where
+        F: Fn(&Clientbound) -> bool,
+    {
+        let deadline = tokio::time::Instant::now() + timeout;
 
-        debug!("sending introspection = {}", introspection);
+        loop {
+            let data = self.poll_until(Some(deadline)).await?;
+            if predicate(&data) {
+                return Ok(data);
+            }
 
-        self.client
-            .publish(
-                self.client_id(),
-                rumqttc::QoS::ExactlyOnce,
-                false,
-                introspection.clone(),
-            )
-            .await?;
-        Ok(())
+            trace!(
+                "dropping {}{}, not matching the filter",
+                data.interface,
+                data.path
+            );
+        }
     }
 
-    async fn send_device_owned_properties(&self) -> Result<(), AstarteError> {
-        if let Some(database) = &self.database {
-            let properties = database.load_all_props().await?;
-            // publish only device-owned properties...
-            let device_owned_properties: Vec<StoredProp> = properties
-                .into_iter()
-                .filter(|prop| {
-                    self.interfaces.get_ownership(&prop.interface)
-                        == Some(crate::interface::Ownership::Device)
-                })
-                .collect();
-            for prop in device_owned_properties {
-                let topic = format!("{}/{}{}", self.client_id(), prop.interface, prop.path);
-                if let Some(version_major) = self
-                    .interfaces
-                    .get_property_major(&prop.interface, &prop.path)
-                {
-                    // ..and only if they are up-to-date
-                    if version_major == prop.interface_major {
-                        debug!(
-                            "sending device-owned property = {}{}",
-                            prop.interface, prop.path
-                        );
-                        self.client
-                            .publish(topic, rumqttc::QoS::ExactlyOnce, false, prop.value)
-                            .await?;
-                    }
+    /// Poll up to `max` values, returning the ones received when `timeout` elapses
+    ///
+    /// The batch may be empty if nothing has been received. An error of
+    /// [`poll`](AstarteSdk::poll) is returned only if no value was received before it, otherwise
+    /// the values are returned and the error is only logged. Like
+    /// [`recv_with_filter`](AstarteSdk::recv_with_filter), the timeout only interrupts the wait
+    /// for new packets.
+    pub async fn recv_batch(
+        &mut self,
+        max: usize,
+        timeout: Duration,
+    ) -> Result<Vec<Clientbound>, AstarteError> {
+        let deadline = tokio::time::Instant::now() + timeout;
+        let mut batch = Vec::new();
+
+        while batch.len() < max {
+            match self.poll_until(Some(deadline)).await {
+                Ok(data) => batch.push(data),
+                Err(AstarteError::Timeout) => break,
+                Err(err) if batch.is_empty() => return Err(err),
+                Err(err) => {
+                    warn!("poll failed, returning {} values: {}", batch.len(), err);
+                    break;
                 }
             }
         }
 
-        Ok(())
+        Ok(batch)
     }
 
-    /// unset a device property
-    pub async fn unset<D>(
+    /// Handle a publish from Astarte, returns `None` if there is no data for the user
+    async fn process_publish(
         &self,
-        interface_name: &str,
-        interface_path: &str,
-    ) -> Result<(), AstarteError>
-    where
-        D: Into<AstarteType>,
-    {
-        trace!("unsetting {} {}", interface_name, interface_path);
+        topic: &str,
+        payload: &[u8],
+    ) -> Result<Option<Clientbound>, AstarteError> {
+        let (interface, path) = match parse_topic(&self.topic_prefix, topic) {
+            Some(parsed) => parsed,
+            None => return Ok(None),
+        };
 
-        if cfg!(debug_assertions) {
-            self.interfaces
-                .validate_send(interface_name, interface_path, &[], &None)?;
+        if interface == "control" && path == "/consumer/properties" {
+            // TODO: implement consumer purge properties
+            return Ok(None);
         }
 
-        self.send_with_timestamp_impl(interface_name, interface_path, AstarteType::Unset, None)
-            .await?;
+        self.stats.received(payload.len());
+        self.observability_hook
+            .on_receive(&interface, &path, payload.len());
 
-        Ok(())
-    }
+        debug!("Incoming publish = {} {:?}", topic, payload);
 
-    /// Serialize data directly from Bson
-    fn serialize(
-        data: Bson,
-        timestamp: Option<chrono::DateTime<chrono::Utc>>,
-    ) -> Result<Vec<u8>, AstarteError> {
-        if let Bson::Null = data {
-            return Ok(Vec::new());
+        let bdata = self.decode_payload(payload)?;
+
+        if !self.interfaces.is_enabled(&interface) {
+            debug!("dropping publish on disabled interface {}", interface);
+            return Ok(None);
         }
 
-        let doc = if let Some(timestamp) = timestamp {
-            bson::doc! {
-               "t": timestamp,
-               "v": data
-            }
-        } else {
-            bson::doc! {
-               "v": data,
-            }
-        };
+        if let Some(database) = &self.database {
+            //if database is loaded
 
-        let mut buf = Vec::new();
-        doc.to_writer(&mut buf)?;
-        trace!("serialized {:#?}", doc);
-        Ok(buf)
-    }
+            if let Some(major_version) = self.stored_property_major(&interface, &path)
+            //if it's a property
+            {
+                database
+                    .store_prop(&interface, &path, &bdata, major_version)
+                    .await?;
 
-    fn deserialize(bdata: &[u8]) -> Result<Aggregation, AstarteError> {
-        if bdata.is_empty() {
-            return Ok(Aggregation::Individual(AstarteType::Unset));
+                if cfg!(debug_assertions) {
+                    // database selftest / sanity check for debug builds
+                    let original = crate::AstarteSdk::deserialize(&bdata)?;
+                    if let Aggregation::Individual(data) = original {
+                        let db = database
+                            .load_prop(&interface, &path, major_version)
+                            .await
+                            .expect("load_prop failed")
+                            .expect("property wasn't correctly saved in the database");
+                        assert!(data == db);
+                        let prop = self
+                            .get_property(&interface, &path)
+                            .await?
+                            .expect("property wasn't correctly saved in the database");
+                        assert!(data == prop);
+                        trace!("database test ok");
+                    } else {
+                        panic!("This should be impossible, can't have object properties");
+                    }
+                }
+            }
         }
-        if let Ok(deserialized) = bson::Document::from_reader(&mut std::io::Cursor::new(bdata)) {
-            trace!("{:?}", deserialized);
-            if let Some(v) = deserialized.get("v") {
-                if let Bson::Document(doc) = v {
-                    let strings = doc.iter().map(|f| f.0.clone());
 
-                    let data = doc.iter().map(|f| f.1.clone().try_into());
-                    let data: Result<Vec<AstarteType>, AstarteError> = data.collect();
-                    let data = data?;
+        if cfg!(debug_assertions) {
+            self.interfaces
+                .validate_receive(&interface, &path, &bdata)?;
+        }
 
-                    let hmap: HashMap<String, AstarteType> = strings.zip(data).collect();
+        let data = AstarteSdk::deserialize(&bdata)?;
 
-                    Ok(Aggregation::Object(hmap))
-                } else if let Ok(v) = v.clone().try_into() {
-                    Ok(Aggregation::Individual(v))
-                } else {
-                    Err(AstarteError::DeserializationError)
-                }
-            } else {
-                Err(AstarteError::DeserializationError)
+        if let Aggregation::Individual(value) = &data {
+            self.notify_property_waiters(&interface, &path, value);
+        }
+
+        let event = Clientbound {
+            interface,
+            path,
+            data,
+        };
+        self.forward_event(&event).await;
+        self.forward_property_change(&event).await;
+        self.forward_datastream(&event, &bdata).await;
+        self.call_handler(&event).await;
+
+        if let Some(recorder) = &self.recorder {
+            if let Err(err) = recorder.record(&event).await {
+                error!("couldn't record event: {}", err);
+                self.report_event_loop_error(err);
             }
-        } else {
-            Err(AstarteError::DeserializationError)
         }
+
+        Ok(Some(event))
     }
 
-    /// get property from database, if present
-    pub async fn get_property(
-        &self,
-        interface: &str,
-        path: &str,
-    ) -> Result<Option<AstarteType>, AstarteError> {
-        if let Some(database) = &self.database {
-            if let Some(major) = self.interfaces.get_property_major(interface, path) {
-                let prop = database.load_prop(interface, path, major).await?;
-                return Ok(prop);
-            }
+    /// Pass the event to the handler registered for its interface, if any
+    async fn call_handler(&self, event: &Clientbound) {
+        let handler = match self.handlers.get(&event.interface) {
+            Some(handler) => handler,
+            None => return,
+        };
+
+        if let Err(err) = handler.on_event(event.clone()).await {
+            error!(
+                "handler of {} failed on {}: {}",
+                event.interface, event.path, err
+            );
+            self.report_event_loop_error(err);
         }
+    }
 
-        Ok(None)
+    /// Send a copy of the event to the receivers returned by [`subscribe_all`](AstarteSdk::subscribe_all)
+    async fn forward_event(&self, event: &Clientbound) {
+        let senders = self.event_senders.lock().unwrap().clone();
+
+        for sender in senders {
+            // waits for slow receivers, a closed receiver is removed below
+            let _ = sender.send(event.clone()).await;
+        }
+
+        self.event_senders
+            .lock()
+            .unwrap()
+            .retain(|sender| !sender.is_closed());
     }
 
-    // ------------------------------------------------------------------------
-    // individual types
-    // ------------------------------------------------------------------------
+    /// Send the changes of the server-owned properties to the receivers returned by
+    /// [`subscribe_property_changes`](AstarteSdk::subscribe_property_changes)
+    async fn forward_property_change(&self, event: &Clientbound) {
+        let senders = self.property_senders.lock().unwrap().clone();
+        if senders.is_empty() {
+            return;
+        }
 
-    /// Send data to an astarte interface
-    /// ```no_run
-    /// #[tokio::main]
-    /// async fn main() {
-    ///     let mut sdk_options = astarte_sdk::builder::AstarteBuilder::new("_","_","_","_");
-    ///     sdk_options.build().await.unwrap();
-    ///     let d = sdk_options.connect().await.unwrap();
+        let is_server_property = self
+            .get_interface_info(&event.interface)
+            .map_or(false, |info| Self::is_server_property(&info));
+        let new_value = match &event.data {
+            Aggregation::Individual(AstarteType::Unset) if is_server_property => None,
+            Aggregation::Individual(value) if is_server_property => Some(value.clone()),
+            _ => return,
+        };
+
+        let change = PropertyChange {
+            interface: event.interface.clone(),
+            path: event.path.clone(),
+            new_value,
+        };
+
+        for sender in senders {
+            let _ = sender.send(change.clone()).await;
+        }
+
+        self.property_senders
+            .lock()
+            .unwrap()
+            .retain(|sender| !sender.is_closed());
+    }
+
+    /// Send the individual datastream values to the receivers returned by
+    /// [`subscribe_datastream`](AstarteSdk::subscribe_datastream)
+    async fn forward_datastream(&self, event: &Clientbound, bdata: &[u8]) {
+        let senders = self.datastream_senders.lock().unwrap().clone();
+        if senders.is_empty() {
+            return;
+        }
+
+        let is_datastream = self
+            .get_interface_info(&event.interface)
+            .map_or(false, |info| {
+                info.interface_type == interface::InterfaceType::Datastream
+            });
+        let value = match &event.data {
+            Aggregation::Individual(value) if is_datastream => value.clone(),
+            _ => return,
+        };
+
+        let datastream = DatastreamEvent {
+            interface: event.interface.clone(),
+            path: event.path.clone(),
+            value,
+            timestamp: AstarteSdk::deserialize_timestamp(bdata),
+        };
+
+        for sender in senders {
+            let _ = sender.send(datastream.clone()).await;
+        }
+
+        self.datastream_senders
+            .lock()
+            .unwrap()
+            .retain(|sender| !sender.is_closed());
+    }
+
+    /// Receive all the data from Astarte on a channel, in arrival order
     ///
-    ///     d.send("com.test.interface", "/data", 45).await.unwrap();
-    /// }
-    /// ```
+    /// Data is still returned by [`poll`](AstarteSdk::poll), which must be called for the channel
+    /// to receive anything. Polling waits for the channel to have space, so a slow receiver slows
+    /// down the whole client. The size of the channel is set with
+    /// [`set_event_channel_size`](builder::AstarteBuilder::set_event_channel_size).
+    pub fn subscribe_all(&self) -> mpsc::Receiver<Clientbound> {
+        let (tx, rx) = mpsc::channel(self.event_channel_size);
+        self.event_senders.lock().unwrap().push(tx);
+        rx
+    }
 
-    pub async fn send<D>(
+    /// Receive the changes of the server-owned properties on a channel, in arrival order
+    ///
+    /// Works like [`subscribe_all`](AstarteSdk::subscribe_all), without the datastream values
+    /// and the device-owned properties.
+    pub fn subscribe_property_changes(&self) -> mpsc::Receiver<PropertyChange> {
+        let (tx, rx) = mpsc::channel(self.event_channel_size);
+        self.property_senders.lock().unwrap().push(tx);
+        rx
+    }
+
+    /// Receive the values of the individual datastreams on a channel, in arrival order
+    ///
+    /// Works like [`subscribe_all`](AstarteSdk::subscribe_all), without the properties and the
+    /// object datastreams.
+    pub fn subscribe_datastream(&self) -> mpsc::Receiver<DatastreamEvent> {
+        let (tx, rx) = mpsc::channel(self.event_channel_size);
+        self.datastream_senders.lock().unwrap().push(tx);
+        rx
+    }
+
+    /// Receive the errors [`poll`](AstarteSdk::poll) handles without returning them, like a
+    /// failure to record an event
+    ///
+    /// The errors that stop the polling are still returned by `poll`. Errors are dropped when
+    /// the channel is full, and the channel is closed once all the clones of the client are
+    /// dropped. Only the last returned receiver gets the errors.
+    pub fn event_loop_errors(&self) -> mpsc::Receiver<AstarteError> {
+        let (tx, rx) = mpsc::channel(self.event_channel_size);
+        *self.error_sender.lock().unwrap() = Some(tx);
+        rx
+    }
+
+    fn report_event_loop_error(&self, err: AstarteError) {
+        if let Some(sender) = &*self.error_sender.lock().unwrap() {
+            if sender.try_send(err).is_err() {
+                warn!("error channel is full or closed, dropping the error");
+            }
+        }
+    }
+
+    /// Close the connection with the broker until [`reconnect`](AstarteSdk::reconnect) is called
+    ///
+    /// Sending fails while disconnected, and [`poll`](AstarteSdk::poll) waits for the reconnection.
+    pub async fn disconnect(&self) -> Result<(), AstarteError> {
+        self.connection_enabled.send_replace(false);
+        self.client.disconnect().await?;
+        self.stats.disconnected();
+
+        Ok(())
+    }
+
+    /// Connect again to the broker after [`disconnect`](AstarteSdk::disconnect)
+    ///
+    /// The connection is established by the next [`poll`](AstarteSdk::poll), which also publishes
+    /// the introspection and the device-owned properties when the broker didn't keep the session.
+    pub async fn reconnect(&self) -> Result<(), AstarteError> {
+        self.connection_enabled.send_replace(true);
+
+        Ok(())
+    }
+
+    /// Reset the device to its factory state, with new credentials and no stored properties
+    ///
+    /// A new certificate is requested to the Pairing API, then the client disconnects from the
+    /// broker, removes all the stored properties and connects again with the new certificate.
+    /// The properties stored before the call are permanently lost, Astarte sends again the
+    /// server-owned ones once connected. If a step fails, the previous ones are rolled back and
+    /// the client keeps the old certificate and the stored properties. Another task must be
+    /// polling the client for the disconnection to complete.
+    pub async fn factory_reset(&self) -> Result<(), AstarteError> {
+        let mqtt_opts = self
+            .builder
+            .pair_again(&self.build_options.broker_url)
+            .await
+            .map_err(|err| AstarteError::Reported(format!("couldn't pair the device: {}", err)))?;
+
+        if let Err(err) = self.disconnect().await {
+            self.reconnect().await?;
+            return Err(err);
+        }
+
+        let old_opts = std::mem::replace(&mut self.eventloop.lock().await.options, mqtt_opts);
+
+        if let Some(database) = &self.database {
+            if let Err(err) = database.clear().await {
+                self.eventloop.lock().await.options = old_opts;
+                self.reconnect().await?;
+                return Err(err);
+            }
+        }
+
+        info!("factory reset done, connecting with the new credentials");
+
+        self.reconnect().await
+    }
+
+    /// Measure the round-trip time to the broker
+    ///
+    /// A PINGREQ is sent to the broker and the time until the PINGRESP is returned.
+    /// Responses are received by [`poll`](AstarteSdk::poll), so another task must be
+    /// polling the client while waiting.
+    pub async fn ping(&self) -> Result<Duration, AstarteError> {
+        let rx = self.pings.lock().unwrap().wait();
+
+        let start = tokio::time::Instant::now();
+
+        self.requests
+            .send(rumqttc::Request::PingReq)
+            .await
+            .map_err(|_| AstarteError::SendError("mqtt event loop is closed".into()))?;
+
+        match tokio::time::timeout(PING_TIMEOUT, rx).await {
+            Ok(Ok(())) => Ok(start.elapsed()),
+            Ok(Err(_)) => Err(AstarteError::ReceiveError("ping waiter dropped".into())),
+            Err(_) => {
+                self.pings.lock().unwrap().remove_closed();
+                Err(AstarteError::PingTimeout)
+            }
+        }
+    }
+
+    /// Wait for a value of a property sent by Astarte
+    ///
+    /// Returns the first value received by [`poll`](AstarteSdk::poll) after the call, so
+    /// another task must be polling the client while waiting.
+    pub async fn wait_for_property(
         &self,
-        interface_name: &str,
-        interface_path: &str,
-        data: D,
+        interface: &str,
+        path: &str,
+        timeout: Duration,
+    ) -> Result<AstarteType, AstarteError> {
+        let (tx, rx) = oneshot::channel();
+        self.property_waiters.lock().unwrap().push(PropertyWaiter {
+            interface: interface.to_owned(),
+            path: path.to_owned(),
+            tx,
+        });
+
+        match tokio::time::timeout(timeout, rx).await {
+            Ok(Ok(value)) => Ok(value),
+            Ok(Err(_)) => Err(AstarteError::ReceiveError("property waiter dropped".into())),
+            Err(_) => {
+                // the receiver has been dropped, remove the waiter
+                self.property_waiters
+                    .lock()
+                    .unwrap()
+                    .retain(|waiter| !waiter.tx.is_closed());
+                Err(AstarteError::Timeout)
+            }
+        }
+    }
+
+    /// List the values of the server-owned properties of an interface
+    ///
+    /// Astarte can only be asked for the properties by publishing an emptyCache, which makes it
+    /// forget the device-owned ones, so the values are read from the database, where the
+    /// server-owned properties are stored as soon as they're received. Use
+    /// [`trigger_property_resync`](AstarteSdk::trigger_property_resync) to read them again from
+    /// Astarte. Without a database the map is always empty.
+    pub async fn list_server_properties(
+        &self,
+        interface: &str,
+    ) -> Result<HashMap<String, AstarteType>, AstarteError> {
+        self.check_server_property_interface(interface)?;
+
+        let database = match &self.database {
+            Some(database) => database,
+            None => return Ok(HashMap::new()),
+        };
+
+        let mut properties = HashMap::new();
+        for prop in database.load_all_props().await? {
+            if prop.interface != interface
+                || self.interfaces.get_property_major(interface, &prop.path)
+                    != Some(prop.interface_major)
+            {
+                continue;
+            }
+
+            if let Aggregation::Individual(value) = AstarteSdk::deserialize(&prop.value)? {
+                if value != AstarteType::Unset {
+                    properties.insert(prop.path, value);
+                }
+            }
+        }
+
+        Ok(properties)
+    }
+
+    /// Read again from Astarte the server-owned properties of an interface and update the
+    /// database
+    ///
+    /// An emptyCache is published (see [`publish_empty_cache`](AstarteSdk::publish_empty_cache)),
+    /// which makes Astarte send again all the server-owned properties, and the values are
+    /// collected until none arrives for the timeout set with
+    /// [`set_property_resync_timeout`](builder::AstarteBuilder::set_property_resync_timeout).
+    /// Astarte forgets the device-owned properties on an emptyCache, so they are published again
+    /// right after.
+    ///
+    /// The values are stored as they are received. A stored property that isn't sent again
+    /// before the timeout is kept, since a slow answer can't be told apart from a removed
+    /// property. Another task must be polling the client meanwhile.
+    pub async fn trigger_property_resync(&self, interface: &str) -> Result<(), AstarteError> {
+        let received = self
+            .collect_server_properties(interface, self.property_resync_timeout)
+            .await?;
+
+        let database = match &self.database {
+            Some(database) => database,
+            None => return Ok(()),
+        };
+
+        for prop in database.load_all_props().await? {
+            if prop.interface == interface && !received.contains_key(&prop.path) {
+                warn!(
+                    "{}{} not sent again by Astarte, keeping the stored value",
+                    prop.interface, prop.path
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn collect_server_properties(
+        &self,
+        interface: &str,
+        idle_timeout: Duration,
+    ) -> Result<HashMap<String, AstarteType>, AstarteError> {
+        self.check_server_property_interface(interface)?;
+
+        let server_properties = self.fetch_server_properties(idle_timeout).await?;
+        self.send_device_owned_properties().await?;
+
+        Ok(server_properties
+            .into_iter()
+            .filter(|((prop_interface, _), _)| prop_interface == interface)
+            .map(|((_, path), value)| (path, value))
+            .collect())
+    }
+
+    fn check_server_property_interface(&self, interface: &str) -> Result<(), AstarteError> {
+        let info = self.get_interface_info(interface).ok_or_else(|| {
+            AstarteError::ReceiveError(format!("Interface '{}' does not exists", interface))
+        })?;
+        if !Self::is_server_property(&info) {
+            return Err(AstarteError::ReceiveError(format!(
+                "Interface '{}' is not a server-owned properties interface",
+                interface
+            )));
+        }
+
+        Ok(())
+    }
+
+    fn is_server_property(info: &interface::InterfaceInfo) -> bool {
+        info.interface_type == crate::interface::InterfaceType::Properties
+            && info.ownership == crate::interface::Ownership::Server
+    }
+
+    /// Publish an emptyCache and collect the server-owned properties sent again by Astarte,
+    /// keyed by interface and path
+    async fn fetch_server_properties(
+        &self,
+        idle_timeout: Duration,
+    ) -> Result<HashMap<(String, String), AstarteType>, AstarteError> {
+        let mut events = self.subscribe_all();
+
+        self.publish_empty_cache().await?;
+
+        let mut properties = HashMap::new();
+        while let Ok(Some(event)) = tokio::time::timeout(idle_timeout, events.recv()).await {
+            let is_server_property = self
+                .get_interface_info(&event.interface)
+                .map_or(false, |info| Self::is_server_property(&info));
+            if !is_server_property {
+                continue;
+            }
+
+            if let Aggregation::Individual(value) = event.data {
+                properties.insert((event.interface, event.path), value);
+            }
+        }
+
+        Ok(properties)
+    }
+
+    /// Reconcile the properties stored in the database with the ones on Astarte
+    ///
+    /// The server-owned properties are fetched from Astarte like in
+    /// [`trigger_property_resync`](AstarteSdk::trigger_property_resync) and compared with the
+    /// cached ones, the ones missing from the cache are stored. The conflicts, the cached values
+    /// that differ from Astarte or that Astarte doesn't have anymore, are resolved with the
+    /// [`SyncStrategy`] set with [`set_sync_strategy`](builder::AstarteBuilder::set_sync_strategy):
+    /// - [`SyncStrategy::ServerWins`], the default, takes the values on Astarte and removes the
+    ///   properties it doesn't have from the database;
+    /// - [`SyncStrategy::DeviceWins`] keeps the cached values.
+    ///
+    /// The device-owned properties are then published again, since Astarte forgets them when
+    /// the server-owned ones are fetched.
+    ///
+    /// Another task must be polling the client meanwhile.
+    pub async fn force_property_sync(&self) -> Result<SyncReport, AstarteError> {
+        self.sync_properties(SERVER_PROPERTIES_TIMEOUT).await
+    }
+
+    async fn sync_properties(&self, idle_timeout: Duration) -> Result<SyncReport, AstarteError> {
+        let database = self
+            .database
+            .as_ref()
+            .ok_or_else(|| AstarteError::Reported("no database to sync the properties".into()))?;
+
+        // the received properties are stored by poll, so read the cache before fetching them
+        let cached = database.load_all_props().await?;
+        let mut server_properties = self.fetch_server_properties(idle_timeout).await?;
+
+        let mut report = SyncReport {
+            pushed_to_server: self.send_device_owned_properties().await?,
+            ..SyncReport::default()
+        };
+
+        for prop in cached {
+            if self.interfaces.get_ownership(&prop.interface)
+                != Some(crate::interface::Ownership::Server)
+            {
+                continue;
+            }
+
+            let cached_value = match AstarteSdk::deserialize(&prop.value)? {
+                Aggregation::Individual(value) => value,
+                Aggregation::Object(_) => continue,
+            };
+
+            let server_value =
+                server_properties.remove(&(prop.interface.clone(), prop.path.clone()));
+            if server_value.as_ref() == Some(&cached_value) {
+                continue;
+            }
+
+            report.conflicts += 1;
+
+            // a value on Astarte was already stored when received
+            match (self.sync_strategy, server_value) {
+                (SyncStrategy::ServerWins, Some(_)) => {}
+                (SyncStrategy::ServerWins, None) => {
+                    database.delete_prop(&prop.interface, &prop.path).await?;
+                }
+                (SyncStrategy::DeviceWins, _) => {
+                    database
+                        .store_prop(
+                            &prop.interface,
+                            &prop.path,
+                            &prop.value,
+                            prop.interface_major,
+                        )
+                        .await?;
+                }
+            }
+        }
+
+        // the properties the device didn't have
+        report.updated_from_server += server_properties.len() as u32;
+
+        Ok(report)
+    }
+
+    fn notify_property_waiters(&self, interface: &str, path: &str, value: &AstarteType) {
+        let mut waiters = self.property_waiters.lock().unwrap();
+
+        let (ready, pending): (Vec<_>, Vec<_>) = waiters
+            .drain(..)
+            .partition(|waiter| waiter.interface == interface && waiter.path == path);
+        *waiters = pending;
+
+        for waiter in ready {
+            // the caller may have already timed out
+            let _ = waiter.tx.send(value.clone());
+        }
+    }
+
+    /// Realm the device belongs to
+    pub fn realm(&self) -> &str {
+        &self.realm
+    }
+
+    /// Id of the device in its realm
+    pub fn device_id(&self) -> &str {
+        &self.device_id
+    }
+
+    /// Human-readable name of the device set with
+    /// [`device_alias`](builder::AstarteBuilder::device_alias)
+    pub fn device_alias(&self) -> Option<&str> {
+        self.device_alias.as_deref()
+    }
+
+    /// Url of the Astarte pairing API the device was registered with
+    pub fn pairing_url(&self) -> &str {
+        &self.pairing_url
+    }
+
+    /// Url of the mqtt broker, as returned by the pairing API or set on the builder
+    pub fn broker_url(&self) -> &str {
+        self.build_options.broker_url.as_str()
+    }
+
+    /// Cumulative statistics of the mqtt connection
+    pub fn connection_stats(&self) -> ConnectionStats {
+        self.stats.get()
+    }
+
+    /// Zero the statistics returned by [`connection_stats`](AstarteSdk::connection_stats)
+    ///
+    /// The reset is not synchronized with the event loop, so the counts may still include a few
+    /// messages that were being sent or received while resetting.
+    pub fn statistics_reset(&self) {
+        self.stats.reset();
+    }
+
+    /// Log a summary of the client state, e.g. as a periodic heartbeat
+    pub fn log_diagnostics(&self) {
+        info!("{}", self.diagnostics());
+    }
+
+    fn diagnostics(&self) -> String {
+        let device_id: String = self.device_id.chars().take(8).collect();
+        let alias = self
+            .device_alias
+            .as_ref()
+            .map(|alias| format!(" alias={}", alias))
+            .unwrap_or_default();
+
+        format!(
+            "astarte sdk {}: realm={} device_id={}...{} interfaces={} connected={} database={} uptime={:?}",
+            env!("CARGO_PKG_VERSION"),
+            self.realm,
+            device_id,
+            alias,
+            self.registered_interface_count(),
+            self.stats.is_connected(),
+            self.database.is_some(),
+            self.created_at.elapsed()
+        )
+    }
+
+    /// Change the log verbosity of the SDK at runtime
+    ///
+    /// Only the messages of the SDK are filtered, the level is shared by all the clients in the
+    /// process. The messages still go through the `log` crate, so its global maximum level and
+    /// the installed logger can filter them out too.
+    pub fn set_log_level(&self, level: log::LevelFilter) {
+        logging::set_max_level(level);
+    }
+
+    /// Topic of `path` in `interface`, every topic is built here to apply the prefix
+    fn build_topic(&self, interface: &str, path: &str) -> String {
+        format!(
+            "{}/{}{}",
+            self.topic_prefix,
+            interface.trim_matches('/'),
+            path
+        )
+    }
+
+    /// Publish a message, all the publishes must go through here to keep track of the acknowledgements
+    ///
+    /// If `await_ack` is set, QoS 1 and 2 messages return only after the broker acknowledged them.
+    async fn publish<V>(
+        &self,
+        topic: String,
+        qos: rumqttc::QoS,
+        payload: V,
+        await_ack: bool,
     ) -> Result<(), AstarteError>
     where
-        D: Into<AstarteType>,
+        V: Into<Vec<u8>>,
     {
-        self.send_with_timestamp_impl(interface_name, interface_path, data, None)
-            .await
+        self.publish_impl(
+            topic,
+            qos,
+            false,
+            payload.into(),
+            await_ack,
+            self.dead_letter_queue,
+        )
+        .await
+        .map(|_| ())
     }
 
-    /// Send data to an astarte interface, with timestamp
-    /// ```no_run
-    /// #[tokio::main]
-    /// async fn main() {
-    ///     use chrono::Utc;
-    ///     use chrono::TimeZone;
-    ///     let mut sdk_options = astarte_sdk::builder::AstarteBuilder::new("_","_","_","_");
-    ///     sdk_options.build().await.unwrap();
-    ///     let d = sdk_options.connect().await.unwrap();
+    /// Publish a value on `interface_path`, the QoS and the retain flag are taken from its mapping
     ///
-    ///     d.send_with_timestamp("com.test.interface", "/data", 45, Utc.timestamp(1537449422, 0) ).await.unwrap();
-    /// }
-    /// ```
-    pub async fn send_with_timestamp<D>(
+    /// Returns once the message is handed to the event loop, the broker acknowledgement can then
+    /// be awaited with [`HandedOver::acknowledged`].
+    async fn publish_on_mapping(
         &self,
         interface_name: &str,
         interface_path: &str,
-        data: D,
-        timestamp: chrono::DateTime<chrono::Utc>,
-    ) -> Result<(), AstarteError>
-    where
-        D: Into<AstarteType>,
-    {
-        self.send_with_timestamp_impl(interface_name, interface_path, data, Some(timestamp))
+        payload: Vec<u8>,
+    ) -> Result<HandedOver, AstarteError> {
+        self.hand_over(
+            self.build_topic(interface_name, interface_path),
+            self.interfaces
+                .get_mqtt_reliability(interface_name, interface_path),
+            self.interfaces
+                .get_mqtt_retain(interface_name, interface_path),
+            payload,
+            self.await_puback,
+            self.dead_letter_queue,
+        )
+        .await
+    }
+
+    /// Publish a message, if `queue_undeliverable` the messages that can't be handed to the event
+    /// loop are stored in the dead letter queue
+    ///
+    /// Returns the packet id acknowledged by the broker, 0 if the ack is not awaited.
+    async fn publish_impl(
+        &self,
+        topic: String,
+        qos: rumqttc::QoS,
+        retain: bool,
+        payload: Vec<u8>,
+        await_ack: bool,
+        queue_undeliverable: bool,
+    ) -> Result<u16, AstarteError> {
+        self.hand_over(topic, qos, retain, payload, await_ack, queue_undeliverable)
+            .await?
+            .acknowledged()
             .await
     }
 
-    async fn send_with_timestamp_impl<D>(
+    /// Hand a message to the event loop, like [`publish_impl`](AstarteSdk::publish_impl) without
+    /// waiting for the broker acknowledgement
+    async fn hand_over(
         &self,
-        interface_name: &str,
-        interface_path: &str,
-        data: D,
-        timestamp: Option<chrono::DateTime<chrono::Utc>>,
-    ) -> Result<(), AstarteError>
-    where
-        D: Into<AstarteType>,
-    {
-        debug!("sending {} {}", interface_name, interface_path);
+        topic: String,
+        qos: rumqttc::QoS,
+        retain: bool,
+        payload: Vec<u8>,
+        await_ack: bool,
+        queue_undeliverable: bool,
+    ) -> Result<HandedOver, AstarteError> {
+        let bytes = payload.len();
+        if bytes > self.max_payload_size {
+            return Err(AstarteError::PayloadTooLarge {
+                size: bytes,
+                limit: self.max_payload_size,
+            });
+        }
+
+        if !*self.connection_enabled_rx.borrow() {
+            let err = AstarteError::SendError("client is disconnected".into());
+            if queue_undeliverable {
+                return self
+                    .queue_dead_letter(&topic, qos, retain, &payload, err)
+                    .await
+                    .map(|()| HandedOver::Done);
+            }
+
+            return Err(err);
+        }
+
+        let await_ack = await_ack && qos != rumqttc::QoS::AtMostOnce;
+        let (tx, rx) = oneshot::channel();
+        // the introspection is published on the prefix itself
+        let parsed_topic = parse_topic(&self.topic_prefix, &topic);
+
+        {
+            // the publishes must be queued in the same order they are sent to the event loop
+            let _order = self.publish_lock.lock().await;
+
+            // removed from the tracker if the send is cancelled before the publish is handed over
+            let queued = QueuedPublish::new(&self.pubacks, if await_ack { Some(tx) } else { None });
+
+            if let Err(err) = self.client.publish(topic, qos, retain, payload).await {
+                drop(queued);
+
+                // the event loop is gone, the request is returned with the error
+                let undelivered = match &err {
+                    rumqttc::ClientError::Request(async_channel::SendError(
+                        rumqttc::Request::Publish(publish),
+                    )) if queue_undeliverable => Some(publish.clone()),
+                    _ => None,
+                };
+
+                let err = AstarteError::from(err);
+                self.observability_hook.on_error(&err);
+
+                return match undelivered {
+                    Some(publish) => self
+                        .queue_dead_letter(&publish.topic, qos, retain, &publish.payload, err)
+                        .await
+                        .map(|()| HandedOver::Done),
+                    None => Err(err),
+                };
+            }
+
+            queued.sent();
+        }
+
+        self.stats.published(bytes);
+        if let Some((interface, path)) = parsed_topic {
+            self.observability_hook.on_publish(&interface, &path, bytes);
+        }
+
+        if !await_ack {
+            return Ok(HandedOver::Done);
+        }
+
+        Ok(HandedOver::Pending(rx))
+    }
+
+    /// Store a message in the dead letter queue, returns `err` if there is no database
+    async fn queue_dead_letter(
+        &self,
+        topic: &str,
+        qos: rumqttc::QoS,
+        retain: bool,
+        payload: &[u8],
+        err: AstarteError,
+    ) -> Result<(), AstarteError> {
+        let database = match &self.database {
+            Some(database) => database,
+            None => return Err(err),
+        };
+
+        warn!(
+            "couldn't publish on {}, queued to send later: {}",
+            topic, err
+        );
+        database
+            .store_dead_letter(topic, qos as u8, retain, payload)
+            .await
+    }
+
+    /// Publish the messages in the dead letter queue, returns the number of messages sent
+    ///
+    /// The messages are sent oldest first, without waiting for the broker acknowledgement.
+    /// Sending stops at the first message that still can't be published, which is kept in the
+    /// queue with the following ones. The queue is also drained in the background every time
+    /// the client connects. Another task must be polling the client meanwhile, since the
+    /// messages are handed to the event loop.
+    pub async fn drain_dead_letter_queue(&self) -> Result<u64, AstarteError> {
+        let database = match &self.database {
+            Some(database) => database,
+            None => return Ok(0),
+        };
+
+        // concurrent drains would send the same messages twice
+        let _draining = self.dead_letter_lock.lock().await;
+
+        let mut sent = 0;
+        for letter in database.load_dead_letters().await? {
+            let qos = match letter.qos {
+                0 => rumqttc::QoS::AtMostOnce,
+                1 => rumqttc::QoS::AtLeastOnce,
+                _ => rumqttc::QoS::ExactlyOnce,
+            };
+
+            self.publish_impl(
+                letter.topic,
+                qos,
+                letter.retain,
+                letter.payload,
+                false,
+                false,
+            )
+            .await?;
+            database.delete_dead_letter(letter.id).await?;
+            sent += 1;
+        }
+
+        if sent > 0 {
+            debug!("sent {} messages from the dead letter queue", sent);
+        }
+
+        Ok(sent)
+    }
+
+    /// Drain the dead letter queue from another task, retrying with backoff while it fails
+    ///
+    /// Publishing waits for room in the request channel, which only [`poll`](AstarteSdk::poll)
+    /// empties, so poll can't drain the queue itself.
+    fn spawn_dead_letter_drain(&self) {
+        let device = self.clone();
+
+        tokio::spawn(async move {
+            let (min, max) = DEAD_LETTER_BACKOFF;
+            let backoff = backoff::Backoff::new(min, max);
+
+            for _ in 0..DEAD_LETTER_ATTEMPTS {
+                if let Some(delay) = backoff.next_delay() {
+                    tokio::time::sleep(delay).await;
+                }
+
+                match device.drain_dead_letter_queue().await {
+                    Ok(_) => return,
+                    Err(err) => {
+                        warn!("couldn't drain the dead letter queue: {}", err);
+                        backoff.failed();
+                    }
+                }
+            }
+        });
+    }
+
+    fn empty_cache_topic(&self) -> String {
+        self.build_topic("control", "/emptyCache")
+    }
+
+    /// Tell Astarte that the device has no cached properties
+    ///
+    /// Astarte then sends again all the server-owned properties and forgets the device-owned
+    /// ones, until the device publishes them again. This is done automatically on every new
+    /// session if no property is stored in the database.
+    pub async fn publish_empty_cache(&self) -> Result<(), AstarteError> {
+        let url = self.empty_cache_topic();
+        debug!("sending emptyCache to {}", url);
+
+        self.publish(url, rumqttc::QoS::ExactlyOnce, "1", false)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn property_cache_is_empty(&self) -> Result<bool, AstarteError> {
+        match &self.database {
+            Some(database) => Ok(database.load_all_props().await?.is_empty()),
+            None => Ok(true),
+        }
+    }
+
+    /// Publish the introspection, the list of interfaces supported by the device
+    ///
+    /// It's published on every new session, call this to force Astarte to sync it again.
+    pub async fn publish_introspection(&self) -> Result<(), AstarteError> {
+        let introspection = self.interfaces.get_introspection_string();
+
+        debug!("sending introspection = {}", introspection);
+
+        self.publish(
+            self.topic_prefix.clone(),
+            rumqttc::QoS::ExactlyOnce,
+            introspection.clone(),
+            false,
+        )
+        .await?;
+        Ok(())
+    }
+
+    /// Publish again the device-owned properties of an interface stored in the database
+    ///
+    /// Useful when only the properties of an interface need to be synced, e.g. after it has
+    /// been added again to the introspection.
+    pub async fn publish_device_owned_properties_for_interface(
+        &self,
+        interface: &str,
+    ) -> Result<(), AstarteError> {
+        if self.interfaces.get_ownership(interface) != Some(crate::interface::Ownership::Device) {
+            return Err(AstarteError::SendError(format!(
+                "Interface '{}' is not a device-owned interface",
+                interface
+            )));
+        }
+
+        self.send_device_owned_properties_of(Some(interface))
+            .await?;
+
+        Ok(())
+    }
+
+    /// Publish the device-owned properties stored in the database, returns how many were sent
+    async fn send_device_owned_properties(&self) -> Result<u32, AstarteError> {
+        self.send_device_owned_properties_of(None).await
+    }
+
+    /// Publish the stored device-owned properties, of a single interface if given
+    async fn send_device_owned_properties_of(
+        &self,
+        interface: Option<&str>,
+    ) -> Result<u32, AstarteError> {
+        let mut sent = 0;
+
+        if let Some(database) = &self.database {
+            let properties = database.load_all_props().await?;
+            // publish only device-owned properties...
+            let device_owned_properties: Vec<StoredProp> = properties
+                .into_iter()
+                .filter(|prop| {
+                    self.interfaces.get_ownership(&prop.interface)
+                        == Some(crate::interface::Ownership::Device)
+                        && interface.map_or(true, |interface| prop.interface == interface)
+                })
+                .collect();
+            for prop in device_owned_properties {
+                let topic = self.build_topic(&prop.interface, &prop.path);
+                if let Some(version_major) = self
+                    .interfaces
+                    .get_property_major(&prop.interface, &prop.path)
+                {
+                    // ..and only if they are up-to-date
+                    if version_major == prop.interface_major {
+                        debug!(
+                            "sending device-owned property = {}{}",
+                            prop.interface, prop.path
+                        );
+                        self.publish(
+                            topic,
+                            rumqttc::QoS::ExactlyOnce,
+                            self.encode_payload(prop.value, None)?,
+                            false,
+                        )
+                        .await?;
+                        sent += 1;
+                    }
+                }
+            }
+        }
+
+        Ok(sent)
+    }
+
+    /// unset a device property
+    ///
+    /// A pending expiry set with [`set_property_expiry`](AstarteSdk::set_property_expiry) is
+    /// cancelled.
+    pub async fn unset<D>(
+        &self,
+        interface_name: &str,
+        interface_path: &str,
+    ) -> Result<(), AstarteError>
+    where
+        D: Into<AstarteType>,
+    {
+        let expiry = self
+            .property_expiries
+            .lock()
+            .unwrap()
+            .remove(&(interface_name.to_owned(), interface_path.to_owned()));
+        if let Some(expiry) = expiry {
+            expiry.task.abort();
+        }
+
+        self.unset_impl(interface_name, interface_path).await
+    }
+
+    async fn unset_impl(
+        &self,
+        interface_name: &str,
+        interface_path: &str,
+    ) -> Result<(), AstarteError> {
+        trace!("unsetting {} {}", interface_name, interface_path);
+
+        if cfg!(debug_assertions) {
+            self.interfaces
+                .validate_send(interface_name, interface_path, &[], &None)?;
+        }
+
+        self.send_with_timestamp_impl(interface_name, interface_path, AstarteType::Unset, None)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Whether the mapping is a property that can be unset
+    fn allows_unset(&self, interface_name: &str, interface_path: &str) -> bool {
+        self.interfaces
+            .with_mapping(interface_name, interface_path, |mapping| match mapping {
+                interface::Mapping::Properties(mapping) => mapping.allow_unset,
+                interface::Mapping::Datastream(_) => false,
+            })
+            .unwrap_or(false)
+    }
+
+    /// Unset a device property once `ttl` has elapsed
+    ///
+    /// Calling it again for the same property restarts the timer, while
+    /// [`unset`](AstarteSdk::unset) cancels it. The timer runs in a tokio task spawned by the
+    /// client, so it must be called from within a tokio runtime. The mapping must allow unsetting
+    /// its values.
+    pub async fn set_property_expiry(
+        &self,
+        interface_name: &str,
+        interface_path: &str,
+        ttl: Duration,
+    ) -> Result<(), AstarteError> {
+        if self.interfaces.get_ownership(interface_name) != Some(interface::Ownership::Device) {
+            return Err(AstarteError::SendError(format!(
+                "Interface '{}' is not a device-owned interface",
+                interface_name
+            )));
+        }
+
+        if !self.allows_unset(interface_name, interface_path) {
+            return Err(AstarteError::SendError(format!(
+                "{}{} is not a property that can be unset",
+                interface_name, interface_path
+            )));
+        }
+
+        let key = (interface_name.to_owned(), interface_path.to_owned());
+        let token = Arc::new(());
+
+        let device = self.clone();
+        let task_key = key.clone();
+        let task_token = Arc::clone(&token);
+        let task = tokio::spawn(async move {
+            tokio::time::sleep(ttl).await;
+
+            {
+                let mut expiries = device.property_expiries.lock().unwrap();
+                match expiries.get(&task_key) {
+                    Some(expiry) if Arc::ptr_eq(&expiry.token, &task_token) => {
+                        expiries.remove(&task_key);
+                    }
+                    // the timer has been restarted or cancelled
+                    _ => return,
+                }
+            }
+
+            let (interface_name, interface_path) = task_key;
+            debug!("property {}{} expired", interface_name, interface_path);
+            if let Err(err) = device.unset_impl(&interface_name, &interface_path).await {
+                warn!(
+                    "couldn't unset expired property {}{}: {}",
+                    interface_name, interface_path, err
+                );
+            }
+        });
+
+        let old = self
+            .property_expiries
+            .lock()
+            .unwrap()
+            .insert(key, PropertyExpiry { token, task });
+        if let Some(old) = old {
+            old.task.abort();
+        }
+
+        Ok(())
+    }
+
+    /// Serialize data directly from Bson
+    fn serialize(
+        data: Bson,
+        timestamp: Option<chrono::DateTime<chrono::Utc>>,
+        capacity: usize,
+    ) -> Result<Vec<u8>, AstarteError> {
+        if let Bson::Null = data {
+            return Ok(Vec::new());
+        }
+
+        let doc = if let Some(timestamp) = timestamp {
+            bson::doc! {
+               "t": timestamp,
+               "v": data
+            }
+        } else {
+            bson::doc! {
+               "v": data,
+            }
+        };
+
+        let mut buf = Vec::with_capacity(capacity);
+        doc.to_writer(&mut buf)?;
+        trace!("serialized {:#?}", doc);
+        Ok(buf)
+    }
+
+    /// Explicit timestamp of a bson payload, if any
+    fn deserialize_timestamp(bdata: &[u8]) -> Option<chrono::DateTime<chrono::Utc>> {
+        let document = bson::Document::from_reader(&mut std::io::Cursor::new(bdata)).ok()?;
+
+        document
+            .get_datetime("t")
+            .ok()
+            .map(|timestamp| timestamp.to_chrono())
+    }
+
+    fn deserialize(bdata: &[u8]) -> Result<Aggregation, AstarteError> {
+        if bdata.is_empty() {
+            return Ok(Aggregation::Individual(AstarteType::Unset));
+        }
+        if let Ok(deserialized) = bson::Document::from_reader(&mut std::io::Cursor::new(bdata)) {
+            trace!("{:?}", deserialized);
+            if let Some(v) = deserialized.get("v") {
+                if let Bson::Document(doc) = v {
+                    let strings = doc.iter().map(|f| f.0.clone());
+
+                    let data = doc.iter().map(|f| f.1.clone().try_into());
+                    let data: Result<Vec<AstarteType>, AstarteError> = data.collect();
+                    let data = data?;
+
+                    let hmap: HashMap<String, AstarteType> = strings.zip(data).collect();
+
+                    Ok(Aggregation::Object(hmap))
+                } else if let Ok(v) = v.clone().try_into() {
+                    Ok(Aggregation::Individual(v))
+                } else {
+                    Err(AstarteError::DeserializationError)
+                }
+            } else {
+                Err(AstarteError::DeserializationError)
+            }
+        } else {
+            Err(AstarteError::DeserializationError)
+        }
+    }
+
+    /// get property from database, if present
+    pub async fn get_property(
+        &self,
+        interface: &str,
+        path: &str,
+    ) -> Result<Option<AstarteType>, AstarteError> {
+        if let Some(database) = &self.database {
+            if let Some(major) = self.interfaces.get_property_major(interface, path) {
+                let prop = database.load_prop(interface, path, major).await?;
+                return Ok(prop);
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Remove all the properties stored in the database and unset the device-owned ones
+    ///
+    /// An unset is published for every device-owned property that was stored and whose mapping
+    /// allows it, so that Astarte doesn't keep values the device has forgotten. Each property is
+    /// removed once its unset is sent, the others at the end.
+    pub async fn clear_property_cache(&self) -> Result<(), AstarteError> {
+        let database = match &self.database {
+            Some(database) => database,
+            None => return Ok(()),
+        };
+
+        self.unset_stored_device_properties(database).await?;
+        database.clear().await?;
+
+        Ok(())
+    }
+
+    /// Unset all the device-owned properties stored in the database, e.g. before a planned
+    /// shutdown
+    ///
+    /// An unset is published for each property whose mapping allows it, which is then deleted
+    /// from the database. The properties that can't be unset are kept, like the server-owned
+    /// ones, unlike [`clear_property_cache`](AstarteSdk::clear_property_cache).
+    pub async fn unset_all_device_properties(&self) -> Result<(), AstarteError> {
+        match &self.database {
+            Some(database) => self.unset_stored_device_properties(database).await,
+            None => Ok(()),
+        }
+    }
+
+    /// Unset the stored device-owned properties whose mapping allows it, deleting each one once
+    /// its unset is sent
+    async fn unset_stored_device_properties(
+        &self,
+        database: &(dyn AstarteDatabase + Sync + Send),
+    ) -> Result<(), AstarteError> {
+        for prop in database.load_all_props().await? {
+            if self.interfaces.get_ownership(&prop.interface)
+                != Some(crate::interface::Ownership::Device)
+            {
+                continue;
+            }
+
+            if !self.allows_unset(&prop.interface, &prop.path) {
+                debug!(
+                    "{}{} can't be unset, skipping it",
+                    prop.interface, prop.path
+                );
+                continue;
+            }
+
+            debug!(
+                "unsetting device-owned property = {}{}",
+                prop.interface, prop.path
+            );
+
+            self.unset::<AstarteType>(&prop.interface, &prop.path)
+                .await?;
+            database.delete_prop(&prop.interface, &prop.path).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Get the mapping of a registered interface matching `path`, parametric endpoints included
+    ///
+    /// The returned [`MappingRef`](interface::MappingRef) keeps the interface alive, call
+    /// [`mapping`](interface::MappingRef::mapping) on it to borrow the mapping.
+    pub fn get_mapping(&self, interface_name: &str, path: &str) -> Option<interface::MappingRef> {
+        self.interfaces.get_mapping(interface_name, path)
+    }
+
+    /// Check if `path` matches a mapping of a registered interface, parametric endpoints included
+    pub fn interface_owns_path(&self, interface_name: &str, path: &str) -> bool {
+        self.interfaces
+            .with_mapping(interface_name, path, |_| ())
+            .is_some()
+    }
+
+    /// Number of interfaces registered in the device introspection
+    pub fn registered_interface_count(&self) -> usize {
+        self.interfaces.count()
+    }
+
+    /// Interfaces registered in the device introspection, sorted by name
+    pub fn registered_interfaces(&self) -> impl Iterator<Item = Arc<Interface>> {
+        self.interfaces
+            .all()
+            .into_iter()
+            .sorted_by(|a, b| a.name().cmp(b.name()))
+    }
+
+    /// Check if a new version of an interface can replace the registered one
+    ///
+    /// Changing the major version, removing a mapping or changing its type are breaking
+    /// changes. The stored properties are affected when their major version differs from the new
+    /// one, or their path or type doesn't match any mapping of the new interface.
+    pub async fn check_interface_compatibility(
+        &self,
+        new_json: &str,
+    ) -> Result<CompatibilityReport, AstarteError> {
+        use crate::interface::traits::{Interface as InterfaceTrait, Mapping as MappingTrait};
+
+        let new: Interface = new_json.parse()?;
+        let name = new.name();
+        let (new_major, _) = new.version();
+
+        let breaking_changes = self
+            .interfaces
+            .get(name)
+            .map(|registered| registered.breaking_changes(&new))
+            .unwrap_or_default();
+
+        let mut stored_props_affected = 0;
+        if let Some(database) = &self.database {
+            for prop in database.load_all_props().await? {
+                // unset properties hold no value to migrate
+                if prop.interface != name || prop.value.is_empty() {
+                    continue;
+                }
+
+                let mapping_type = new.mapping(&prop.path).map(|m| m.mapping_type());
+                // rows that can't be decoded are reported instead of failing the whole check
+                let valid = prop.interface_major == new_major
+                    && match (mapping_type, AstarteSdk::deserialize(&prop.value)) {
+                        (Some(mapping_type), Ok(Aggregation::Individual(value))) => {
+                            value == mapping_type
+                        }
+                        _ => false,
+                    };
+
+                if !valid {
+                    stored_props_affected += 1;
+                }
+            }
+        }
+
+        Ok(CompatibilityReport {
+            compatible: breaking_changes.is_empty() && stored_props_affected == 0,
+            stored_props_affected,
+            breaking_changes,
+        })
+    }
+
+    /// Replace a registered interface with a newer minor version of it
+    ///
+    /// The new version must have the same name and major version as the registered one, a
+    /// greater minor version, and mustn't remove or change the type of the registered mappings.
+    /// The interface is replaced for all the clones of the client, and the introspection is
+    /// published again.
+    pub async fn apply_interface_patch(&self, new_json: &str) -> Result<(), AstarteError> {
+        use crate::interface::traits::Interface as InterfaceTrait;
+
+        let new: Interface = new_json.parse()?;
+        let name = new.name().to_owned();
+        let registered = self.interfaces.get(&name).ok_or_else(|| {
+            AstarteError::Reported(format!("Interface '{}' does not exists", name))
+        })?;
+
+        let (major, minor) = registered.version();
+        let (new_major, new_minor) = new.version();
+        if new_major != major {
+            return Err(AstarteError::InterfaceMajorChanged {
+                name,
+                registered: major,
+                new: new_major,
+            });
+        }
+        if new_minor <= minor {
+            return Err(AstarteError::InterfaceVersionDowngrade {
+                name,
+                registered: minor,
+                new: new_minor,
+            });
+        }
+
+        let breaking_changes = registered.breaking_changes(&new);
+        if !breaking_changes.is_empty() {
+            return Err(AstarteError::Reported(format!(
+                "Interface '{}' has breaking changes: {}",
+                name,
+                breaking_changes.join(", ")
+            )));
+        }
+
+        self.interfaces.replace(new);
+        info!("patched {} to version {}.{}", name, new_major, new_minor);
+
+        self.publish_introspection().await
+    }
+
+    /// Stop processing the data of an interface, without removing it from the introspection
+    ///
+    /// The data received on the interface is dropped, and sending fails with
+    /// [`AstarteError::InterfaceDisabled`], until [`enable_interface`](AstarteSdk::enable_interface)
+    /// is called. The state is shared by all the clones of the client.
+    pub fn disable_interface(&self, name: &str) -> Result<(), AstarteError> {
+        self.interfaces.set_enabled(name, false)
+    }
+
+    /// Process again the data of an interface disabled with
+    /// [`disable_interface`](AstarteSdk::disable_interface)
+    pub fn enable_interface(&self, name: &str) -> Result<(), AstarteError> {
+        self.interfaces.set_enabled(name, true)
+    }
+
+    /// Checks that the device can send data on an interface
+    fn check_can_send(&self, interface_name: &str) -> Result<(), AstarteError> {
+        if !self.interfaces.is_enabled(interface_name) {
+            return Err(AstarteError::InterfaceDisabled(interface_name.to_owned()));
+        }
+
+        if self.interface_ownership(interface_name) == Some(interface::Ownership::Server) {
+            return Err(AstarteError::SendError(format!(
+                "Interface '{}' is server-owned",
+                interface_name
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Get the type of a registered interface, properties or datastream
+    pub fn interface_type(&self, name: &str) -> Option<interface::InterfaceType> {
+        self.interfaces
+            .get(name)
+            .map(|interface| interface.interface_type())
+    }
+
+    /// Get the aggregation of a registered interface, individual or object
+    pub fn interface_aggregation(&self, name: &str) -> Option<interface::Aggregation> {
+        self.interfaces
+            .get(name)
+            .map(|interface| interface.aggregation())
+    }
+
+    /// Major version to store a property with, `None` if the path is not a property and
+    /// mustn't be stored
+    fn stored_property_major(&self, interface_name: &str, interface_path: &str) -> Option<i32> {
+        if self.interface_type(interface_name) != Some(interface::InterfaceType::Properties) {
+            return None;
+        }
+
+        self.interfaces
+            .get_property_major(interface_name, interface_path)
+    }
+
+    /// Get the ownership of a registered interface
+    pub fn interface_ownership(&self, name: &str) -> Option<interface::Ownership> {
+        self.interfaces.get_ownership(name)
+    }
+
+    /// Get the major and minor version of a registered interface
+    pub fn interface_version(&self, name: &str) -> Option<(u32, u32)> {
+        let (major, minor) = self.interfaces.get_version(name)?;
+
+        Some((major.try_into().ok()?, minor.try_into().ok()?))
+    }
+
+    /// Get the metadata of a registered interface
+    pub fn get_interface_info(&self, interface_name: &str) -> Option<interface::InterfaceInfo> {
+        self.interfaces.get_interface_info(interface_name)
+    }
+
+    /// Take a copy of all the properties stored in the database
+    ///
+    /// The properties are read with a single query, so the snapshot is consistent even if they are
+    /// being updated at the same time. The snapshot is empty if no database is set.
+    pub async fn snapshot(&self) -> Result<PropertySnapshot, AstarteError> {
+        let timestamp = chrono::Utc::now();
+
+        let props = match &self.database {
+            Some(database) => database.load_all_props().await?,
+            None => Vec::new(),
+        };
+
+        let properties = props
+            .into_iter()
+            .map(PropertyInfo::try_from)
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(PropertySnapshot {
+            timestamp,
+            properties,
+        })
+    }
+
+    /// Export all the properties stored in the database
+    ///
+    /// The returned bytes can be loaded with [`import_state`](AstarteSdk::import_state),
+    /// e.g. when the device process is moved to another host.
+    pub async fn export_state(&self) -> Result<Vec<u8>, AstarteError> {
+        let props = match &self.database {
+            Some(database) => database.load_all_props().await?,
+            None => Vec::new(),
+        };
+
+        let props: Vec<Bson> = props.into_iter().map(StoredProp::into_bson).collect();
+
+        let mut buf = Vec::new();
+        bson::doc! { "props": props }.to_writer(&mut buf)?;
+        Ok(buf)
+    }
+
+    /// Store in the database the properties exported with [`export_state`](AstarteSdk::export_state)
+    ///
+    /// Properties of interfaces that are not registered in this client are skipped.
+    pub async fn import_state(&self, data: &[u8]) -> Result<(), AstarteError> {
+        let database = self
+            .database
+            .as_ref()
+            .ok_or_else(|| AstarteError::Reported("no database to import the state into".into()))?;
+
+        let doc = bson::Document::from_reader(&mut std::io::Cursor::new(data))
+            .map_err(|_| AstarteError::DeserializationError)?;
+        let props = doc
+            .get_array("props")
+            .map_err(|_| AstarteError::DeserializationError)?;
+
+        for prop in props {
+            let prop = StoredProp::from_bson(prop).ok_or(AstarteError::DeserializationError)?;
+
+            if !self.interfaces.contains(&prop.interface) {
+                warn!(
+                    "skipping import of {}{}, interface is not registered",
+                    prop.interface, prop.path
+                );
+                continue;
+            }
+
+            database
+                .store_prop(
+                    &prop.interface,
+                    &prop.path,
+                    &prop.value,
+                    prop.interface_major,
+                )
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    // ------------------------------------------------------------------------
+    // individual types
+    // ------------------------------------------------------------------------
+
+    /// Send data to an astarte interface
+    /// ```no_run
+    /// #[tokio::main]
+    /// async fn main() {
+    ///     let mut sdk_options = astarte_sdk::builder::AstarteBuilder::new("_","_","_","_");
+    ///     sdk_options.build().await.unwrap();
+    ///     let d = sdk_options.connect().await.unwrap();
+    ///
+    ///     d.send("com.test.interface", "/data", 45).await.unwrap();
+    /// }
+    /// ```
+
+    pub async fn send<D>(
+        &self,
+        interface_name: &str,
+        interface_path: &str,
+        data: D,
+    ) -> Result<(), AstarteError>
+    where
+        D: Into<AstarteType>,
+    {
+        self.send_with_timestamp_impl(interface_name, interface_path, data, None)
+            .await
+    }
+
+    /// Send data to an astarte interface, with timestamp
+    /// ```no_run
+    /// #[tokio::main]
+    /// async fn main() {
+    ///     use chrono::Utc;
+    ///     use chrono::TimeZone;
+    ///     let mut sdk_options = astarte_sdk::builder::AstarteBuilder::new("_","_","_","_");
+    ///     sdk_options.build().await.unwrap();
+    ///     let d = sdk_options.connect().await.unwrap();
+    ///
+    ///     d.send_with_timestamp("com.test.interface", "/data", 45, Utc.timestamp(1537449422, 0) ).await.unwrap();
+    /// }
+    /// ```
+    pub async fn send_with_timestamp<D>(
+        &self,
+        interface_name: &str,
+        interface_path: &str,
+        data: D,
+        timestamp: chrono::DateTime<chrono::Utc>,
+    ) -> Result<(), AstarteError>
+    where
+        D: Into<AstarteType>,
+    {
+        self.send_with_timestamp_impl(interface_name, interface_path, data, Some(timestamp))
+            .await
+    }
+
+    /// Send data to an astarte interface, with an optional timestamp
+    ///
+    /// Any value convertible to [`AstarteType`] can be sent, e.g. `42` or `"hello"`. Floating
+    /// point numbers must be converted with `AstarteType::try_from`, since NaN and infinity
+    /// can't be sent. The returned [`MessageReceipt`] tells the size of the payload and when it
+    /// was published.
+    ///
+    /// The MQTT QoS is the one of the mapping reliability: 0 for `unreliable`, 1 for
+    /// `guaranteed` and 2 for `unique`.
+    ///
+    /// The MQTT retain flag is set only for the mappings with the `stored` retention. The two
+    /// are not the same thing: the Astarte retention tells how long the device keeps the data it
+    /// could not send yet, the retain flag makes the broker keep the last value published on the
+    /// topic.
+    /// ```no_run
+    /// #[tokio::main]
+    /// async fn main() {
+    ///     let mut sdk_options = astarte_sdk::builder::AstarteBuilder::new("_","_","_","_");
+    ///     sdk_options.build().await.unwrap();
+    ///     let d = sdk_options.connect().await.unwrap();
+    ///
+    ///     d.send_individual("com.test.interface", "/data", 45, None).await.unwrap();
+    /// }
+    /// ```
+    pub async fn send_individual<D>(
+        &self,
+        interface_name: &str,
+        interface_path: &str,
+        data: D,
+        timestamp: Option<chrono::DateTime<chrono::Utc>>,
+    ) -> Result<MessageReceipt, AstarteError>
+    where
+        D: Into<AstarteType>,
+    {
+        self.send_with_receipt(interface_name, interface_path, data, timestamp)
+            .await
+    }
+
+    /// Send data to an astarte interface, failing with [`AstarteError::PublishTimeout`] if it
+    /// takes longer than `timeout`
+    ///
+    /// The time includes waiting for a free publish slot and, with
+    /// [`await_puback`](builder::AstarteBuilder::await_puback), for the broker acknowledgement.
+    /// A message that was already handed to the event loop may still be delivered after the
+    /// timeout.
+    pub async fn send_individual_timeout(
+        &self,
+        interface_name: &str,
+        interface_path: &str,
+        data: AstarteType,
+        timestamp: Option<chrono::DateTime<chrono::Utc>>,
+        timeout: Duration,
+    ) -> Result<(), AstarteError> {
+        tokio::time::timeout(
+            timeout,
+            self.send_with_timestamp_impl(interface_name, interface_path, data, timestamp),
+        )
+        .await
+        .map_err(|_| AstarteError::PublishTimeout)?
+    }
+
+    /// Send data to an astarte interface, making sure the value matches the mapping type
+    ///
+    /// Returns [`AstarteError::TypeMismatch`] if the type of `data` is not the one declared by the
+    /// mapping. All the other send methods perform the same check.
+    pub async fn send_individual_checked(
+        &self,
+        interface_name: &str,
+        interface_path: &str,
+        data: AstarteType,
+    ) -> Result<(), AstarteError> {
+        self.send_with_timestamp_impl(interface_name, interface_path, data, None)
+            .await
+    }
+
+    /// Send data to an astarte interface without any validation
+    ///
+    /// Neither the interface, the path nor the type of `value` are checked, and properties are
+    /// not stored in the database. Sending to a wrong interface or path makes the broker reject
+    /// the message and disconnect the device, sending the wrong type may break the device state
+    /// on Astarte. Only meant for hot paths where the values are known to be valid.
+    #[doc(hidden)]
+    pub async fn send_individual_no_validate(
+        &self,
+        interface_name: &str,
+        interface_path: &str,
+        value: AstarteType,
+    ) -> Result<(), AstarteError> {
+        let permit = self.publish_permit().await?;
+
+        let buf = self.encode_payload(AstarteSdk::serialize_individual(value, None)?, None)?;
+
+        let handed_over = self
+            .publish_on_mapping(interface_name, interface_path, buf)
+            .await?;
+        drop(permit);
+
+        handed_over.acknowledged().await.map(|_| ())
+    }
+
+    /// Send a burst of readings of a datastream mapping, each with its own timestamp
+    ///
+    /// Astarte has no batch message, so the readings are published one after the other
+    /// once all of them have been validated. The mapping must have an explicit timestamp.
+    /// Each reading is its own message, so the burst can be bigger than the maximum message
+    /// size set with [`set_max_message_size`](builder::AstarteBuilder::set_max_message_size).
+    /// Returns [`AstarteError::BurstTooLarge`] without sending anything if one of the readings
+    /// is bigger than it, or [`AstarteError::PayloadTooLarge`] if it's bigger than the size set
+    /// with [`set_max_payload_size`](builder::AstarteBuilder::set_max_payload_size).
+    pub async fn send_datastream_burst(
+        &self,
+        interface_name: &str,
+        interface_path: &str,
+        burst: Vec<(AstarteType, chrono::DateTime<chrono::Utc>)>,
+    ) -> Result<(), AstarteError> {
+        let aggregation = self
+            .interfaces
+            .get(interface_name)
+            .map(|interface| interface.aggregation());
+        let explicit_timestamp = self
+            .interfaces
+            .with_mapping(interface_name, interface_path, |mapping| match mapping {
+                interface::Mapping::Datastream(mapping) => mapping.explicit_timestamp,
+                interface::Mapping::Properties(_) => false,
+            })
+            .ok_or_else(|| AstarteError::SendError("Mapping doesn't exist".into()))?;
+
+        if !explicit_timestamp || aggregation != Some(interface::Aggregation::Individual) {
+            return Err(AstarteError::SendError(
+                "bursts need an individual datastream mapping with explicit timestamp".into(),
+            ));
+        }
+
+        for (data, timestamp) in &burst {
+            self.interfaces
+                .validate_type(interface_name, interface_path, data)?;
+
+            let buf = AstarteSdk::serialize_individual(data.clone(), Some(*timestamp))?;
+            let size = self.encode_payload(buf, Some(*timestamp))?.len();
+            if size > self.max_message_size {
+                return Err(AstarteError::BurstTooLarge {
+                    size,
+                    max: self.max_message_size,
+                });
+            }
+            if size > self.max_payload_size {
+                return Err(AstarteError::PayloadTooLarge {
+                    size,
+                    limit: self.max_payload_size,
+                });
+            }
+        }
+
+        for (data, timestamp) in burst {
+            self.send_with_timestamp_impl(interface_name, interface_path, data, Some(timestamp))
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    /// Send a value on each of the mappings of an individual interface, e.g. to update them all
+    /// at once
+    ///
+    /// The paths and the types of all the values are checked before sending anything, the
+    /// values are then published concurrently, each in its own message. If some of them can't
+    /// be sent, returns [`AstarteError::PartialBatch`] with the error of each failed path.
+    pub async fn send_individual_batch_for_interface(
+        &self,
+        interface_name: &str,
+        values: HashMap<&str, AstarteType>,
+        timestamp: Option<chrono::DateTime<chrono::Utc>>,
+    ) -> Result<(), AstarteError> {
+        self.check_can_send(interface_name)?;
+
+        if self.interface_aggregation(interface_name) == Some(interface::Aggregation::Object) {
+            return Err(AstarteError::WrongAggregation(interface_name.to_owned()));
+        }
+
+        for (interface_path, data) in &values {
+            self.interfaces
+                .validate_type(interface_name, interface_path, data)?;
+        }
+
+        let results = futures::future::join_all(values.into_iter().map(
+            |(interface_path, data)| async move {
+                let res = self
+                    .send_with_timestamp_impl(interface_name, interface_path, data, timestamp)
+                    .await;
+
+                (interface_path, res)
+            },
+        ))
+        .await;
+
+        let mut sent = 0;
+        let mut failed = Vec::new();
+        for (interface_path, res) in results {
+            match res {
+                Ok(()) => sent += 1,
+                Err(err) => {
+                    warn!(
+                        "failed to send {}{} in batch: {}",
+                        interface_name, interface_path, err
+                    );
+                    failed.push((interface_path.to_owned(), err));
+                }
+            }
+        }
+
+        if failed.is_empty() {
+            Ok(())
+        } else {
+            Err(AstarteError::PartialBatch { sent, failed })
+        }
+    }
+
+    /// Send datastream values stored while the device was offline, with their original timestamp
+    ///
+    /// Events are `(interface, path, value, timestamp)` and are sent in order with the QoS of their
+    /// mapping. A failed event doesn't stop the replay, its error is collected in the returned
+    /// [`ReplayResult`]. Depending on the interface, Astarte may discard values with a timestamp
+    /// that is too old.
+    pub async fn replay_stored_datastream(
+        &self,
+        events: Vec<(String, String, AstarteType, chrono::DateTime<chrono::Utc>)>,
+    ) -> Result<ReplayResult, AstarteError> {
+        let mut result = ReplayResult::default();
+
+        for (interface_name, interface_path, data, timestamp) in events {
+            match self
+                .send_with_timestamp_impl(&interface_name, &interface_path, data, Some(timestamp))
+                .await
+            {
+                Ok(()) => result.sent += 1,
+                Err(err) => {
+                    warn!(
+                        "failed to replay {}{}: {}",
+                        interface_name, interface_path, err
+                    );
+                    result.failed += 1;
+                    result.errors.push(err);
+                }
+            }
+        }
+
+        Ok(result)
+    }
+
+    async fn send_with_timestamp_impl<D>(
+        &self,
+        interface_name: &str,
+        interface_path: &str,
+        data: D,
+        timestamp: Option<chrono::DateTime<chrono::Utc>>,
+    ) -> Result<(), AstarteError>
+    where
+        D: Into<AstarteType>,
+    {
+        self.send_with_receipt(interface_name, interface_path, data, timestamp)
+            .await
+            .map(|_| ())
+    }
+
+    async fn send_with_receipt<D>(
+        &self,
+        interface_name: &str,
+        interface_path: &str,
+        data: D,
+        timestamp: Option<chrono::DateTime<chrono::Utc>>,
+    ) -> Result<MessageReceipt, AstarteError>
+    where
+        D: Into<AstarteType>,
+    {
+        debug!("sending {} {}", interface_name, interface_path);
+
+        self.check_can_send(interface_name)?;
+
+        if self.interface_aggregation(interface_name) == Some(interface::Aggregation::Object) {
+            return Err(AstarteError::WrongAggregation(interface_name.to_owned()));
+        }
+
+        let permit = self.publish_permit().await?;
+
+        let data: AstarteType = data.into();
+
+        self.interfaces
+            .validate_type(interface_name, interface_path, &data)?;
+
+        let buf = AstarteSdk::serialize_individual(data.clone(), timestamp)?;
+
+        if cfg!(debug_assertions) {
+            self.interfaces
+                .validate_send(interface_name, interface_path, &buf, &timestamp)?;
+        }
+
+        if self
+            .check_property_on_send(interface_name, interface_path, data.clone())
+            .await?
+        {
+            debug!("property was already sent, no need to send it again");
+            return Ok(MessageReceipt {
+                packet_id: 0,
+                serialized_bytes: 0,
+                publish_queued_at: Instant::now(),
+            });
+        }
+
+        let payload = self.encode_payload(buf, timestamp)?;
+        let serialized_bytes = payload.len();
+        let publish_queued_at = Instant::now();
+
+        let handed_over = self
+            .publish_on_mapping(interface_name, interface_path, payload)
+            .await?;
+        // the message doesn't take memory in the client anymore
+        drop(permit);
+        let packet_id = handed_over.acknowledged().await?;
+
+        // we store the property in the database after it has been successfully sent
+        self.store_property_on_send(interface_name, interface_path, data)
+            .await?;
+
+        Ok(MessageReceipt {
+            packet_id,
+            serialized_bytes,
+            publish_queued_at,
+        })
+    }
+
+    /// Wait for a free slot to publish, if the concurrent publishes are limited
+    async fn publish_permit(&self) -> Result<Option<SemaphorePermit<'_>>, AstarteError> {
+        match &self.publish_permits {
+            Some(permits) => permits
+                .acquire()
+                .await
+                .map(Some)
+                .map_err(|_| AstarteError::SendError("publish permits closed".into())),
+            None => Ok(None),
+        }
+    }
+
+    /// checks if a property mapping has alredy been sent, so we don't have to send the same thing again
+    /// returns true if property was already sent
+    async fn check_property_on_send<D>(
+        &self,
+        interface_name: &str,
+        interface_path: &str,
+        data: D,
+    ) -> Result<bool, AstarteError>
+    where
+        D: Into<AstarteType>,
+    {
+        if let Some(db) = &self.database {
+            //if database is present
+
+            let data: AstarteType = data.into();
+
+            if let Some(major) = self.stored_property_major(interface_name, interface_path) {
+                //if mapping is a property
+                let db_data = db.load_prop(interface_name, interface_path, major).await?;
+
+                if let Some(db_data) = db_data {
+                    // if already in db
+                    if db_data == data {
+                        return Ok(true);
+                    }
+                }
+            }
+        }
+
+        Ok(false)
+    }
+
+    async fn store_property_on_send<D>(
+        &self,
+        interface_name: &str,
+        interface_path: &str,
+        data: D,
+    ) -> Result<bool, AstarteError>
+    where
+        D: Into<AstarteType>,
+    {
+        if let Some(db) = &self.database {
+            //if database is present
+
+            let data: AstarteType = data.into();
+
+            if let Some(major) = self.stored_property_major(interface_name, interface_path) {
+                //if mapping is a property
+                let bin = AstarteSdk::serialize_individual(data, None)?;
+                db.store_prop(interface_name, interface_path, &bin, major)
+                    .await?;
+                debug!("Stored new property in database");
+            }
+        }
+
+        Ok(false)
+    }
+
+    /// Serialize an astarte type into a vec of bytes
+    fn serialize_individual<D>(
+        data: D,
+        timestamp: Option<chrono::DateTime<chrono::Utc>>,
+    ) -> Result<Vec<u8>, AstarteError>
+    where
+        D: Into<AstarteType>,
+    {
+        let data: AstarteType = data.into();
+        let capacity = data.size_hint() + INDIVIDUAL_DOCUMENT_SIZE;
+
+        AstarteSdk::serialize(data.into(), timestamp, capacity)
+    }
+
+    /// Encode a bson payload with the serializer set on the builder
+    fn encode_payload(
+        &self,
+        buf: Vec<u8>,
+        timestamp: Option<chrono::DateTime<chrono::Utc>>,
+    ) -> Result<Vec<u8>, AstarteError> {
+        match &self.serializer {
+            // an empty payload unsets a property in every format
+            Some(custom) if !buf.is_empty() => {
+                custom.serialize(&AstarteSdk::deserialize(&buf)?, timestamp)
+            }
+            _ => Ok(buf),
+        }
+    }
+
+    /// Decode a payload encoded with the serializer set on the builder to bson
+    fn decode_payload(&self, payload: &[u8]) -> Result<Vec<u8>, AstarteError> {
+        match &self.serializer {
+            Some(custom) if !payload.is_empty() => {
+                serializer::BsonSerializer.serialize(&custom.deserialize(payload)?, None)
+            }
+            _ => Ok(payload.to_vec()),
+        }
+    }
+
+    // ------------------------------------------------------------------------
+    // object types
+    // ------------------------------------------------------------------------
+
+    /// helper function to convert from an HashMap of AstarteType to an HashMap of Bson
+    pub fn to_bson_map(data: HashMap<&str, AstarteType>) -> HashMap<&str, Bson> {
+        data.into_iter().map(|f| (f.0, f.1.into())).collect()
+    }
+
+    /// Serialize a group of astarte types to a vec of bytes, representing an object
+    fn serialize_object<T>(
+        data: T,
+        timestamp: Option<chrono::DateTime<chrono::Utc>>,
+    ) -> Result<Vec<u8>, AstarteError>
+    where
+        T: serde::Serialize,
+    {
+        let doc = to_document(&data)?;
+
+        AstarteSdk::serialize(Bson::Document(doc), timestamp, 0)
+    }
+
+    async fn send_object_with_timestamp_impl<T>(
+        &self,
+        interface_name: &str,
+        interface_path: &str,
+        data: T,
+        timestamp: Option<chrono::DateTime<chrono::Utc>>,
+    ) -> Result<(), AstarteError>
+    where
+        T: serde::Serialize,
+    {
+        self.check_can_send(interface_name)?;
+
+        let buf = AstarteSdk::serialize_object(data, timestamp)?;
+
+        if cfg!(debug_assertions) {
+            self.interfaces
+                .validate_send(interface_name, interface_path, &buf, &timestamp)?;
+        }
+
+        self.publish_on_mapping(
+            interface_name,
+            interface_path,
+            self.encode_payload(buf, timestamp)?,
+        )
+        .await?
+        .acknowledged()
+        .await?;
+
+        Ok(())
+    }
+
+    /// Report a device error on the [`ERROR_INTERFACE`] interface
+    ///
+    /// The interface must be registered as a device-owned object datastream, with the
+    /// `/error/code` longinteger and `/error/message` string mappings.
+    pub async fn report_error(&self, code: u32, message: &str) -> Result<(), AstarteError> {
+        if !self.interfaces.contains(ERROR_INTERFACE) {
+            return Err(AstarteError::SendError(format!(
+                "{} is not registered",
+                ERROR_INTERFACE
+            )));
+        }
+
+        let error = bson::doc! {
+            "code": i64::from(code),
+            "message": message,
+        };
+
+        self.send_object(ERROR_INTERFACE, "/error", error).await
+    }
+
+    /// Publish the device description on the [`PRODUCT_INFO_INTERFACE`] interface
+    ///
+    /// The interface must be registered as device-owned properties, with the `/hwVersion`,
+    /// `/swVersion` and `/model` string mappings. Like the other properties, the values already
+    /// sent are not sent again.
+    pub async fn publish_product_info(&self, info: ProductInfo) -> Result<(), AstarteError> {
+        if !self.interfaces.contains(PRODUCT_INFO_INTERFACE) {
+            return Err(AstarteError::SendError(format!(
+                "{} is not registered",
+                PRODUCT_INFO_INTERFACE
+            )));
+        }
+
+        let ProductInfo {
+            hw_version,
+            sw_version,
+            model,
+        } = info;
+
+        for (path, value) in [
+            ("/hwVersion", hw_version),
+            ("/swVersion", sw_version),
+            ("/model", model),
+        ] {
+            self.send(PRODUCT_INFO_INTERFACE, path, value).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Publish the description of the system the device runs on, see
+    /// [`publish_system_info_with`](AstarteSdk::publish_system_info_with)
+    ///
+    /// The values are read from the system, the ones that can't be read are skipped.
+    #[cfg(feature = "system-info")]
+    pub async fn publish_system_info(&self) -> Result<(), AstarteError> {
+        self.publish_system_info_with(SystemInfo::collect()).await
+    }
+
+    /// Publish the system description on the [`SYSTEM_INFO_INTERFACE`] interface
+    ///
+    /// The interface must be registered as device-owned properties, with the `/hostname`,
+    /// `/osVersion` and `/cpuModel` string mappings and the `/totalRam` longinteger mapping.
+    /// The missing values are not sent.
+    pub async fn publish_system_info_with(&self, info: SystemInfo) -> Result<(), AstarteError> {
+        if !self.interfaces.contains(SYSTEM_INFO_INTERFACE) {
+            return Err(AstarteError::SendError(format!(
+                "{} is not registered",
+                SYSTEM_INFO_INTERFACE
+            )));
+        }
+
+        let SystemInfo {
+            hostname,
+            os_version,
+            total_ram,
+            cpu_model,
+        } = info;
+
+        let values = [
+            ("/hostname", hostname.map(AstarteType::from)),
+            ("/osVersion", os_version.map(AstarteType::from)),
+            ("/totalRam", total_ram.map(AstarteType::from)),
+            ("/cpuModel", cpu_model.map(AstarteType::from)),
+        ];
+        for (path, value) in values {
+            match value {
+                Some(value) => self.send(SYSTEM_INFO_INTERFACE, path, value).await?,
+                None => debug!("{} is not available, not publishing it", path),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Send data to an object interface. with timestamp
+    pub async fn send_object_with_timestamp<T>(
+        &self,
+        interface_name: &str,
+        interface_path: &str,
+        data: T,
+        timestamp: chrono::DateTime<chrono::Utc>,
+    ) -> Result<(), AstarteError>
+    where
+        T: serde::Serialize,
+    {
+        self.send_object_with_timestamp_impl(interface_name, interface_path, data, Some(timestamp))
+            .await
+    }
+
+    /// Send data to an object interface. with timestamp
+    pub async fn send_object<T>(
+        &self,
+        interface_name: &str,
+        interface_path: &str,
+        data: T,
+    ) -> Result<(), AstarteError>
+    where
+        T: serde::Serialize,
+    {
+        self.send_object_with_timestamp_impl(interface_name, interface_path, data, None)
+            .await
+    }
+
+    /// Send data to an object interface, making sure every field matches a mapping
+    ///
+    /// All the fields are checked before sending, and the problems are returned together in
+    /// [`AstarteError::AggregateValidation`].
+    pub async fn send_object_checked(
+        &self,
+        interface_name: &str,
+        interface_path: &str,
+        data: HashMap<String, AstarteType>,
+    ) -> Result<(), AstarteError> {
+        let aggregation = self
+            .interfaces
+            .get(interface_name)
+            .map(|interface| interface.aggregation());
+        if aggregation != Some(interface::Aggregation::Object) {
+            return Err(AstarteError::SendError(format!(
+                "Interface '{}' is not an object interface",
+                interface_name
+            )));
+        }
+
+        let mut errors = Vec::new();
+        for (key, value) in data.iter() {
+            let path = format!("{}/{}", interface_path.trim_end_matches('/'), key);
+
+            match self.interfaces.validate_type(interface_name, &path, value) {
+                Ok(()) => {}
+                Err(AstarteError::TypeMismatch { expected, got }) => {
+                    errors.push(format!("{}: expected {:?} got {:?}", path, expected, got))
+                }
+                Err(_) => errors.push(format!("{}: mapping doesn't exist", path)),
+            }
+        }
+
+        if !errors.is_empty() {
+            errors.sort();
+            return Err(AstarteError::AggregateValidation { errors });
+        }
+
+        let data: HashMap<String, Bson> = data
+            .into_iter()
+            .map(|(key, value)| (key, value.into()))
+            .collect();
+
+        self.send_object_with_timestamp_impl(interface_name, interface_path, data, None)
+            .await
+    }
+}
+
+impl fmt::Debug for AstarteSdk {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Device")
+            .field("realm", &self.realm)
+            .field("device_id", &self.device_id)
+            .field("device_alias", &self.device_alias)
+            .field("credentials_secret", &self.credentials_secret)
+            .field("pairing_url", &self.pairing_url)
+            .field("build_options", &self.build_options)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::{TimeZone, Utc};
+
+    use std::convert::TryFrom;
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    use crate::builder::AstarteBuilder;
+    use crate::database::{AstarteDatabase, AstarteSqliteDatabase};
+    use crate::stats::ConnectionStats;
+    use crate::{types::AstarteType, AstarteSdk, Interface};
+
+    const ERROR_INTERFACE_JSON: &str = r#"
+    {
+        "interface_name": "org.astarte-platform.generic.errors.v1",
+        "version_major": 1,
+        "version_minor": 0,
+        "type": "datastream",
+        "ownership": "device",
+        "aggregation": "object",
+        "mappings": [
+            {
+                "endpoint": "/error/code",
+                "type": "longinteger"
+            },
+            {
+                "endpoint": "/error/message",
+                "type": "string"
+            }
+        ]
+    }
+    "#;
+
+    const PRODUCT_INFO_JSON: &str = r#"
+    {
+        "interface_name": "org.astarte-platform.generic.ProductInfo.v1",
+        "version_major": 1,
+        "version_minor": 0,
+        "type": "properties",
+        "ownership": "device",
+        "mappings": [
+            {
+                "endpoint": "/hwVersion",
+                "type": "string"
+            },
+            {
+                "endpoint": "/swVersion",
+                "type": "string"
+            },
+            {
+                "endpoint": "/model",
+                "type": "string"
+            }
+        ]
+    }
+    "#;
+
+    const GUARANTEED_INTERFACE: &str = r#"
+    {
+        "interface_name": "com.test.Guaranteed",
+        "version_major": 0,
+        "version_minor": 1,
+        "type": "datastream",
+        "ownership": "device",
+        "mappings": [
+            {
+                "endpoint": "/value",
+                "type": "integer",
+                "reliability": "guaranteed"
+            }
+        ]
+    }
+    "#;
+
+    fn do_vecs_match(a: &[u8], b: &[u8]) -> bool {
+        let matching = a.iter().zip(b.iter()).filter(|&(a, b)| a == b).count();
+
+        println!("matching {:?}\nwith     {:?}\n", a, b);
+        matching == a.len() && matching == b.len()
+    }
+
+    #[test]
+    fn serialize_individual() {
+        assert!(do_vecs_match(
+            &AstarteSdk::serialize_individual(false, None).unwrap(),
+            &[0x09, 0x00, 0x00, 0x00, 0x08, 0x76, 0x00, 0x00, 0x00]
+        ));
+        assert!(do_vecs_match(
+            &AstarteSdk::serialize_individual(AstarteType::Double(16.73), None).unwrap(),
+            &[
+                0x10, 0x00, 0x00, 0x00, 0x01, 0x76, 0x00, 0x7b, 0x14, 0xae, 0x47, 0xe1, 0xba, 0x30,
+                0x40, 0x00
+            ]
+        ));
+        assert!(do_vecs_match(
+            &AstarteSdk::serialize_individual(
+                AstarteType::Double(16.73),
+                Some(Utc.timestamp(1537449422, 890000000))
+            )
+            .unwrap(),
+            &[
+                0x1b, 0x00, 0x00, 0x00, 0x09, 0x74, 0x00, 0x2a, 0x70, 0x20, 0xf7, 0x65, 0x01, 0x00,
+                0x00, 0x01, 0x76, 0x00, 0x7b, 0x14, 0xae, 0x47, 0xe1, 0xba, 0x30, 0x40, 0x00
+            ]
+        ));
+    }
+
+    #[test]
+    fn test_parse_topic() {
+        let topic = "test/u-WraCwtK_G_fjJf63TiAw/com.interface.test/led/red".to_owned();
+        let (interface, path) = crate::parse_topic("test/u-WraCwtK_G_fjJf63TiAw", &topic).unwrap();
+        assert!(interface == "com.interface.test");
+        assert!(path == "/led/red");
+
+        let topic = "tenant/a/b/com.interface.test/led/red";
+        let (interface, path) = crate::parse_topic("tenant/a/b", topic).unwrap();
+        assert!(interface == "com.interface.test");
+        assert!(path == "/led/red");
+
+        assert!(crate::parse_topic("test/other", &topic).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_export_import_state() {
+        let value = AstarteSdk::serialize_individual("foo", None).unwrap();
+
+        let db = AstarteSqliteDatabase::new("sqlite::memory:").await.unwrap();
+        db.store_prop(
+            "org.astarte-platform.genericsensors.AvailableSensors",
+            "/1/name",
+            &value,
+            0,
+        )
+        .await
+        .unwrap();
+        db.store_prop("com.test.unregistered", "/foo", &value, 1)
+            .await
+            .unwrap();
+
+        let device = test_device_with(|options| {
+            options.with_database(db);
+        })
+        .await;
+
+        let state = device.export_state().await.unwrap();
+
+        let db2 = AstarteSqliteDatabase::new("sqlite::memory:").await.unwrap();
+        let mut options = AstarteBuilder::new("test", "test2", "test", "test");
+        options
+            .add_interface_files("examples/interfaces/")
+            .unwrap()
+            .with_database(db2.clone());
+        let device2 = options.connect_offline().await;
+
+        device2.import_state(&state).await.unwrap();
+
+        assert_eq!(
+            device2
+                .get_property(
+                    "org.astarte-platform.genericsensors.AvailableSensors",
+                    "/1/name"
+                )
+                .await
+                .unwrap(),
+            Some(AstarteType::String("foo".into()))
+        );
+
+        // the property of the unregistered interface is skipped
+        assert_eq!(db2.load_all_props().await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_snapshot() {
+        let interface = "org.astarte-platform.genericsensors.AvailableSensors";
+
+        let db = AstarteSqliteDatabase::new("sqlite::memory:").await.unwrap();
+        for (path, value) in [("/1/name", "foo"), ("/2/name", "bar")] {
+            let value = AstarteSdk::serialize_individual(value, None).unwrap();
+            db.store_prop(interface, path, &value, 0).await.unwrap();
+        }
+
+        let mut options = AstarteBuilder::new("test", "test", "test", "test");
+        options
+            .add_interface_files("examples/interfaces/")
+            .unwrap()
+            .with_database(db.clone());
+        let device = options.connect_offline().await;
+
+        let snapshot = device.snapshot().await.unwrap();
+
+        let value = AstarteSdk::serialize_individual("baz", None).unwrap();
+        db.store_prop(interface, "/1/name", &value, 0)
+            .await
+            .unwrap();
+
+        assert_eq!(snapshot.properties.len(), 2);
+        let prop = snapshot
+            .properties
+            .iter()
+            .find(|prop| prop.path == "/1/name")
+            .unwrap();
+        assert_eq!(prop.value, AstarteType::String("foo".into()));
+
+        let table = snapshot.to_string();
+        assert!(table.contains("/1/name"));
+        assert!(table.contains("/2/name"));
+    }
+
+    #[tokio::test]
+    async fn test_await_puback() {
+        let interface: Interface = GUARANTEED_INTERFACE.parse().unwrap();
+        let port = mock_broker_with(MockBroker {
+            puback_delay: Some(Duration::from_millis(300)),
+            ..Default::default()
+        })
+        .await;
+
+        let mut options = AstarteBuilder::new("test", "test", "test", "test");
+        options
+            .interfaces
+            .insert("com.test.Guaranteed".into(), interface);
+        options.await_puback(true);
+        let device = options.connect_offline().await;
+        device.eventloop.lock().await.options =
+            rumqttc::MqttOptions::new("test/test", "127.0.0.1", port);
+
+        let mut poller = device.clone();
+        let polling = tokio::spawn(async move { while poller.poll().await.is_ok() {} });
+
+        let sender = device.clone();
+        let mut send =
+            tokio::spawn(async move { sender.send("com.test.Guaranteed", "/value", 42).await });
+
+        // the broker delays the ack
+        assert!(tokio::time::timeout(Duration::from_millis(100), &mut send)
+            .await
+            .is_err());
+
+        tokio::time::timeout(Duration::from_secs(5), send)
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        polling.abort();
+    }
+
+    #[tokio::test]
+    async fn test_await_puback_timeout() {
+        tokio::time::pause();
+
+        let interface: Interface = GUARANTEED_INTERFACE.parse().unwrap();
+
+        let mut options = AstarteBuilder::new("test", "test", "test", "test");
+        options
+            .interfaces
+            .insert("com.test.Guaranteed".into(), interface);
+        options.await_puback(true);
+        let device = options.connect_offline().await;
+
+        // nothing polls the client, so the message is never acknowledged
+        let res = device.send("com.test.Guaranteed", "/value", 42).await;
+        assert!(matches!(res, Err(crate::AstarteError::PublishTimeout)));
+    }
+
+    #[tokio::test]
+    async fn test_ping() {
+        let port = mock_broker(Vec::new()).await;
+
+        let device = test_device().await;
+        device.eventloop.lock().await.options =
+            rumqttc::MqttOptions::new("test/test", "127.0.0.1", port);
+
+        let mut poller = device.clone();
+        let polling = tokio::spawn(async move { while poller.poll().await.is_ok() {} });
+
+        let rtt = device.ping().await.unwrap();
+        assert!(rtt > Duration::ZERO && rtt < Duration::from_secs(1));
+        polling.abort();
+    }
+
+    #[tokio::test]
+    async fn test_ping_timeout() {
+        tokio::time::pause();
+
+        let device = test_device().await;
+
+        // nothing polls the client, so the broker never answers
+        assert!(matches!(
+            device.ping().await,
+            Err(crate::AstarteError::PingTimeout)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_replay_stored_datastream() {
+        let device = test_device().await;
+
+        let timestamp = Utc.timestamp(1537449422, 0);
+        let events = vec![
+            (
+                "com.test.Everything".to_owned(),
+                "/boolean".to_owned(),
+                AstarteType::Boolean(true),
+                timestamp,
+            ),
+            (
+                "com.test.Missing".to_owned(),
+                "/boolean".to_owned(),
+                AstarteType::Boolean(true),
+                timestamp,
+            ),
+            (
+                "com.test.Everything".to_owned(),
+                "/boolean".to_owned(),
+                AstarteType::Integer(3),
+                timestamp,
+            ),
+            (
+                "com.test.Everything".to_owned(),
+                "/integer".to_owned(),
+                AstarteType::Integer(3),
+                timestamp,
+            ),
+        ];
+
+        let result = device.replay_stored_datastream(events).await.unwrap();
+
+        assert_eq!(result.sent, 2);
+        assert_eq!(result.failed, 2);
+        assert!(matches!(
+            result.errors[1],
+            crate::AstarteError::TypeMismatch { .. }
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_clear_property_cache() {
+        let db = AstarteSqliteDatabase::new("sqlite::memory:").await.unwrap();
+        let value = AstarteSdk::serialize_individual("foo", None).unwrap();
+        for path in ["/token", "/permanent"] {
+            db.store_prop("com.test.Expiring", path, &value, 0)
+                .await
+                .unwrap();
+        }
+        // server-owned properties are not unset
+        let value = AstarteSdk::serialize_individual(true, None).unwrap();
+        db.store_prop(
+            "org.astarte-platform.genericsensors.SamplingRate",
+            "/1/enable",
+            &value,
+            0,
+        )
+        .await
+        .unwrap();
+
+        let mut options = AstarteBuilder::new("test", "test", "test", "test");
+        options
+            .add_interface_files("examples/interfaces/")
+            .unwrap()
+            .interfaces_from_slice(&[EXPIRING_PROPERTIES])
+            .unwrap()
+            .with_database(db.clone());
+        let device = options.connect_offline().await;
+
+        device.clear_property_cache().await.unwrap();
+
+        // the mapping without allow_unset is only removed
+        let published = published(&device).await;
+        assert_eq!(published.len(), 1);
+        assert_eq!(published[0].topic, "test/test/com.test.Expiring/token");
+        assert!(published[0].payload.is_empty());
+        assert!(db.load_all_props().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_set_log_level() {
+        let device = test_device().await;
+
+        let global = log::max_level();
+
+        device.set_log_level(log::LevelFilter::Warn);
+        assert!(crate::logging::enabled(log::Level::Warn));
+        assert!(!crate::logging::enabled(log::Level::Debug));
+
+        device.set_log_level(log::LevelFilter::Trace);
+        assert!(crate::logging::enabled(log::Level::Trace));
+
+        // the application level is untouched
+        assert_eq!(log::max_level(), global);
+    }
+
+    #[tokio::test]
+    async fn test_check_interface_compatibility() {
+        let json = include_str!(
+            "../examples/interfaces/org.astarte-platform.genericsensors.AvailableSensors.json"
+        );
+        let interface = "org.astarte-platform.genericsensors.AvailableSensors";
+
+        let db = AstarteSqliteDatabase::new("sqlite::memory:").await.unwrap();
+        let mut options = AstarteBuilder::new("test", "test", "test", "test");
+        options
+            .interfaces_from_slice(&[json])
+            .unwrap()
+            .with_database(db.clone());
+        let device = options.connect_offline().await;
+
+        for (path, value) in &[("/1/name", "foo"), ("/1/unit", "m")] {
+            let value = AstarteSdk::serialize_individual(*value, None).unwrap();
+            db.store_prop(interface, path, &value, 0).await.unwrap();
+        }
+
+        // minor bump adding a mapping
+        let mut minor: serde_json::Value = serde_json::from_str(json).unwrap();
+        minor["version_minor"] = 2.into();
+        minor["mappings"]
+            .as_array_mut()
+            .unwrap()
+            .push(serde_json::json!({"endpoint": "/%{sensor_id}/range", "type": "double"}));
+
+        let report = device
+            .check_interface_compatibility(&minor.to_string())
+            .await
+            .unwrap();
+        assert_eq!(
+            report,
+            crate::CompatibilityReport {
+                compatible: true,
+                stored_props_affected: 0,
+                breaking_changes: Vec::new(),
+            }
+        );
+
+        // major bump removing a mapping and changing the type of the other
+        let mut major: serde_json::Value = serde_json::from_str(json).unwrap();
+        major["version_major"] = 1.into();
+        major["mappings"].as_array_mut().unwrap().pop();
+        major["mappings"][0]["type"] = "integer".into();
+
+        let report = device
+            .check_interface_compatibility(&major.to_string())
+            .await
+            .unwrap();
+        assert!(!report.compatible);
+        assert_eq!(report.stored_props_affected, 2);
+        assert_eq!(
+            report.breaking_changes,
+            vec![
+                "major version changed from 0 to 1",
+                "mapping /%{sensor_id}/name type changed from String to Integer",
+                "mapping /%{sensor_id}/unit removed",
+            ]
+        );
+
+        // unset rows are skipped, undecodable ones are reported
+        db.store_prop(interface, "/2/name", &[], 0).await.unwrap();
+        let report = device
+            .check_interface_compatibility(&minor.to_string())
+            .await
+            .unwrap();
+        assert!(report.compatible);
+
+        db.store_prop(interface, "/3/name", &[0xff, 0x00], 0)
+            .await
+            .unwrap();
+        let report = device
+            .check_interface_compatibility(&minor.to_string())
+            .await
+            .unwrap();
+        assert!(!report.compatible);
+        assert_eq!(report.stored_props_affected, 1);
+        assert!(report.breaking_changes.is_empty());
+
+        assert!(device.check_interface_compatibility("{}").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_event_loop_errors() {
+        let device = test_device().await;
+
+        // nobody is listening
+        device.report_event_loop_error(crate::AstarteError::Unreported);
+
+        let mut errors = device.event_loop_errors();
+        assert!(matches!(
+            errors.try_recv(),
+            Err(tokio::sync::mpsc::error::TryRecvError::Empty)
+        ));
+
+        device.report_event_loop_error(crate::AstarteError::Timeout);
+        assert!(matches!(
+            errors.try_recv(),
+            Ok(crate::AstarteError::Timeout)
+        ));
+
+        drop(device);
+        assert!(matches!(
+            errors.try_recv(),
+            Err(tokio::sync::mpsc::error::TryRecvError::Disconnected)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_disable_interface() {
+        let device = test_device().await;
+
+        assert!(device.disable_interface("com.test.Missing").is_err());
+
+        let interface = "org.astarte-platform.genericsensors.SamplingRate";
+        let topic = format!("test/test/{}/1/enable", interface);
+        let value = AstarteSdk::serialize_individual(true, None).unwrap();
+
+        device.disable_interface(interface).unwrap();
+        device
+            .clone()
+            .disable_interface("com.test.Everything")
+            .unwrap();
+
+        assert!(device
+            .process_publish(&topic, &value)
+            .await
+            .unwrap()
+            .is_none());
+        let res = device
+            .send_with_timestamp("com.test.Everything", "/integer", 42, Utc::now())
+            .await;
+        assert!(matches!(
+            res,
+            Err(crate::AstarteError::InterfaceDisabled(_))
+        ));
+        assert_eq!(device.connection_stats().total_publishes, 0);
+
+        device.enable_interface(interface).unwrap();
+        device.enable_interface("com.test.Everything").unwrap();
+
+        assert!(device
+            .process_publish(&topic, &value)
+            .await
+            .unwrap()
+            .is_some());
+        device
+            .send_with_timestamp("com.test.Everything", "/integer", 42, Utc::now())
+            .await
+            .unwrap();
+        assert_eq!(device.connection_stats().total_publishes, 1);
+    }
+
+    #[tokio::test]
+    async fn test_send_object_checked() {
+        let interface = "org.astarte-platform.genericsensors.Geolocation";
+
+        let device = test_device().await;
+
+        let mut data = std::collections::HashMap::new();
+        data.insert("latitude".to_owned(), AstarteType::Double(45.4));
+        data.insert("longitude".to_owned(), AstarteType::Double(11.9));
+
+        device
+            .send_object_checked(interface, "/1", data.clone())
+            .await
+            .unwrap();
+        assert_eq!(device.connection_stats().total_publishes, 1);
+
+        data.insert("altitude".to_owned(), AstarteType::Integer(12));
+        data.insert("pressure".to_owned(), AstarteType::Double(1.0));
+
+        let res = device.send_object_checked(interface, "/1", data).await;
+        match res {
+            Err(crate::AstarteError::AggregateValidation { errors }) => assert_eq!(
+                errors,
+                vec![
+                    "/1/altitude: expected Double got Integer",
+                    "/1/pressure: mapping doesn't exist",
+                ]
+            ),
+            _ => panic!("expected a validation error, got {:?}", res),
+        }
+        assert_eq!(device.connection_stats().total_publishes, 1);
+
+        let mut data = std::collections::HashMap::new();
+        data.insert("integer".to_owned(), AstarteType::Integer(1));
+        assert!(device
+            .send_object_checked("com.test.Everything", "/", data)
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_publish_device_owned_properties_for_interface() {
+        const DEVICE_PROPERTIES: &str = r#"
+        {
+            "interface_name": "com.test.DeviceProperties",
+            "version_major": 0,
+            "version_minor": 1,
+            "type": "properties",
+            "ownership": "device",
+            "mappings": [
+                {
+                    "endpoint": "/name",
+                    "type": "string"
+                }
+            ]
+        }
+        "#;
+
+        let db = AstarteSqliteDatabase::new("sqlite::memory:").await.unwrap();
+        let mut options = AstarteBuilder::new("test", "test", "test", "test");
+        options
+            .add_interface_files("examples/interfaces/")
+            .unwrap()
+            .interfaces_from_slice(&[DEVICE_PROPERTIES])
+            .unwrap()
+            .with_database(db.clone());
+        let device = options.connect_offline().await;
+
+        let value = AstarteSdk::serialize_individual("foo", None).unwrap();
+        db.store_prop(
+            "org.astarte-platform.genericsensors.AvailableSensors",
+            "/1/name",
+            &value,
+            0,
+        )
+        .await
+        .unwrap();
+        db.store_prop("com.test.DeviceProperties", "/name", &value, 0)
+            .await
+            .unwrap();
+
+        device
+            .publish_device_owned_properties_for_interface("com.test.DeviceProperties")
+            .await
+            .unwrap();
+        assert_eq!(device.connection_stats().total_publishes, 1);
+
+        assert!(device
+            .publish_device_owned_properties_for_interface(
+                "org.astarte-platform.genericsensors.SamplingRate"
+            )
+            .await
+            .is_err());
+        assert_eq!(device.connection_stats().total_publishes, 1);
+    }
+
+    #[tokio::test]
+    async fn test_interface_ownership() {
+        use crate::interface::Ownership;
+
+        let device = test_device().await;
+
+        assert_eq!(
+            device.interface_ownership("org.astarte-platform.genericsensors.AvailableSensors"),
+            Some(Ownership::Device)
+        );
+        assert_eq!(
+            device.interface_ownership("org.astarte-platform.genericsensors.SamplingRate"),
+            Some(Ownership::Server)
+        );
+        assert_eq!(device.interface_ownership("com.test.Missing"), None);
+
+        let res = device
+            .send(
+                "org.astarte-platform.genericsensors.SamplingRate",
+                "/1/enable",
+                true,
+            )
+            .await;
+        assert!(matches!(res, Err(crate::AstarteError::SendError(_))));
+        assert_eq!(device.connection_stats().total_publishes, 0);
+    }
+
+    #[tokio::test]
+    async fn test_interface_type() {
+        use crate::interface::InterfaceType;
+
+        let db = AstarteSqliteDatabase::new("sqlite::memory:").await.unwrap();
+        let mut options = AstarteBuilder::new("test", "test", "test", "test");
+        options
+            .add_interface_files("examples/interfaces/")
+            .unwrap()
+            .with_database(db.clone());
+        let device = options.connect_offline().await;
+
+        assert_eq!(
+            device.interface_type("org.astarte-platform.genericsensors.SamplingRate"),
+            Some(InterfaceType::Properties)
+        );
+        assert_eq!(
+            device.interface_type("com.test.Everything"),
+            Some(InterfaceType::Datastream)
+        );
+        assert_eq!(device.interface_type("com.test.Missing"), None);
+
+        // only properties are stored
+        let value = AstarteSdk::serialize_individual(true, None).unwrap();
+        device
+            .process_publish(
+                "test/test/org.astarte-platform.genericsensors.SamplingRate/1/enable",
+                &value,
+            )
+            .await
+            .unwrap();
+        device
+            .send_with_timestamp("com.test.Everything", "/boolean", true, Utc::now())
+            .await
+            .unwrap();
+
+        let props = db.load_all_props().await.unwrap();
+        assert_eq!(props.len(), 1);
+        assert_eq!(props[0].interface_major, 1);
+    }
+
+    #[tokio::test]
+    async fn test_interface_version() {
+        let device = test_device().await;
+
+        assert_eq!(
+            device.interface_version("com.test.Everything"),
+            Some((0, 1))
+        );
+        assert_eq!(
+            device.interface_version("org.astarte-platform.genericsensors.SamplingRate"),
+            Some((1, 0))
+        );
+        assert_eq!(device.interface_version("com.test.Missing"), None);
+    }
+
+    #[tokio::test]
+    async fn test_registered_interface_count() {
+        let mut options = AstarteBuilder::new("test", "test", "test", "test");
+        let device = options.connect_offline().await;
+        assert_eq!(device.registered_interface_count(), 0);
+
+        options
+            .interfaces_from_slice(&[include_str!(
+                "../examples/interfaces/com.test.Everything.json"
+            )])
+            .unwrap();
+        let device = options.connect_offline().await;
+        assert_eq!(device.registered_interface_count(), 1);
+
+        options
+            .interfaces_from_slice(&[include_str!(
+                "../examples/interfaces/org.astarte-platform.genericsensors.SamplingRate.json"
+            )])
+            .unwrap();
+        let device = options.connect_offline().await;
+        assert_eq!(device.registered_interface_count(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_wait_for_property() {
+        let interface = "org.astarte-platform.genericsensors.SamplingRate";
+
+        let device = test_device().await;
+
+        let res = device
+            .wait_for_property(interface, "/1/enable", Duration::from_millis(10))
+            .await;
+        assert!(matches!(res, Err(crate::AstarteError::Timeout)));
+        assert!(device.property_waiters.lock().unwrap().is_empty());
+
+        let waiter = device.clone();
+        let wait = tokio::spawn(async move {
+            waiter
+                .wait_for_property(interface, "/1/enable", Duration::from_secs(5))
+                .await
+        });
+
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        device.notify_property_waiters(interface, "/2/enable", &AstarteType::Boolean(false));
+        device.notify_property_waiters(interface, "/1/enable", &AstarteType::Boolean(true));
+
+        assert_eq!(wait.await.unwrap().unwrap(), AstarteType::Boolean(true));
+    }
+
+    #[tokio::test]
+    async fn test_send_datastream_burst() {
+        let device = test_device_with(|options| {
+            options.set_max_message_size(100);
+        })
+        .await;
+        // the payload limit is lowered to the message size
+        assert_eq!(device.max_payload_size, 100);
+
+        let burst: Vec<_> = (0..3)
+            .map(|i| {
+                (
+                    AstarteType::Integer(i),
+                    Utc.timestamp(1537449422 + i64::from(i), 0),
+                )
+            })
+            .collect();
+
+        let queued = device.requests.len();
+        device
+            .send_datastream_burst("com.test.Everything", "/integer", burst.clone())
+            .await
+            .unwrap();
+        assert_eq!(device.requests.len(), queued + 3);
+
+        // the limit applies to each reading, not to the whole burst
+        let long_burst: Vec<_> = burst.iter().cycle().take(10).cloned().collect();
+        device
+            .send_datastream_burst("com.test.Everything", "/integer", long_burst)
+            .await
+            .unwrap();
+        assert_eq!(device.requests.len(), queued + 13);
+
+        let big_burst = vec![
+            (AstarteType::String("foo".into()), Utc::now()),
+            (AstarteType::String("x".repeat(200)), Utc::now()),
+        ];
+        let res = device
+            .send_datastream_burst("com.test.Everything", "/string", big_burst)
+            .await;
+        assert!(matches!(
+            res,
+            Err(crate::AstarteError::BurstTooLarge { max: 100, .. })
+        ));
+
+        // properties can't be sent in bursts
+        let res = device
+            .send_datastream_burst(
+                "org.astarte-platform.genericsensors.AvailableSensors",
+                "/1/name",
+                vec![(AstarteType::String("foo".into()), Utc::now())],
+            )
+            .await;
+        assert!(res.is_err());
+        assert_eq!(device.requests.len(), queued + 13);
+
+        // the payload limit is checked before sending too
+        let device = test_device_with(|options| {
+            options.set_max_payload_size(50);
+        })
+        .await;
+        let big_burst = vec![
+            (AstarteType::String("foo".into()), Utc::now()),
+            (AstarteType::String("x".repeat(100)), Utc::now()),
+        ];
+        let res = device
+            .send_datastream_burst("com.test.Everything", "/string", big_burst)
+            .await;
+        assert!(matches!(
+            res,
+            Err(crate::AstarteError::PayloadTooLarge { limit: 50, .. })
+        ));
+        assert!(published(&device).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_send_individual() {
+        let device = test_device().await;
+
+        let timestamp = Some(Utc::now());
+        device
+            .send_individual("com.test.Everything", "/integer", 42, timestamp)
+            .await
+            .unwrap();
+        device
+            .send_individual("com.test.Everything", "/longinteger", 42_i64, timestamp)
+            .await
+            .unwrap();
+        device
+            .send_individual("com.test.Everything", "/string", "hello", timestamp)
+            .await
+            .unwrap();
+        device
+            .send_individual("com.test.Everything", "/boolean", true, timestamp)
+            .await
+            .unwrap();
+        device
+            .send_individual(
+                "com.test.Everything",
+                "/double",
+                AstarteType::try_from(4.5).unwrap(),
+                timestamp,
+            )
+            .await
+            .unwrap();
+        assert_eq!(device.connection_stats().total_publishes, 5);
+
+        let res = device
+            .send_individual("com.test.Everything", "/integer", "hello", timestamp)
+            .await;
+        assert!(matches!(res, Err(crate::AstarteError::TypeMismatch { .. })));
+    }
+
+    #[tokio::test]
+    async fn test_send_individual_no_validate() {
+        let device = test_device().await;
+
+        device
+            .send_individual_no_validate(
+                "com.test.Everything",
+                "/integer",
+                AstarteType::Integer(42),
+            )
+            .await
+            .unwrap();
+
+        // not rejected, unlike the validated send
+        device
+            .send_individual_no_validate(
+                "com.test.Everything",
+                "/integer",
+                AstarteType::String("hello".into()),
+            )
+            .await
+            .unwrap();
+        device
+            .send_individual_no_validate("com.test.Missing", "/value", AstarteType::Boolean(true))
+            .await
+            .unwrap();
+
+        assert_eq!(device.connection_stats().total_publishes, 3);
+    }
+
+    #[tokio::test]
+    async fn test_connection_stats() {
+        let device = test_device().await;
+
+        for i in 0..10 {
+            device
+                .send_with_timestamp("com.test.Everything", "/integer", i, Utc::now())
+                .await
+                .unwrap();
+        }
+
+        let stats = device.connection_stats();
+        assert_eq!(stats.total_publishes, 10);
+        assert!(stats.bytes_sent > 0);
+        assert_eq!(stats.total_receives, 0);
+    }
+
+    #[tokio::test]
+    async fn test_subscribe_all() {
+        let device = test_device().await;
+
+        let mut events = device.subscribe_all();
+
+        let value = AstarteSdk::serialize_individual(true, None).unwrap();
+        device
+            .process_publish(
+                "test/test/org.astarte-platform.genericsensors.SamplingRate/1/enable",
+                &value,
+            )
+            .await
+            .unwrap()
+            .unwrap();
+
+        let value = AstarteSdk::serialize_individual(3, Some(Utc::now())).unwrap();
+        device
+            .process_publish("test/test/com.test.Everything/integer", &value)
+            .await
+            .unwrap()
+            .unwrap();
+
+        let event = events.recv().await.unwrap();
+        assert_eq!(
+            event.interface,
+            "org.astarte-platform.genericsensors.SamplingRate"
+        );
+        assert_eq!(event.path, "/1/enable");
+
+        let event = events.recv().await.unwrap();
+        assert_eq!(event.interface, "com.test.Everything");
+        assert!(matches!(
+            event.data,
+            crate::Aggregation::Individual(AstarteType::Integer(3))
+        ));
+
+        // dropped receivers are not kept around
+        drop(events);
+        device
+            .process_publish("test/test/com.test.Everything/integer", &value)
+            .await
+            .unwrap();
+        assert!(device.event_senders.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_interface_owns_path() {
+        let device = test_device().await;
+
+        assert!(device.interface_owns_path("com.test.Everything", "/integer"));
+        assert!(!device.interface_owns_path("com.test.Everything", "/missing"));
+        assert!(!device.interface_owns_path("com.test.Missing", "/integer"));
+
+        // parametric endpoint /%{sensor_id}/name
+        let interface = "org.astarte-platform.genericsensors.AvailableSensors";
+        assert!(device.interface_owns_path(interface, "/1/name"));
+        assert!(device.interface_owns_path(interface, "/sensor/name"));
+        assert!(!device.interface_owns_path(interface, "/name"));
+        assert!(!device.interface_owns_path(interface, "/1/2/name"));
+    }
+
+    #[tokio::test]
+    async fn test_log_diagnostics() {
+        let mut options = AstarteBuilder::new("test", "u-WraCwtK_G_fjJf63TiAw", "test", "test");
+        options.add_interface_files("examples/interfaces/").unwrap();
+        let device = options.connect_offline().await;
+
+        device.log_diagnostics();
+        assert!(device
+            .diagnostics()
+            .contains("realm=test device_id=u-WraCwt... interfaces=4"));
+
+        options.device_alias("kitchen-sensor");
+        let device = options.connect_offline().await;
+        assert_eq!(device.device_alias(), Some("kitchen-sensor"));
+        assert!(device
+            .diagnostics()
+            .contains("device_id=u-WraCwt... alias=kitchen-sensor interfaces=4"));
+    }
+
+    #[tokio::test]
+    async fn test_report_error() {
+        let mut options = AstarteBuilder::new("test", "test", "test", "test");
+        options.add_interface_files("examples/interfaces/").unwrap();
+        let device = options.connect_offline().await;
+
+        // the error interface must be registered
+        assert!(device.report_error(42, "sensor failure").await.is_err());
+
+        options
+            .interfaces_from_slice(&[ERROR_INTERFACE_JSON])
+            .unwrap();
+        let device = options.connect_offline().await;
+
+        device.report_error(42, "sensor failure").await.unwrap();
+        assert!(device.connection_stats().bytes_sent > 0);
+
+        let published = published(&device).await;
+        assert_eq!(published.len(), 1);
+        assert_eq!(
+            published[0].topic,
+            "test/test/org.astarte-platform.generic.errors.v1/error"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_record_events() {
+        let path =
+            std::env::temp_dir().join(format!("astarte-events-{}.jsonl", uuid::Uuid::new_v4()));
+
+        let mut options = AstarteBuilder::new("test", "test", "test", "test");
+        options
+            .add_interface_files("examples/interfaces/")
+            .unwrap()
+            .record_events_to(&path);
+        let device = options.connect_offline().await;
+
+        for i in 0..3 {
+            let value = AstarteSdk::serialize_individual(i, Some(Utc::now())).unwrap();
+            device
+                .process_publish("test/test/com.test.Everything/integer", &value)
+                .await
+                .unwrap();
+        }
+
+        let content = std::fs::read_to_string(&path).unwrap();
+        let lines: Vec<serde_json::Value> = content
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+
+        assert_eq!(lines.len(), 3);
+        for (i, line) in lines.iter().enumerate() {
+            assert_eq!(line["interface"], "com.test.Everything");
+            assert_eq!(line["path"], "/integer");
+            assert_eq!(line["value"], i);
+            assert!(line["timestamp"].is_string());
+        }
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_disconnect_reconnect() {
+        let device = test_device().await;
+
+        device.disconnect().await.unwrap();
+        assert!(device
+            .send_with_timestamp("com.test.Everything", "/integer", 3, Utc::now())
+            .await
+            .is_err());
+
+        device.reconnect().await.unwrap();
+        device
+            .send_with_timestamp("com.test.Everything", "/integer", 3, Utc::now())
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_publish_introspection() {
+        let device = test_device().await;
+
+        let introspection = device.interfaces.get_introspection_string();
+        let mut entries: Vec<&str> = introspection.split(';').collect();
+        entries.sort_unstable();
+        assert_eq!(
+            entries,
+            [
+                "com.test.Everything:0:1",
+                "org.astarte-platform.genericsensors.AvailableSensors:0:1",
+                "org.astarte-platform.genericsensors.Geolocation:1:0",
+                "org.astarte-platform.genericsensors.SamplingRate:1:0",
+            ]
+        );
+
+        device.publish_introspection().await.unwrap();
+
+        let stats = device.connection_stats();
+        assert_eq!(stats.total_publishes, 1);
+        assert_eq!(stats.bytes_sent, introspection.len() as u64);
+    }
+
+    #[tokio::test]
+    async fn test_publish_empty_cache() {
+        let db = AstarteSqliteDatabase::new("sqlite::memory:").await.unwrap();
+
+        let mut options = AstarteBuilder::new("test", "device", "test", "test");
+        options
+            .add_interface_files("examples/interfaces/")
+            .unwrap()
+            .with_database(db.clone());
+        let device = options.connect_offline().await;
+
+        assert_eq!(device.empty_cache_topic(), "test/device/control/emptyCache");
+        assert!(device.property_cache_is_empty().await.unwrap());
+
+        device.publish_empty_cache().await.unwrap();
+        assert_eq!(device.connection_stats().total_publishes, 1);
+
+        let value = AstarteSdk::serialize_individual("foo", None).unwrap();
+        db.store_prop(
+            "org.astarte-platform.genericsensors.AvailableSensors",
+            "/1/name",
+            &value,
+            0,
+        )
+        .await
+        .unwrap();
+        assert!(!device.property_cache_is_empty().await.unwrap());
+    }
+
+    #[test]
+    fn test_error_classification() {
+        use crate::interface::MappingType;
+        use crate::AstarteError;
+
+        let io_error = || std::io::Error::new(std::io::ErrorKind::Other, "test");
+
+        let cases = vec![
+            (
+                AstarteError::BsonClientError(rumqttc::ClientError::Request(
+                    async_channel::SendError(rumqttc::Request::PingReq),
+                )),
+                (true, true),
+            ),
+            (
+                AstarteError::ConnectionError(rumqttc::ConnectionError::Io(io_error())),
+                (true, true),
+            ),
+            (
+                AstarteError::DbError(sqlx::Error::PoolTimedOut),
+                (true, true),
+            ),
+            (AstarteError::PingTimeout, (true, true)),
+            (AstarteError::Timeout, (true, true)),
+            (AstarteError::PublishTimeout, (true, true)),
+            (AstarteError::IoError(io_error()), (true, false)),
+            (AstarteError::CertificatePinMismatch, (false, false)),
+            (
+                AstarteError::BsonSerError(bson::to_document(&1).unwrap_err()),
+                (false, false),
+            ),
+            (
+                AstarteError::InterfaceError("{}".parse::<Interface>().unwrap_err()),
+                (false, false),
+            ),
+            (
+                AstarteError::InterfaceDisabled("test".into()),
+                (false, false),
+            ),
+            (
+                AstarteError::AggregateValidation {
+                    errors: vec!["test".into()],
+                },
+                (false, false),
+            ),
+            (
+                AstarteError::DatabaseCorrupted("test".into()),
+                (false, false),
+            ),
+            (AstarteError::DeserializationError, (false, false)),
+            (AstarteError::FromBsonError, (false, false)),
+            (AstarteError::FromBsonArrayError, (false, false)),
+            (AstarteError::FloatError, (false, false)),
+            (AstarteError::SendError("test".into()), (false, false)),
+            (AstarteError::ReceiveError("test".into()), (false, false)),
+            (
+                AstarteError::TypeMismatch {
+                    expected: MappingType::Double,
+                    got: MappingType::String,
+                },
+                (false, false),
+            ),
+            (
+                AstarteError::BurstTooLarge { size: 2, max: 1 },
+                (false, false),
+            ),
+            (
+                AstarteError::PayloadTooLarge { size: 2, limit: 1 },
+                (false, false),
+            ),
+            (
+                AstarteError::InterfaceVersionDowngrade {
+                    name: "test".into(),
+                    registered: 2,
+                    new: 1,
+                },
+                (false, false),
+            ),
+            (
+                AstarteError::InterfaceMajorChanged {
+                    name: "test".into(),
+                    registered: 1,
+                    new: 2,
+                },
+                (false, false),
+            ),
+            (
+                AstarteError::WrongAggregation("test".into()),
+                (false, false),
+            ),
+            (AstarteError::Timeout.context("test"), (true, true)),
+            (
+                AstarteError::PartialBatch {
+                    sent: 1,
+                    failed: vec![("/test".into(), AstarteError::Timeout)],
+                },
+                (true, true),
+            ),
+            (
+                AstarteError::PartialBatch {
+                    sent: 1,
+                    failed: vec![
+                        ("/test".into(), AstarteError::Timeout),
+                        ("/other".into(), AstarteError::FloatError),
+                    ],
+                },
+                (false, false),
+            ),
+            (AstarteError::FloatError.context("test"), (false, false)),
+            (AstarteError::Reported("test".into()), (false, false)),
+            (AstarteError::Unreported, (false, false)),
+        ];
+
+        for (error, expected) in cases {
+            assert_eq!(error.classify(), expected, "{:?}", error);
+            assert_eq!(error.is_transient(), expected.0);
+            assert_eq!(error.is_recoverable(), expected.1);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_list_server_properties() {
+        let interface = "org.astarte-platform.genericsensors.SamplingRate";
+
+        let device = test_device().await;
+
+        assert!(device
+            .list_server_properties("org.astarte-platform.genericsensors.AvailableSensors")
+            .await
+            .is_err());
+        assert!(device
+            .list_server_properties("com.test.Missing")
+            .await
+            .is_err());
+        assert!(device
+            .list_server_properties(interface)
+            .await
+            .unwrap()
+            .is_empty());
+
+        let db = AstarteSqliteDatabase::new("sqlite::memory:").await.unwrap();
+        let device = test_device_with(|options| {
+            options.with_database(db.clone());
+        })
+        .await;
+
+        let enable = AstarteSdk::serialize_individual(true, None).unwrap();
+        let period = AstarteSdk::serialize_individual(10, None).unwrap();
+        let name = AstarteSdk::serialize_individual("foo", None).unwrap();
+        device
+            .process_publish(&format!("test/test/{}/1/enable", interface), &enable)
+            .await
+            .unwrap();
+        device
+            .process_publish(
+                "test/test/org.astarte-platform.genericsensors.AvailableSensors/1/name",
+                &name,
+            )
+            .await
+            .unwrap();
+        device
+            .process_publish(
+                &format!("test/test/{}/1/samplingPeriod", interface),
+                &period,
+            )
+            .await
+            .unwrap();
+        device
+            .process_publish(&format!("test/test/{}/2/enable", interface), &[])
+            .await
+            .unwrap();
+
+        let properties = device.list_server_properties(interface).await.unwrap();
+        assert_eq!(properties.len(), 2);
+        assert_eq!(properties["/1/enable"], AstarteType::Boolean(true));
+        assert_eq!(properties["/1/samplingPeriod"], AstarteType::Integer(10));
+
+        // nothing is asked to Astarte
+        assert!(published(&device).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_force_property_sync() {
+        use crate::{SyncReport, SyncStrategy};
+
+        let interface = "org.astarte-platform.genericsensors.SamplingRate";
+
+        let expected = vec![
+            (
+                SyncStrategy::ServerWins,
+                SyncReport {
+                    updated_from_server: 1,
+                    pushed_to_server: 1,
+                    conflicts: 2,
+                },
+                (true, None),
+            ),
+            (
+                SyncStrategy::DeviceWins,
+                SyncReport {
+                    updated_from_server: 1,
+                    pushed_to_server: 1,
+                    conflicts: 2,
+                },
+                (false, Some(AstarteType::Boolean(true))),
+            ),
+        ];
+
+        for (strategy, report, (first, second)) in expected {
+            let db = AstarteSqliteDatabase::new("sqlite::memory:").await.unwrap();
+
+            let mut options = AstarteBuilder::new("test", "test", "test", "test");
+            options
+                .add_interface_files("examples/interfaces/")
+                .unwrap()
+                .with_database(db.clone());
+            options.set_sync_strategy(strategy);
+            let device = options.connect_offline().await;
+
+            let cached = vec![
+                (interface, "/1/enable", false),
+                (interface, "/2/enable", true),
+            ];
+            for (interface, path, value) in cached {
+                let value = AstarteSdk::serialize_individual(value, None).unwrap();
+                db.store_prop(interface, path, &value, 1).await.unwrap();
+            }
+            let name = AstarteSdk::serialize_individual("foo", None).unwrap();
+            db.store_prop(
+                "org.astarte-platform.genericsensors.AvailableSensors",
+                "/1/name",
+                &name,
+                0,
+            )
+            .await
+            .unwrap();
+
+            let syncer = device.clone();
+            let sync =
+                tokio::spawn(
+                    async move { syncer.sync_properties(Duration::from_millis(200)).await },
+                );
+
+            tokio::time::sleep(Duration::from_millis(50)).await;
+
+            let enable = AstarteSdk::serialize_individual(true, None).unwrap();
+            let period = AstarteSdk::serialize_individual(10, None).unwrap();
+            device
+                .process_publish(&format!("test/test/{}/1/enable", interface), &enable)
+                .await
+                .unwrap();
+            device
+                .process_publish(
+                    &format!("test/test/{}/3/samplingPeriod", interface),
+                    &period,
+                )
+                .await
+                .unwrap();
+
+            assert_eq!(sync.await.unwrap().unwrap(), report);
+
+            let republished = published(&device)
+                .await
+                .iter()
+                .filter(|publish| {
+                    publish.topic
+                        == "test/test/org.astarte-platform.genericsensors.AvailableSensors/1/name"
+                })
+                .count();
+            assert_eq!(republished as u32, report.pushed_to_server);
+
+            // the conflicts follow the strategy, the new properties are always stored
+            assert_eq!(
+                device.get_property(interface, "/1/enable").await.unwrap(),
+                Some(AstarteType::Boolean(first))
+            );
+            assert_eq!(
+                device.get_property(interface, "/2/enable").await.unwrap(),
+                second
+            );
+            assert_eq!(
+                device
+                    .get_property(interface, "/3/samplingPeriod")
+                    .await
+                    .unwrap(),
+                Some(AstarteType::Integer(10))
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_custom_topic_prefix() {
+        let device = test_device_with(|options| {
+            options.with_custom_topic_prefix("tenant/test/");
+        })
+        .await;
+
+        assert_eq!(
+            device.build_topic("com.test.Everything", "/integer"),
+            "tenant/test/com.test.Everything/integer"
+        );
+        assert_eq!(device.empty_cache_topic(), "tenant/test/control/emptyCache");
+
+        let value = AstarteSdk::serialize_individual(true, None).unwrap();
+        let event = device
+            .process_publish(
+                "tenant/test/org.astarte-platform.genericsensors.SamplingRate/1/enable",
+                &value,
+            )
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            event.interface,
+            "org.astarte-platform.genericsensors.SamplingRate"
+        );
+        assert_eq!(event.path, "/1/enable");
+    }
+
+    #[tokio::test]
+    async fn test_realm_and_device_id() {
+        let mut options = AstarteBuilder::new("test_realm", "test_device", "secret", "test");
+        let device = options.connect_offline().await;
+
+        assert_eq!(device.realm(), "test_realm");
+        assert_eq!(device.device_id(), "test_device");
+    }
+
+    #[tokio::test]
+    async fn test_pairing_and_broker_url() {
+        let mut options =
+            AstarteBuilder::new("test", "test", "test", "https://api.example.com/pairing");
+        let device = options.connect_offline().await;
+
+        assert_eq!(device.pairing_url(), "https://api.example.com/pairing");
+        assert_eq!(device.broker_url(), "mqtts://localhost:8883");
+    }
+
+    #[tokio::test]
+    async fn test_registered_interfaces() {
+        use crate::interface::traits::Interface as InterfaceTrait;
+
+        let mut options = AstarteBuilder::new("test", "test", "test", "test");
+        options
+            .interfaces_from_slice(&[
+                include_str!(
+                    "../examples/interfaces/org.astarte-platform.genericsensors.SamplingRate.json"
+                ),
+                include_str!("../examples/interfaces/com.test.Everything.json"),
+                include_str!(
+                    "../examples/interfaces/org.astarte-platform.genericsensors.Geolocation.json"
+                ),
+            ])
+            .unwrap();
+        let device = options.connect_offline().await;
+
+        let names: Vec<String> = device
+            .registered_interfaces()
+            .map(|interface| interface.name().to_owned())
+            .collect();
+        assert_eq!(
+            names,
+            [
+                "com.test.Everything",
+                "org.astarte-platform.genericsensors.Geolocation",
+                "org.astarte-platform.genericsensors.SamplingRate",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_statistics_reset() {
+        let device = test_device().await;
+
+        for i in 0..5 {
+            device
+                .send_with_timestamp("com.test.Everything", "/integer", i, Utc::now())
+                .await
+                .unwrap();
+        }
+        assert_eq!(device.connection_stats().total_publishes, 5);
+
+        device.statistics_reset();
+        assert_eq!(device.connection_stats(), ConnectionStats::default());
+
+        for i in 0..3 {
+            device
+                .send_with_timestamp("com.test.Everything", "/integer", i, Utc::now())
+                .await
+                .unwrap();
+        }
+
+        let stats = device.connection_stats();
+        assert_eq!(stats.total_publishes, 3);
+        assert!(stats.bytes_sent > 0);
+    }
+
+    #[tokio::test]
+    async fn test_custom_serializer() {
+        use crate::serializer::{AstarteSerializer, MsgpackSerializer};
+        use crate::Aggregation;
+
+        let interface = "org.astarte-platform.genericsensors.SamplingRate";
+
+        let db = AstarteSqliteDatabase::new("sqlite::memory:").await.unwrap();
+        let device = test_device_with(|options| {
+            options.with_database(db.clone());
+            options.with_serializer(MsgpackSerializer);
+        })
+        .await;
+
+        let payload = MsgpackSerializer
+            .serialize(&Aggregation::Individual(AstarteType::Boolean(true)), None)
+            .unwrap();
+        let event = device
+            .process_publish(&format!("test/test/{}/1/enable", interface), &payload)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(matches!(
+            event.data,
+            Aggregation::Individual(AstarteType::Boolean(true))
+        ));
+
+        // stored as bson
+        assert_eq!(
+            db.load_prop(interface, "/1/enable", 1).await.unwrap(),
+            Some(AstarteType::Boolean(true))
+        );
+
+        device
+            .send_with_timestamp("com.test.Everything", "/integer", 42, Utc::now())
+            .await
+            .unwrap();
+        assert_eq!(device.connection_stats().total_publishes, 1);
+    }
+
+    #[tokio::test]
+    async fn test_publish_product_info() {
+        use crate::observability::ObservabilityHook;
+        use crate::ProductInfo;
+
+        #[derive(Default, Clone)]
+        struct PublishHook(std::sync::Arc<std::sync::Mutex<Vec<(String, String, usize)>>>);
+
+        impl ObservabilityHook for PublishHook {
+            fn on_publish(&self, interface: &str, path: &str, size: usize) {
+                self.0
+                    .lock()
+                    .unwrap()
+                    .push((interface.to_owned(), path.to_owned(), size));
+            }
+
+            fn on_receive(&self, _interface: &str, _path: &str, _size: usize) {}
+
+            fn on_error(&self, _error: &crate::AstarteError) {}
+        }
+
+        let info = ProductInfo {
+            hw_version: "rev2".into(),
+            sw_version: "1.4.0".into(),
+            model: "sensor-box".into(),
+        };
+
+        let mut options = AstarteBuilder::new("test", "test", "test", "test");
+        options.add_interface_files("examples/interfaces/").unwrap();
+        let device = options.connect_offline().await;
+
+        // the product info interface must be registered
+        assert!(device.publish_product_info(info.clone()).await.is_err());
+
+        let hook = PublishHook::default();
+        options
+            .interfaces_from_slice(&[PRODUCT_INFO_JSON])
+            .unwrap()
+            .with_observability_hook(hook.clone());
+        let device = options.connect_offline().await;
+
+        device.publish_product_info(info).await.unwrap();
+
+        let expected: Vec<_> = [
+            ("/hwVersion", "rev2"),
+            ("/swVersion", "1.4.0"),
+            ("/model", "sensor-box"),
+        ]
+        .iter()
+        .map(|(path, value)| {
+            let payload = AstarteSdk::serialize_individual(*value, None).unwrap();
+            (
+                crate::PRODUCT_INFO_INTERFACE.to_owned(),
+                path.to_string(),
+                payload.len(),
+            )
+        })
+        .collect();
+        assert_eq!(*hook.0.lock().unwrap(), expected);
+    }
+
+    #[tokio::test]
+    async fn test_max_payload_size() {
+        let device = test_device_with(|options| {
+            options.set_max_payload_size(1024);
+        })
+        .await;
+
+        let timestamp = Utc::now();
+        let overhead = AstarteSdk::serialize_individual("", Some(timestamp))
+            .unwrap()
+            .len();
+
+        let value = "a".repeat(1024 - overhead);
+        device
+            .send_with_timestamp("com.test.Everything", "/string", value, timestamp)
+            .await
+            .unwrap();
+        assert_eq!(device.connection_stats().total_publishes, 1);
+
+        let value = "a".repeat(1024 - overhead + 1);
+        let res = device
+            .send_with_timestamp("com.test.Everything", "/string", value, timestamp)
+            .await;
+        assert!(matches!(
+            res,
+            Err(crate::AstarteError::PayloadTooLarge {
+                size: 1025,
+                limit: 1024
+            })
+        ));
+        assert_eq!(device.connection_stats().total_publishes, 1);
+    }
+
+    #[tokio::test]
+    async fn test_dead_letter_queue() {
+        let db = AstarteSqliteDatabase::new("sqlite::memory:").await.unwrap();
+        let device = test_device_with(|options| {
+            options.with_database(db.clone());
+            options.enable_dead_letter_queue();
+        })
+        .await;
+
+        device.disconnect().await.unwrap();
+        device
+            .send_with_timestamp("com.test.Everything", "/integer", 42, Utc::now())
+            .await
+            .unwrap();
+        assert_eq!(device.connection_stats().total_publishes, 0);
+
+        let letters = db.load_dead_letters().await.unwrap();
+        assert_eq!(letters.len(), 1);
+        assert_eq!(letters[0].topic, "test/test/com.test.Everything/integer");
+
+        // still disconnected, the message is kept
+        assert!(device.drain_dead_letter_queue().await.is_err());
+        assert_eq!(db.load_dead_letters().await.unwrap().len(), 1);
+
+        // the retain flag is kept with the message
+        db.store_dead_letter(&letters[0].topic, 1, true, &letters[0].payload)
+            .await
+            .unwrap();
+
+        device.reconnect().await.unwrap();
+        assert_eq!(device.drain_dead_letter_queue().await.unwrap(), 2);
+        assert_eq!(device.connection_stats().total_publishes, 2);
+        assert!(db.load_dead_letters().await.unwrap().is_empty());
+
+        let retain: Vec<_> = published(&device)
+            .await
+            .into_iter()
+            .map(|publish| publish.retain)
+            .collect();
+        assert_eq!(retain, [false, true]);
+    }
+
+    #[tokio::test]
+    async fn test_dead_letter_queue_drained_on_connect() {
+        let port = mock_broker(Vec::new()).await;
+
+        let db = AstarteSqliteDatabase::new("sqlite::memory:").await.unwrap();
+        let device = test_device_with(|options| {
+            options.with_database(db.clone());
+            options.enable_dead_letter_queue();
+        })
+        .await;
+        device.eventloop.lock().await.options =
+            rumqttc::MqttOptions::new("test/test", "127.0.0.1", port);
+
+        // more messages than the request channel holds
+        let payload = AstarteSdk::serialize_individual(42, Some(Utc::now())).unwrap();
+        for _ in 0..60 {
+            db.store_dead_letter("test/test/com.test.Everything/integer", 0, false, &payload)
+                .await
+                .unwrap();
+        }
+
+        let mut poller = device.clone();
+        let polling = tokio::spawn(async move { while poller.poll().await.is_ok() {} });
+
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while !db.load_dead_letters().await.unwrap().is_empty() {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+        polling.abort();
+
+        assert!(device.connection_stats().total_publishes >= 60);
+    }
+
+    #[tokio::test]
+    async fn test_max_concurrent_publishes() {
+        let mut options = AstarteBuilder::new("test", "test", "test", "test");
+        options.add_interface_files("examples/interfaces/").unwrap();
+        assert!(options.max_concurrent_publishes(0).is_err());
+        options.max_concurrent_publishes(1).unwrap();
+        let device = options.connect_offline().await;
+
+        let permit = device.publish_permit().await.unwrap();
+        assert!(permit.is_some());
+
+        // the only slot is taken, the send waits for it
+        let res = tokio::time::timeout(
+            Duration::from_millis(50),
+            device.send_with_timestamp("com.test.Everything", "/integer", 42, Utc::now()),
+        )
+        .await;
+        assert!(res.is_err());
+        assert_eq!(device.connection_stats().total_publishes, 0);
+
+        drop(permit);
+        device
+            .send_with_timestamp("com.test.Everything", "/integer", 42, Utc::now())
+            .await
+            .unwrap();
+        assert_eq!(device.connection_stats().total_publishes, 1);
+
+        // the slot is released once the message is handed over, before the broker ack
+        let interface: Interface = GUARANTEED_INTERFACE.parse().unwrap();
+        let mut options = AstarteBuilder::new("test", "test", "test", "test");
+        options
+            .interfaces
+            .insert("com.test.Guaranteed".into(), interface);
+        options.await_puback(true);
+        options.max_concurrent_publishes(1).unwrap();
+        let device = options.connect_offline().await;
+
+        let sender = device.clone();
+        let send = tokio::spawn(async move {
+            sender
+                .send_individual("com.test.Guaranteed", "/value", 42, None)
+                .await
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(device.connection_stats().total_publishes, 1);
+
+        let permit = tokio::time::timeout(Duration::from_millis(50), device.publish_permit())
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(permit.is_some());
+        send.abort();
+    }
+
+    #[tokio::test]
+    async fn test_subscribe_property_changes() {
+        let device = test_device().await;
+
+        let mut changes = device.subscribe_property_changes();
+
+        let value = AstarteSdk::serialize_individual(3, Some(Utc::now())).unwrap();
+        device
+            .process_publish("test/test/com.test.Everything/integer", &value)
+            .await
+            .unwrap();
+
+        let value = AstarteSdk::serialize_individual(true, None).unwrap();
+        device
+            .process_publish(
+                "test/test/org.astarte-platform.genericsensors.SamplingRate/1/enable",
+                &value,
+            )
+            .await
+            .unwrap();
+        device
+            .process_publish(
+                "test/test/org.astarte-platform.genericsensors.SamplingRate/1/enable",
+                &[],
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(
+            changes.recv().await.unwrap(),
+            crate::PropertyChange {
+                interface: "org.astarte-platform.genericsensors.SamplingRate".into(),
+                path: "/1/enable".into(),
+                new_value: Some(AstarteType::Boolean(true)),
+            }
+        );
+        assert_eq!(changes.recv().await.unwrap().new_value, None);
+        assert!(changes.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_subscribe_datastream() {
+        let device = test_device().await;
+
+        let mut datastream = device.subscribe_datastream();
+
+        let value = AstarteSdk::serialize_individual(true, None).unwrap();
+        device
+            .process_publish(
+                "test/test/org.astarte-platform.genericsensors.SamplingRate/1/enable",
+                &value,
+            )
+            .await
+            .unwrap();
+
+        let timestamp = Utc.timestamp(1537449422, 0);
+        let value = AstarteSdk::serialize_individual(3, Some(timestamp)).unwrap();
+        device
+            .process_publish("test/test/com.test.Everything/integer", &value)
+            .await
+            .unwrap();
+
+        let value = AstarteSdk::serialize_individual(false, None).unwrap();
+        device
+            .process_publish(
+                "test/test/org.astarte-platform.genericsensors.SamplingRate/1/enable",
+                &value,
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(
+            datastream.recv().await.unwrap(),
+            crate::DatastreamEvent {
+                interface: "com.test.Everything".into(),
+                path: "/integer".into(),
+                value: AstarteType::Integer(3),
+                timestamp: Some(timestamp),
+            }
+        );
+        assert!(datastream.try_recv().is_err());
+    }
+
+    fn everything_with_version(major: i32, minor: i32) -> String {
+        let mut json: serde_json::Value = serde_json::from_str(include_str!(
+            "../examples/interfaces/com.test.Everything.json"
+        ))
+        .unwrap();
+        json["version_major"] = major.into();
+        json["version_minor"] = minor.into();
+
+        json.to_string()
+    }
+
+    #[tokio::test]
+    async fn test_apply_interface_patch() {
+        let device = test_device().await;
+        let clone = device.clone();
+
+        device
+            .apply_interface_patch(&everything_with_version(0, 2))
+            .await
+            .unwrap();
+
+        assert_eq!(clone.interface_version("com.test.Everything"), Some((0, 2)));
+        assert!(device
+            .interfaces
+            .get_introspection_string()
+            .contains("com.test.Everything:0:2"));
+        assert_eq!(device.connection_stats().total_publishes, 1);
+    }
+
+    #[tokio::test]
+    async fn test_apply_interface_patch_downgrade() {
+        let device = test_device().await;
+
+        for minor in [0, 1] {
+            let res = device
+                .apply_interface_patch(&everything_with_version(0, minor))
+                .await;
+            assert!(matches!(
+                res,
+                Err(crate::AstarteError::InterfaceVersionDowngrade { registered: 1, .. })
+            ));
+        }
+
+        assert_eq!(
+            device.interface_version("com.test.Everything"),
+            Some((0, 1))
+        );
+        assert_eq!(device.connection_stats().total_publishes, 0);
+    }
+
+    #[tokio::test]
+    async fn test_apply_interface_patch_major() {
+        let device = test_device().await;
+
+        let res = device
+            .apply_interface_patch(&everything_with_version(1, 2))
+            .await;
+        assert!(matches!(
+            res,
+            Err(crate::AstarteError::InterfaceMajorChanged {
+                registered: 0,
+                new: 1,
+                ..
+            })
+        ));
+
+        assert_eq!(
+            device.interface_version("com.test.Everything"),
+            Some((0, 1))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_trigger_property_resync() {
+        let interface = "org.astarte-platform.genericsensors.SamplingRate";
+
+        // only the first sensor is sent again before the timeout
+        let port = mock_broker(vec![(
+            format!("test/test/{}/1/enable", interface),
+            AstarteSdk::serialize_individual(true, None).unwrap(),
+        )])
+        .await;
+
+        let db = AstarteSqliteDatabase::new("sqlite::memory:").await.unwrap();
+        let device = test_device_with(|options| {
+            options.with_database(db.clone());
+            options.set_property_resync_timeout(Duration::from_millis(200));
+        })
+        .await;
+        device.eventloop.lock().await.options =
+            rumqttc::MqttOptions::new("test/test", "127.0.0.1", port);
+
+        let enable = AstarteSdk::serialize_individual(false, None).unwrap();
+        for sensor in ["1", "2"] {
+            db.store_prop(interface, &format!("/{}/enable", sensor), &enable, 1)
+                .await
+                .unwrap();
+        }
+
+        let resync = device.clone();
+        let resync = tokio::spawn(async move { resync.trigger_property_resync(interface).await });
+
+        let mut poller = device.clone();
+        let polling = tokio::spawn(async move { while poller.poll().await.is_ok() {} });
+
+        resync.await.unwrap().unwrap();
+        polling.abort();
+
+        assert_eq!(
+            db.load_prop(interface, "/1/enable", 1).await.unwrap(),
+            Some(AstarteType::Boolean(true))
+        );
+        // a missing answer doesn't remove the stored value
+        assert_eq!(
+            db.load_prop(interface, "/2/enable", 1).await.unwrap(),
+            Some(AstarteType::Boolean(false))
+        );
+    }
+
+    #[test]
+    fn test_error_context() {
+        use std::error::Error;
+
+        use crate::AstarteError;
+
+        let err = AstarteError::InterfaceDisabled("com.test.Everything".into())
+            .context("while sending /integer")
+            .context("while reporting the status");
+
+        assert_eq!(
+            err.to_string(),
+            "while reporting the status: while sending /integer: interface com.test.Everything is disabled"
+        );
+
+        let source = err.source().unwrap();
+        assert_eq!(
+            source.to_string(),
+            "while sending /integer: interface com.test.Everything is disabled"
+        );
+        assert!(matches!(
+            err,
+            AstarteError::Contextual { context, .. } if context == "while reporting the status"
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_interface_aggregation() {
+        use crate::interface::Aggregation;
+
+        let device = test_device().await;
+
+        assert_eq!(
+            device.interface_aggregation("com.test.Everything"),
+            Some(Aggregation::Individual)
+        );
+        assert_eq!(
+            device.interface_aggregation("org.astarte-platform.genericsensors.Geolocation"),
+            Some(Aggregation::Object)
+        );
+        assert_eq!(device.interface_aggregation("com.test.Missing"), None);
+
+        device
+            .send_individual("com.test.Everything", "/integer", 42, Some(Utc::now()))
+            .await
+            .unwrap();
+
+        let res = device
+            .send_individual(
+                "org.astarte-platform.genericsensors.Geolocation",
+                "/1/latitude",
+                AstarteType::try_from(45.0).unwrap(),
+                Some(Utc::now()),
+            )
+            .await;
+        assert!(matches!(
+            res,
+            Err(crate::AstarteError::WrongAggregation(interface))
+                if interface == "org.astarte-platform.genericsensors.Geolocation"
+        ));
+        assert_eq!(device.connection_stats().total_publishes, 1);
+    }
+
+    #[tokio::test]
+    async fn test_send_individual_receipt() {
+        let device = test_device().await;
+
+        let before = std::time::Instant::now();
+        let timestamp = Utc::now();
+        let receipt = device
+            .send_individual("com.test.Everything", "/integer", 42, Some(timestamp))
+            .await
+            .unwrap();
+
+        assert!(receipt.serialized_bytes > 0);
+        assert_eq!(
+            receipt.serialized_bytes,
+            AstarteSdk::serialize_individual(42, Some(timestamp))
+                .unwrap()
+                .len()
+        );
+        assert!(receipt.publish_queued_at >= before);
+        // the ack is not awaited
+        assert_eq!(receipt.packet_id, 0);
+    }
+
+    /// Database recording the state of the client when it's cleared by a factory reset
+    #[derive(Clone)]
+    struct ResetDatabase {
+        inner: AstarteSqliteDatabase,
+        device: Arc<Mutex<Option<AstarteSdk>>>,
+        // whether the connection was enabled and the broker the client was set to
+        cleared_with: Arc<Mutex<Option<(bool, (String, u16))>>>,
+        fail_clear: bool,
+    }
+
+    impl ResetDatabase {
+        async fn new(fail_clear: bool) -> Self {
+            ResetDatabase {
+                inner: AstarteSqliteDatabase::new("sqlite::memory:").await.unwrap(),
+                device: Default::default(),
+                cleared_with: Default::default(),
+                fail_clear,
+            }
+        }
+
+        /// A device with a stored property, using this database
+        async fn device(&self, options: &mut AstarteBuilder) -> AstarteSdk {
+            options.add_interface_files("examples/interfaces/").unwrap();
+            options.with_database(self.clone());
+            let device = options.connect_offline().await;
+            *self.device.lock().unwrap() = Some(device.clone());
+
+            let value = AstarteSdk::serialize_individual("foo", None).unwrap();
+            self.store_prop(
+                "org.astarte-platform.genericsensors.AvailableSensors",
+                "/1/name",
+                &value,
+                0,
+            )
+            .await
+            .unwrap();
+
+            device
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl AstarteDatabase for ResetDatabase {
+        async fn store_prop(
+            &self,
+            interface: &str,
+            path: &str,
+            value: &[u8],
+            interface_major: i32,
+        ) -> Result<(), crate::AstarteError> {
+            self.inner
+                .store_prop(interface, path, value, interface_major)
+                .await
+        }
+
+        async fn load_prop(
+            &self,
+            interface: &str,
+            path: &str,
+            interface_major: i32,
+        ) -> Result<Option<AstarteType>, crate::AstarteError> {
+            self.inner.load_prop(interface, path, interface_major).await
+        }
+
+        async fn delete_prop(
+            &self,
+            interface: &str,
+            path: &str,
+        ) -> Result<(), crate::AstarteError> {
+            self.inner.delete_prop(interface, path).await
+        }
+
+        async fn clear(&self) -> Result<(), crate::AstarteError> {
+            let device = self.device.lock().unwrap().clone().unwrap();
+            let broker = device.eventloop.lock().await.options.broker_address();
+            *self.cleared_with.lock().unwrap() =
+                Some((*device.connection_enabled_rx.borrow(), broker));
+
+            if self.fail_clear {
+                return Err(crate::AstarteError::Reported("clear failed".into()));
+            }
+
+            self.inner.clear().await
+        }
+
+        async fn load_all_props(&self) -> Result<Vec<crate::StoredProp>, crate::AstarteError> {
+            self.inner.load_all_props().await
+        }
+    }
+
+    /// Builder whose pairing reuses a pre-issued certificate, without calling the Pairing API
+    fn pre_paired_builder() -> AstarteBuilder {
+        let mut options = AstarteBuilder::new("test", "test", "test", "test");
+        options.client_certificate = Some((Vec::new(), rustls::PrivateKey(Vec::new())));
+
+        options
+    }
+
+    #[tokio::test]
+    async fn test_factory_reset() {
+        let db = ResetDatabase::new(false).await;
+        let mut device = db.device(&mut pre_paired_builder()).await;
+        device.build_options.broker_url = url::Url::parse("mqtt://127.0.0.1:1883").unwrap();
+
+        device.factory_reset().await.unwrap();
+
+        // the database is cleared once disconnected and set to the new credentials
+        let broker = ("127.0.0.1".to_owned(), 1883);
+        assert_eq!(
+            *db.cleared_with.lock().unwrap(),
+            Some((false, broker.clone()))
+        );
+        assert!(db.load_all_props().await.unwrap().is_empty());
+
+        assert_eq!(
+            device.eventloop.lock().await.options.broker_address(),
+            broker
+        );
+        assert!(*device.connection_enabled_rx.borrow());
+    }
+
+    #[tokio::test]
+    async fn test_factory_reset_rollback() {
+        let db = ResetDatabase::new(false).await;
+        let mut options = AstarteBuilder::new("test", "test", "test", "not a pairing url");
+        let device = db.device(&mut options).await;
+
+        // pairing fails, nothing is reset
+        assert!(device.factory_reset().await.is_err());
+
+        assert_eq!(*db.cleared_with.lock().unwrap(), None);
+        assert_eq!(db.load_all_props().await.unwrap().len(), 1);
+        assert!(*device.connection_enabled_rx.borrow());
+        device
+            .send_with_timestamp("com.test.Everything", "/integer", 42, Utc::now())
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_factory_reset_clear_rollback() {
+        let db = ResetDatabase::new(true).await;
+        let mut device = db.device(&mut pre_paired_builder()).await;
+        device.build_options.broker_url = url::Url::parse("mqtt://127.0.0.1:1883").unwrap();
+
+        // clearing the database fails after the other steps, they are rolled back
+        assert!(device.factory_reset().await.is_err());
+
+        assert_eq!(
+            *db.cleared_with.lock().unwrap(),
+            Some((false, ("127.0.0.1".to_owned(), 1883)))
+        );
+        assert_eq!(db.load_all_props().await.unwrap().len(), 1);
+        assert_eq!(
+            device.eventloop.lock().await.options.broker_address(),
+            ("localhost".to_owned(), 8883)
+        );
+        assert!(*device.connection_enabled_rx.borrow());
+    }
+
+    #[tokio::test]
+    async fn test_get_mapping() {
+        use crate::interface::{Mapping, MappingType, Reliability, Retention};
+
+        const SENSORS: &str = r#"
+        {
+            "interface_name": "com.test.Sensors",
+            "version_major": 0,
+            "version_minor": 1,
+            "type": "datastream",
+            "ownership": "device",
+            "mappings": [
+                {
+                    "endpoint": "/%{sensor_id}/value",
+                    "type": "double",
+                    "reliability": "unique",
+                    "retention": "stored",
+                    "expiry": 60,
+                    "explicit_timestamp": true
+                },
+                {
+                    "endpoint": "/status",
+                    "type": "string"
+                }
+            ]
+        }
+        "#;
+
+        let mut options = AstarteBuilder::new("test", "test", "test", "test");
+        options.interfaces_from_slice(&[SENSORS]).unwrap();
+        let device = options.connect_offline().await;
+
+        let mapping_ref = device.get_mapping("com.test.Sensors", "/1/value").unwrap();
+        let mapping = mapping_ref.mapping();
+        assert_eq!(mapping.endpoint(), "/%{sensor_id}/value");
+        assert_eq!(mapping.mapping_type(), MappingType::Double);
+        match mapping {
+            Mapping::Datastream(mapping) => {
+                assert_eq!(mapping.reliability, Reliability::Unique);
+                assert_eq!(mapping.retention, Retention::Stored);
+                assert_eq!(mapping.expiry, Some(60));
+                assert!(mapping.explicit_timestamp);
+            }
+            mapping => panic!("expected a datastream mapping, got {:?}", mapping),
+        }
+
+        let mapping_ref = device.get_mapping("com.test.Sensors", "/status").unwrap();
+        let mapping = mapping_ref.mapping();
+        assert_eq!(mapping.endpoint(), "/status");
+        assert_eq!(mapping.mapping_type(), MappingType::String);
+        match mapping {
+            Mapping::Datastream(mapping) => {
+                assert_eq!(mapping.reliability, Reliability::Unreliable);
+                assert_eq!(mapping.retention, Retention::Discard);
+                assert_eq!(mapping.expiry, None);
+                assert!(!mapping.explicit_timestamp);
+            }
+            mapping => panic!("expected a datastream mapping, got {:?}", mapping),
+        }
+
+        assert!(device
+            .get_mapping("com.test.Sensors", "/1/missing")
+            .is_none());
+        assert!(device.get_mapping("com.test.Missing", "/status").is_none());
+    }
+
+    /// Client with the example interfaces, never connected to a broker
+    async fn test_device() -> AstarteSdk {
+        test_device_with(|_| {}).await
+    }
+
+    /// Like [`test_device`], with the builder configured by `configure` before connecting
+    async fn test_device_with<F>(configure: F) -> AstarteSdk
+    where
+        F: FnOnce(&mut AstarteBuilder),
+    {
+        let mut options = AstarteBuilder::new("test", "test", "test", "test");
+        options.add_interface_files("examples/interfaces/").unwrap();
+        configure(&mut options);
+
+        options.connect_offline().await
+    }
+
+    /// Publishes handed to the event loop of a client that is not connected
+    async fn published(device: &AstarteSdk) -> Vec<rumqttc::Publish> {
+        let eventloop = device.eventloop.lock().await;
+
+        let mut published = Vec::new();
+        while let Ok(request) = eventloop.requests_rx.try_recv() {
+            if let rumqttc::Request::Publish(publish) = request {
+                published.push(publish);
+            }
+        }
+
+        published
+    }
+
+    #[tokio::test]
+    async fn test_send_individual_qos() {
+        const RELIABILITY: &str = r#"
+        {
+            "interface_name": "com.test.Reliability",
+            "version_major": 0,
+            "version_minor": 1,
+            "type": "datastream",
+            "ownership": "device",
+            "mappings": [
+                {
+                    "endpoint": "/unreliable",
+                    "type": "integer",
+                    "reliability": "unreliable"
+                },
+                {
+                    "endpoint": "/guaranteed",
+                    "type": "integer",
+                    "reliability": "guaranteed"
+                },
+                {
+                    "endpoint": "/unique",
+                    "type": "integer",
+                    "reliability": "unique"
+                }
+            ]
+        }
+        "#;
+
+        let mut options = AstarteBuilder::new("test", "test", "test", "test");
+        options.interfaces_from_slice(&[RELIABILITY]).unwrap();
+        let device = options.connect_offline().await;
+
+        for path in ["/unreliable", "/guaranteed", "/unique"] {
+            device
+                .send_individual("com.test.Reliability", path, 42, None)
+                .await
+                .unwrap();
+        }
+
+        let qos: Vec<_> = published(&device)
+            .await
+            .into_iter()
+            .map(|publish| (publish.topic, publish.qos))
+            .collect();
+        assert_eq!(
+            qos,
+            [
+                (
+                    "test/test/com.test.Reliability/unreliable".to_owned(),
+                    rumqttc::QoS::AtMostOnce
+                ),
+                (
+                    "test/test/com.test.Reliability/guaranteed".to_owned(),
+                    rumqttc::QoS::AtLeastOnce
+                ),
+                (
+                    "test/test/com.test.Reliability/unique".to_owned(),
+                    rumqttc::QoS::ExactlyOnce
+                ),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_send_individual_retain() {
+        const RETENTION: &str = r#"
+        {
+            "interface_name": "com.test.Retention",
+            "version_major": 0,
+            "version_minor": 1,
+            "type": "datastream",
+            "ownership": "device",
+            "mappings": [
+                {
+                    "endpoint": "/discard",
+                    "type": "integer",
+                    "retention": "discard"
+                },
+                {
+                    "endpoint": "/volatile",
+                    "type": "integer",
+                    "retention": "volatile"
+                },
+                {
+                    "endpoint": "/stored",
+                    "type": "integer",
+                    "retention": "stored"
+                },
+                {
+                    "endpoint": "/default",
+                    "type": "integer"
+                }
+            ]
+        }
+        "#;
+
+        let mut options = AstarteBuilder::new("test", "test", "test", "test");
+        options.interfaces_from_slice(&[RETENTION]).unwrap();
+        let device = options.connect_offline().await;
+
+        for path in ["/discard", "/volatile", "/stored", "/default"] {
+            device
+                .send_individual("com.test.Retention", path, 42, None)
+                .await
+                .unwrap();
+        }
+
+        let retain: Vec<_> = published(&device)
+            .await
+            .into_iter()
+            .map(|publish| (publish.topic, publish.retain))
+            .collect();
+        assert_eq!(
+            retain,
+            [
+                ("test/test/com.test.Retention/discard".to_owned(), false),
+                ("test/test/com.test.Retention/volatile".to_owned(), false),
+                ("test/test/com.test.Retention/stored".to_owned(), true),
+                ("test/test/com.test.Retention/default".to_owned(), false),
+            ]
+        );
+    }
 
-        let data: AstarteType = data.into();
+    #[tokio::test]
+    async fn test_send_individual_batch_for_interface() {
+        use crate::AstarteError;
+        use std::collections::HashMap;
 
-        let buf = AstarteSdk::serialize_individual(data.clone(), timestamp)?;
+        const BATCH: &str = r#"
+        {
+            "interface_name": "com.test.Batch",
+            "version_major": 0,
+            "version_minor": 1,
+            "type": "datastream",
+            "ownership": "device",
+            "mappings": [
+                {
+                    "endpoint": "/integer",
+                    "type": "integer"
+                },
+                {
+                    "endpoint": "/string",
+                    "type": "string"
+                }
+            ]
+        }
+        "#;
 
-        if cfg!(debug_assertions) {
-            self.interfaces
-                .validate_send(interface_name, interface_path, &buf, &timestamp)?;
+        let mut options = AstarteBuilder::new("test", "test", "test", "test");
+        options.interfaces_from_slice(&[BATCH]).unwrap();
+        options.set_max_payload_size(64);
+        let device = options.connect_offline().await;
+
+        // nothing is sent if a value is invalid
+        let invalid: HashMap<&str, AstarteType> = vec![
+            ("/integer", AstarteType::Integer(42)),
+            ("/missing", AstarteType::Integer(42)),
+        ]
+        .into_iter()
+        .collect();
+        assert!(matches!(
+            device
+                .send_individual_batch_for_interface("com.test.Batch", invalid, None)
+                .await,
+            Err(AstarteError::SendError(_))
+        ));
+        let mismatch: HashMap<&str, AstarteType> = vec![
+            ("/integer", AstarteType::Integer(42)),
+            ("/string", AstarteType::Integer(42)),
+        ]
+        .into_iter()
+        .collect();
+        assert!(matches!(
+            device
+                .send_individual_batch_for_interface("com.test.Batch", mismatch, None)
+                .await,
+            Err(AstarteError::TypeMismatch { .. })
+        ));
+        assert!(published(&device).await.is_empty());
+
+        let values: HashMap<&str, AstarteType> = vec![
+            ("/integer", AstarteType::Integer(42)),
+            ("/string", AstarteType::String("hello".into())),
+        ]
+        .into_iter()
+        .collect();
+        device
+            .send_individual_batch_for_interface("com.test.Batch", values, None)
+            .await
+            .unwrap();
+        let mut topics: Vec<_> = published(&device)
+            .await
+            .into_iter()
+            .map(|publish| publish.topic)
+            .collect();
+        topics.sort();
+        assert_eq!(
+            topics,
+            [
+                "test/test/com.test.Batch/integer",
+                "test/test/com.test.Batch/string"
+            ]
+        );
+
+        // the string is bigger than the maximum payload size
+        let values: HashMap<&str, AstarteType> = vec![
+            ("/integer", AstarteType::Integer(42)),
+            ("/string", AstarteType::String("x".repeat(100))),
+        ]
+        .into_iter()
+        .collect();
+        match device
+            .send_individual_batch_for_interface("com.test.Batch", values, None)
+            .await
+        {
+            Err(AstarteError::PartialBatch { sent, failed }) => {
+                assert_eq!(sent, 1);
+                assert_eq!(failed.len(), 1);
+                assert_eq!(failed[0].0, "/string");
+                assert!(matches!(failed[0].1, AstarteError::PayloadTooLarge { .. }));
+            }
+            res => panic!("expected a partial batch error, got {:?}", res),
         }
+        assert_eq!(published(&device).await.len(), 1);
+    }
 
-        if self
-            .check_property_on_send(interface_name, interface_path, data.clone())
-            .await?
+    #[tokio::test]
+    async fn test_unset_all_device_properties() {
+        const DEVICE_PROPERTIES: &str = r#"
         {
-            debug!("property was already sent, no need to send it again");
-            return Ok(());
+            "interface_name": "com.test.DeviceProperties",
+            "version_major": 0,
+            "version_minor": 1,
+            "type": "properties",
+            "ownership": "device",
+            "mappings": [
+                {
+                    "endpoint": "/%{id}/value",
+                    "type": "integer",
+                    "allow_unset": true
+                }
+            ]
         }
+        "#;
 
-        self.client
-            .publish(
-                self.client_id() + "/" + interface_name.trim_matches('/') + interface_path,
-                self.interfaces
-                    .get_mqtt_reliability(interface_name, interface_path),
-                false,
-                buf,
+        let db = AstarteSqliteDatabase::new("sqlite::memory:").await.unwrap();
+        let value = AstarteSdk::serialize_individual("foo", None).unwrap();
+        for path in ["/1/name", "/1/unit", "/2/name"] {
+            db.store_prop(
+                "org.astarte-platform.genericsensors.AvailableSensors",
+                path,
+                &value,
+                0,
             )
-            .await?;
+            .await
+            .unwrap();
+        }
+        let value = AstarteSdk::serialize_individual(42, None).unwrap();
+        for path in ["/1/value", "/2/value"] {
+            db.store_prop("com.test.DeviceProperties", path, &value, 0)
+                .await
+                .unwrap();
+        }
+        // server-owned properties are kept
+        let value = AstarteSdk::serialize_individual(true, None).unwrap();
+        db.store_prop(
+            "org.astarte-platform.genericsensors.SamplingRate",
+            "/1/enable",
+            &value,
+            1,
+        )
+        .await
+        .unwrap();
 
-        // we store the property in the database after it has been successfully sent
-        self.store_property_on_send(interface_name, interface_path, data)
-            .await?;
-        Ok(())
+        let mut options = AstarteBuilder::new("test", "test", "test", "test");
+        options
+            .add_interface_files("examples/interfaces/")
+            .unwrap()
+            .interfaces_from_slice(&[DEVICE_PROPERTIES])
+            .unwrap()
+            .with_database(db.clone());
+        let device = options.connect_offline().await;
+
+        device.unset_all_device_properties().await.unwrap();
+
+        // the AvailableSensors mappings don't allow unsetting their values
+        let published = published(&device).await;
+        assert_eq!(published.len(), 2);
+        assert!(published.iter().all(|publish| publish.payload.is_empty()
+            && publish
+                .topic
+                .starts_with("test/test/com.test.DeviceProperties/")));
+
+        let remaining = db.load_all_props().await.unwrap();
+        assert_eq!(remaining.len(), 4);
+        assert!(remaining
+            .iter()
+            .all(|prop| prop.interface != "com.test.DeviceProperties"));
     }
 
-    /// checks if a property mapping has alredy been sent, so we don't have to send the same thing again
-    /// returns true if property was already sent
-    async fn check_property_on_send<D>(
-        &self,
-        interface_name: &str,
-        interface_path: &str,
-        data: D,
-    ) -> Result<bool, AstarteError>
-    where
-        D: Into<AstarteType>,
-    {
-        if let Some(db) = &self.database {
-            //if database is present
+    #[tokio::test]
+    async fn test_send_individual_timeout() {
+        let interface: Interface = GUARANTEED_INTERFACE.parse().unwrap();
 
-            let data: AstarteType = data.into();
+        let mut options = AstarteBuilder::new("test", "test", "test", "test");
+        options
+            .interfaces
+            .insert("com.test.Guaranteed".into(), interface);
+        options.await_puback(true);
+        let device = options.connect_offline().await;
 
-            let mapping = self
-                .interfaces
-                .get_mapping(interface_name, interface_path)
-                .ok_or_else(|| AstarteError::SendError("Mapping doesn't exist".into()))?;
+        // the broker never acknowledges the message
+        let res = device
+            .send_individual_timeout(
+                "com.test.Guaranteed",
+                "/value",
+                AstarteType::Integer(42),
+                None,
+                Duration::from_millis(50),
+            )
+            .await;
+        assert!(matches!(res, Err(crate::AstarteError::PublishTimeout)));
 
-            if let crate::interface::Mapping::Properties(_) = mapping {
-                //if mapping is a property
-                let db_data = db.load_prop(interface_name, interface_path, 0).await?;
+        let sender = device.clone();
+        let send = tokio::spawn(async move {
+            sender
+                .send_individual_timeout(
+                    "com.test.Guaranteed",
+                    "/value",
+                    AstarteType::Integer(42),
+                    None,
+                    Duration::from_secs(5),
+                )
+                .await
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        for pkid in 1..=2 {
+            device.pubacks.lock().unwrap().outgoing(pkid);
+        }
+        device.pubacks.lock().unwrap().acked(2);
+        send.await.unwrap().unwrap();
+    }
 
-                if let Some(db_data) = db_data {
-                    // if already in db
-                    if db_data == data {
-                        return Ok(true);
+    /// Behaviour of the broker started by [`mock_broker_with`]
+    #[derive(Default)]
+    struct MockBroker {
+        /// Sent with QoS 0 right after the CONNACK
+        publishes: Vec<(String, Vec<u8>)>,
+        /// Delay of the PUBACK of the QoS 1 publishes, never acknowledged if `None`
+        puback_delay: Option<Duration>,
+    }
+
+    /// Accept a client on a local port, answer its CONNECT and send it `publishes` with QoS 0
+    pub(crate) async fn mock_broker(publishes: Vec<(String, Vec<u8>)>) -> u16 {
+        mock_broker_with(MockBroker {
+            publishes,
+            ..Default::default()
+        })
+        .await
+    }
+
+    /// Accept a client on a local port and answer it as described by `broker`
+    async fn mock_broker_with(broker: MockBroker) -> u16 {
+        use tokio::io::AsyncWriteExt;
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+
+            // the CONNECT packet
+            read_packet(&mut socket).await.unwrap();
+            socket.write_all(&[0x20, 0x02, 0x00, 0x00]).await.unwrap();
+
+            for (topic, payload) in broker.publishes {
+                let mut packet = vec![0x30];
+                let mut len = 2 + topic.len() + payload.len();
+                loop {
+                    let byte = (len % 128) as u8;
+                    len /= 128;
+                    if len == 0 {
+                        packet.push(byte);
+                        break;
                     }
+                    packet.push(byte | 0x80);
                 }
+                packet.extend_from_slice(&(topic.len() as u16).to_be_bytes());
+                packet.extend_from_slice(topic.as_bytes());
+                packet.extend_from_slice(&payload);
+
+                socket.write_all(&packet).await.unwrap();
             }
-        }
 
-        Ok(false)
+            // keep the connection open, answering the pings and the publishes with QoS 1 if
+            // configured to, ignoring the rest
+            while let Ok((header, body)) = read_packet(&mut socket).await {
+                let response = match (header, broker.puback_delay) {
+                    // PINGREQ
+                    (0xc0, _) => vec![0xd0, 0x00],
+                    // PUBLISH with QoS 1
+                    (header, Some(delay)) if header & 0xf6 == 0x32 => {
+                        let topic_len = usize::from(u16::from_be_bytes([body[0], body[1]]));
+
+                        tokio::time::sleep(delay).await;
+                        vec![0x40, 0x02, body[2 + topic_len], body[3 + topic_len]]
+                    }
+                    _ => continue,
+                };
+
+                if socket.write_all(&response).await.is_err() {
+                    break;
+                }
+            }
+        });
+
+        port
     }
 
-    async fn store_property_on_send<D>(
-        &self,
-        interface_name: &str,
-        interface_path: &str,
-        data: D,
-    ) -> Result<bool, AstarteError>
-    where
-        D: Into<AstarteType>,
-    {
-        if let Some(db) = &self.database {
-            //if database is present
+    /// A local port nothing listens on, to make the connection fail
+    pub(crate) async fn closed_port() -> u16 {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        listener.local_addr().unwrap().port()
+    }
 
-            let data: AstarteType = data.into();
+    /// Read an MQTT packet sent by the client, returns its first byte and its body
+    async fn read_packet(socket: &mut tokio::net::TcpStream) -> std::io::Result<(u8, Vec<u8>)> {
+        use tokio::io::AsyncReadExt;
 
-            let mapping = self
-                .interfaces
-                .get_mapping(interface_name, interface_path)
-                .ok_or_else(|| AstarteError::SendError("Mapping doesn't exist".into()))?;
+        let header = socket.read_u8().await?;
 
-            if let crate::interface::Mapping::Properties(_) = mapping {
-                //if mapping is a property
-                let bin = AstarteSdk::serialize_individual(data, None)?;
-                db.store_prop(interface_name, interface_path, &bin, 0)
-                    .await?;
-                debug!("Stored new property in database");
+        let mut len = 0;
+        let mut shift = 0;
+        loop {
+            let byte = socket.read_u8().await?;
+            len += usize::from(byte & 0x7f) << shift;
+            if byte & 0x80 == 0 {
+                break;
             }
+            shift += 7;
         }
 
-        Ok(false)
+        let mut body = vec![0; len];
+        socket.read_exact(&mut body).await?;
+
+        Ok((header, body))
     }
 
-    /// Serialize an astarte type into a vec of bytes
-    fn serialize_individual<D>(
-        data: D,
-        timestamp: Option<chrono::DateTime<chrono::Utc>>,
-    ) -> Result<Vec<u8>, AstarteError>
-    where
-        D: Into<AstarteType>,
-    {
-        AstarteSdk::serialize(data.into().into(), timestamp)
+    #[tokio::test]
+    async fn test_recv_with_filter() {
+        let prefix = "test/test/org.astarte-platform.genericsensors.SamplingRate";
+        let port = mock_broker(vec![
+            (
+                format!("{}/1/enable", prefix),
+                AstarteSdk::serialize_individual(true, None).unwrap(),
+            ),
+            (
+                format!("{}/2/enable", prefix),
+                AstarteSdk::serialize_individual(false, None).unwrap(),
+            ),
+            (
+                format!("{}/3/samplingPeriod", prefix),
+                AstarteSdk::serialize_individual(10, None).unwrap(),
+            ),
+        ])
+        .await;
+
+        let mut device = test_device().await;
+        device.eventloop.lock().await.options =
+            rumqttc::MqttOptions::new("test/test", "127.0.0.1", port);
+
+        let data = device
+            .recv_with_filter(|data| data.path.starts_with("/3/"), Duration::from_secs(5))
+            .await
+            .unwrap();
+        assert_eq!(data.path, "/3/samplingPeriod");
+        assert!(matches!(
+            data.data,
+            crate::Aggregation::Individual(AstarteType::Integer(10))
+        ));
+
+        // no other values are sent
+        assert!(matches!(
+            device
+                .recv_with_filter(|_| true, Duration::from_millis(100))
+                .await,
+            Err(crate::AstarteError::Timeout)
+        ));
     }
 
-    // ------------------------------------------------------------------------
-    // object types
-    // ------------------------------------------------------------------------
+    #[tokio::test]
+    async fn test_recv_batch() {
+        let prefix = "test/test/org.astarte-platform.genericsensors.SamplingRate";
+        let port = mock_broker(
+            (1..=5)
+                .map(|i| {
+                    (
+                        format!("{}/{}/samplingPeriod", prefix, i),
+                        AstarteSdk::serialize_individual(i, None).unwrap(),
+                    )
+                })
+                .collect(),
+        )
+        .await;
 
-    /// helper function to convert from an HashMap of AstarteType to an HashMap of Bson
-    pub fn to_bson_map(data: HashMap<&str, AstarteType>) -> HashMap<&str, Bson> {
-        data.into_iter().map(|f| (f.0, f.1.into())).collect()
+        let mut device = test_device().await;
+        device.eventloop.lock().await.options =
+            rumqttc::MqttOptions::new("test/test", "127.0.0.1", port);
+
+        let batch = device.recv_batch(3, Duration::from_secs(5)).await.unwrap();
+        let paths: Vec<_> = batch.iter().map(|data| data.path.as_str()).collect();
+        assert_eq!(
+            paths,
+            [
+                "/1/samplingPeriod",
+                "/2/samplingPeriod",
+                "/3/samplingPeriod"
+            ]
+        );
+
+        // only the remaining values are received before the timeout
+        let batch = device
+            .recv_batch(10, Duration::from_millis(200))
+            .await
+            .unwrap();
+        assert_eq!(batch.len(), 2);
+
+        // the batch isn't allocated upfront
+        let batch = device
+            .recv_batch(usize::MAX, Duration::from_millis(50))
+            .await
+            .unwrap();
+        assert!(batch.is_empty());
     }
 
-    /// Serialize a group of astarte types to a vec of bytes, representing an object
-    fn serialize_object<T>(
-        data: T,
-        timestamp: Option<chrono::DateTime<chrono::Utc>>,
-    ) -> Result<Vec<u8>, AstarteError>
-    where
-        T: serde::Serialize,
+    const EXPIRING_PROPERTIES: &str = r#"
     {
-        let doc = to_document(&data)?;
+        "interface_name": "com.test.Expiring",
+        "version_major": 0,
+        "version_minor": 1,
+        "type": "properties",
+        "ownership": "device",
+        "mappings": [
+            {
+                "endpoint": "/token",
+                "type": "string",
+                "allow_unset": true
+            },
+            {
+                "endpoint": "/permanent",
+                "type": "string"
+            }
+        ]
+    }
+    "#;
+
+    /// Client with the [`EXPIRING_PROPERTIES`] interface, the clock is paused so the timers
+    /// expire as soon as the test waits for them
+    async fn expiring_device() -> AstarteSdk {
+        tokio::time::pause();
 
-        AstarteSdk::serialize(Bson::Document(doc), timestamp)
+        let mut options = AstarteBuilder::new("test", "test", "test", "test");
+        options
+            .interfaces_from_slice(&[EXPIRING_PROPERTIES])
+            .unwrap();
+        options.connect_offline().await
     }
 
-    async fn send_object_with_timestamp_impl<T>(
-        &self,
-        interface_name: &str,
-        interface_path: &str,
-        data: T,
-        timestamp: Option<chrono::DateTime<chrono::Utc>>,
-    ) -> Result<(), AstarteError>
-    where
-        T: serde::Serialize,
-    {
-        let buf = AstarteSdk::serialize_object(data, timestamp)?;
+    /// Topics of the unsets published by the device
+    async fn unsets(device: &AstarteSdk) -> Vec<String> {
+        published(device)
+            .await
+            .into_iter()
+            .filter(|publish| publish.payload.is_empty())
+            .map(|publish| publish.topic)
+            .collect()
+    }
 
-        if cfg!(debug_assertions) {
-            self.interfaces
-                .validate_send(interface_name, interface_path, &buf, &timestamp)?;
-        }
+    #[tokio::test]
+    async fn test_set_property_expiry() {
+        let device = expiring_device().await;
 
-        self.client
-            .publish(
-                self.client_id() + "/" + interface_name.trim_matches('/') + interface_path,
-                self.interfaces
-                    .get_mqtt_reliability(interface_name, interface_path),
-                false,
-                buf,
+        assert!(device
+            .set_property_expiry(
+                "com.test.Expiring",
+                "/permanent",
+                Duration::from_millis(100)
             )
-            .await?;
+            .await
+            .is_err());
+        assert!(device
+            .set_property_expiry("com.test.Missing", "/token", Duration::from_millis(100))
+            .await
+            .is_err());
 
-        Ok(())
+        device
+            .set_property_expiry("com.test.Expiring", "/token", Duration::from_millis(100))
+            .await
+            .unwrap();
+        assert!(unsets(&device).await.is_empty());
+
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        assert_eq!(unsets(&device).await, ["test/test/com.test.Expiring/token"]);
+        assert!(device.property_expiries.lock().unwrap().is_empty());
     }
 
-    /// Send data to an object interface. with timestamp
-    pub async fn send_object_with_timestamp<T>(
-        &self,
-        interface_name: &str,
-        interface_path: &str,
-        data: T,
-        timestamp: chrono::DateTime<chrono::Utc>,
-    ) -> Result<(), AstarteError>
-    where
-        T: serde::Serialize,
-    {
-        self.send_object_with_timestamp_impl(interface_name, interface_path, data, Some(timestamp))
+    #[tokio::test]
+    async fn test_set_property_expiry_reset() {
+        let device = expiring_device().await;
+
+        device
+            .set_property_expiry("com.test.Expiring", "/token", Duration::from_millis(300))
+            .await
+            .unwrap();
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        device
+            .set_property_expiry("com.test.Expiring", "/token", Duration::from_millis(300))
             .await
+            .unwrap();
+
+        // the first timer would have expired by now
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert!(unsets(&device).await.is_empty());
+
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        assert_eq!(unsets(&device).await, ["test/test/com.test.Expiring/token"]);
     }
 
-    /// Send data to an object interface. with timestamp
-    pub async fn send_object<T>(
-        &self,
-        interface_name: &str,
-        interface_path: &str,
-        data: T,
-    ) -> Result<(), AstarteError>
-    where
-        T: serde::Serialize,
-    {
-        self.send_object_with_timestamp_impl(interface_name, interface_path, data, None)
+    #[tokio::test]
+    async fn test_set_property_expiry_cancel() {
+        let device = expiring_device().await;
+
+        device
+            .set_property_expiry("com.test.Expiring", "/token", Duration::from_millis(100))
             .await
-    }
-}
+            .unwrap();
+        device
+            .unset::<AstarteType>("com.test.Expiring", "/token")
+            .await
+            .unwrap();
+        assert!(device.property_expiries.lock().unwrap().is_empty());
 
-impl fmt::Debug for AstarteSdk {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        f.debug_struct("Device")
-            .field("realm", &self.realm)
-            .field("device_id", &self.device_id)
-            .field("credentials_secret", &self.credentials_secret)
-            .field("pairing_url", &self.pairing_url)
-            .field("build_options", &self.build_options)
-            .finish()
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        assert_eq!(unsets(&device).await, ["test/test/com.test.Expiring/token"]);
     }
-}
 
-#[cfg(test)]
-mod test {
-    use chrono::{TimeZone, Utc};
+    #[tokio::test]
+    async fn test_publish_system_info_with() {
+        use crate::SystemInfo;
 
-    use crate::{types::AstarteType, AstarteSdk};
+        const SYSTEM_INFO_JSON: &str = r#"
+        {
+            "interface_name": "org.astarte-platform.generic.SystemInfo.v1",
+            "version_major": 1,
+            "version_minor": 0,
+            "type": "properties",
+            "ownership": "device",
+            "mappings": [
+                {
+                    "endpoint": "/hostname",
+                    "type": "string"
+                },
+                {
+                    "endpoint": "/osVersion",
+                    "type": "string"
+                },
+                {
+                    "endpoint": "/totalRam",
+                    "type": "longinteger"
+                },
+                {
+                    "endpoint": "/cpuModel",
+                    "type": "string"
+                }
+            ]
+        }
+        "#;
 
-    fn do_vecs_match(a: &[u8], b: &[u8]) -> bool {
-        let matching = a.iter().zip(b.iter()).filter(|&(a, b)| a == b).count();
+        let info = SystemInfo {
+            hostname: Some("sensor-box".into()),
+            os_version: Some("Linux 5.10.0".into()),
+            total_ram: Some(512 * 1024 * 1024),
+            cpu_model: None,
+        };
 
-        println!("matching {:?}\nwith     {:?}\n", a, b);
-        matching == a.len() && matching == b.len()
-    }
+        let mut options = AstarteBuilder::new("test", "test", "test", "test");
+        options.add_interface_files("examples/interfaces/").unwrap();
+        let device = options.connect_offline().await;
 
-    #[test]
-    fn serialize_individual() {
-        assert!(do_vecs_match(
-            &AstarteSdk::serialize_individual(false, None).unwrap(),
-            &[0x09, 0x00, 0x00, 0x00, 0x08, 0x76, 0x00, 0x00, 0x00]
-        ));
-        assert!(do_vecs_match(
-            &AstarteSdk::serialize_individual(AstarteType::Double(16.73), None).unwrap(),
-            &[
-                0x10, 0x00, 0x00, 0x00, 0x01, 0x76, 0x00, 0x7b, 0x14, 0xae, 0x47, 0xe1, 0xba, 0x30,
-                0x40, 0x00
-            ]
-        ));
-        assert!(do_vecs_match(
-            &AstarteSdk::serialize_individual(
-                AstarteType::Double(16.73),
-                Some(Utc.timestamp(1537449422, 890000000))
-            )
-            .unwrap(),
-            &[
-                0x1b, 0x00, 0x00, 0x00, 0x09, 0x74, 0x00, 0x2a, 0x70, 0x20, 0xf7, 0x65, 0x01, 0x00,
-                0x00, 0x01, 0x76, 0x00, 0x7b, 0x14, 0xae, 0x47, 0xe1, 0xba, 0x30, 0x40, 0x00
-            ]
-        ));
-    }
+        // the system info interface must be registered
+        assert!(device.publish_system_info_with(info.clone()).await.is_err());
 
-    #[test]
-    fn test_parse_topic() {
-        let topic = "test/u-WraCwtK_G_fjJf63TiAw/com.interface.test/led/red".to_owned();
-        let (realm, device, interface, path) = crate::parse_topic(&topic).unwrap();
-        assert!(realm == "test");
-        assert!(device == "u-WraCwtK_G_fjJf63TiAw");
-        assert!(interface == "com.interface.test");
-        assert!(path == "/led/red");
+        options.interfaces_from_slice(&[SYSTEM_INFO_JSON]).unwrap();
+        let device = options.connect_offline().await;
+
+        device.publish_system_info_with(info).await.unwrap();
+
+        // the cpu model is not available
+        let topics: Vec<_> = published(&device)
+            .await
+            .into_iter()
+            .map(|publish| publish.topic)
+            .collect();
+        assert_eq!(
+            topics,
+            [
+                "test/test/org.astarte-platform.generic.SystemInfo.v1/hostname",
+                "test/test/org.astarte-platform.generic.SystemInfo.v1/osVersion",
+                "test/test/org.astarte-platform.generic.SystemInfo.v1/totalRam",
+            ]
+        );
     }
 }
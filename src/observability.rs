@@ -0,0 +1,125 @@
+/*
+ * This file is part of Astarte.
+ *
+ * Copyright 2021 SECO Mind Srl
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *    http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Hooks to export the client activity to a telemetry system, e.g. OpenTelemetry
+
+use crate::AstarteError;
+
+/// Called by the client on the messages it exchanges with Astarte
+///
+/// The hooks are called from the tasks sending and polling the client, so they should
+/// return quickly.
+pub trait ObservabilityHook: Send + Sync + 'static {
+    /// A message of `size` bytes was published on an interface
+    fn on_publish(&self, interface: &str, path: &str, size: usize);
+
+    /// A message of `size` bytes was received on an interface
+    fn on_receive(&self, interface: &str, path: &str, size: usize);
+
+    /// Publishing a message or polling the connection failed
+    fn on_error(&self, error: &AstarteError);
+}
+
+/// Hook doing nothing, used when no hook is set on the builder
+#[derive(Debug, Default, Clone, Copy)]
+pub struct NoOpObservabilityHook;
+
+impl ObservabilityHook for NoOpObservabilityHook {
+    fn on_publish(&self, _interface: &str, _path: &str, _size: usize) {}
+
+    fn on_receive(&self, _interface: &str, _path: &str, _size: usize) {}
+
+    fn on_error(&self, _error: &AstarteError) {}
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{Arc, Mutex};
+
+    use chrono::Utc;
+
+    use super::ObservabilityHook;
+    use crate::builder::AstarteBuilder;
+    use crate::test::{closed_port, mock_broker};
+    use crate::AstarteError;
+
+    #[derive(Default, Clone)]
+    struct RecordingHook {
+        calls: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl ObservabilityHook for RecordingHook {
+        fn on_publish(&self, interface: &str, path: &str, size: usize) {
+            self.calls
+                .lock()
+                .unwrap()
+                .push(format!("publish {}{} {}", interface, path, size > 0));
+        }
+
+        fn on_receive(&self, interface: &str, path: &str, size: usize) {
+            self.calls
+                .lock()
+                .unwrap()
+                .push(format!("receive {}{} {}", interface, path, size > 0));
+        }
+
+        fn on_error(&self, error: &AstarteError) {
+            self.calls.lock().unwrap().push(format!("error {}", error));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_observability_hook() {
+        let hook = RecordingHook::default();
+
+        let mut options = AstarteBuilder::new("test", "test", "test", "test");
+        options.add_interface_files("examples/interfaces/").unwrap();
+        options.with_observability_hook(hook.clone());
+
+        let port = mock_broker(vec![(
+            "test/test/org.astarte-platform.genericsensors.SamplingRate/1/enable".into(),
+            crate::AstarteSdk::serialize_individual(true, None).unwrap(),
+        )])
+        .await;
+        let mut device = options.connect_offline().await;
+        device.eventloop.lock().await.options =
+            rumqttc::MqttOptions::new("test/test", "127.0.0.1", port);
+
+        device
+            .send_with_timestamp("com.test.Everything", "/integer", 42, Utc::now())
+            .await
+            .unwrap();
+        device.poll().await.unwrap();
+
+        // nothing listens on the port of the second client
+        let mut offline = options.connect_offline().await;
+        offline.eventloop.lock().await.options =
+            rumqttc::MqttOptions::new("test/test", "127.0.0.1", closed_port().await);
+        assert!(offline.poll().await.is_err());
+
+        assert_eq!(
+            *hook.calls.lock().unwrap(),
+            [
+                "publish com.test.Everything/integer true",
+                "publish control/emptyCache true",
+                "receive org.astarte-platform.genericsensors.SamplingRate/1/enable true",
+                "error mqtt connection error",
+            ]
+        );
+    }
+}
@@ -69,6 +69,32 @@ pub enum PairingError {
     Crypto(#[from] ErrorStack),
 }
 
+/// Status code to return when a pairing error is reported through a web API
+impl From<PairingError> for StatusCode {
+    fn from(err: PairingError) -> Self {
+        match err {
+            PairingError::InvalidCredentials => StatusCode::UNAUTHORIZED,
+            PairingError::ApiError(status_code, _) => status_code,
+            PairingError::RequestError(_) | PairingError::UnexpectedResponse => {
+                StatusCode::BAD_GATEWAY
+            }
+            PairingError::InvalidUrl(_) | PairingError::Crypto(_) => {
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+        }
+    }
+}
+
+/// Http client trusting the CA certificates added to the builder
+pub(crate) fn http_client(device: &AstarteBuilder) -> Result<reqwest::Client, PairingError> {
+    let mut builder = reqwest::Client::builder();
+    for cert in &device.tls_roots {
+        builder = builder.add_root_certificate(reqwest::Certificate::from_der(&cert.0)?);
+    }
+
+    Ok(builder.build()?)
+}
+
 pub async fn fetch_credentials(device: &AstarteBuilder, csr: &str) -> Result<String, PairingError> {
     let AstarteBuilder {
         realm,
@@ -97,7 +123,7 @@ pub async fn fetch_credentials(device: &AstarteBuilder, csr: &str) -> Result<Str
         }
     });
 
-    let client = reqwest::Client::new();
+    let client = http_client(device)?;
     let response = client
         .post(url)
         .bearer_auth(&credentials_secret)
@@ -142,7 +168,7 @@ pub async fn fetch_broker_url(device: &AstarteBuilder) -> Result<String, Pairing
         .push("devices")
         .push(device_id);
 
-    let client = reqwest::Client::new();
+    let client = http_client(device)?;
     let response = client
         .get(url)
         .bearer_auth(&credentials_secret)
@@ -171,3 +197,46 @@ pub async fn fetch_broker_url(device: &AstarteBuilder) -> Result<String, Pairing
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use http::StatusCode;
+    use openssl::error::ErrorStack;
+    use url::ParseError;
+
+    use super::PairingError;
+
+    #[test]
+    fn test_status_code() {
+        let request_error = reqwest::Client::new().get("not a url").build().unwrap_err();
+
+        let cases = vec![
+            (PairingError::InvalidCredentials, StatusCode::UNAUTHORIZED),
+            (
+                PairingError::ApiError(StatusCode::TOO_MANY_REQUESTS, String::new()),
+                StatusCode::TOO_MANY_REQUESTS,
+            ),
+            (
+                PairingError::ApiError(StatusCode::NOT_FOUND, String::new()),
+                StatusCode::NOT_FOUND,
+            ),
+            (
+                PairingError::RequestError(request_error),
+                StatusCode::BAD_GATEWAY,
+            ),
+            (PairingError::UnexpectedResponse, StatusCode::BAD_GATEWAY),
+            (
+                PairingError::InvalidUrl(ParseError::EmptyHost),
+                StatusCode::INTERNAL_SERVER_ERROR,
+            ),
+            (
+                PairingError::Crypto(ErrorStack::get()),
+                StatusCode::INTERNAL_SERVER_ERROR,
+            ),
+        ];
+
+        for (err, status_code) in cases {
+            assert_eq!(StatusCode::from(err), status_code);
+        }
+    }
+}
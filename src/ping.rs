@@ -0,0 +1,110 @@
+/*
+ * This file is part of Astarte.
+ *
+ * Copyright 2021 SECO Mind Srl
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *    http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use tokio::sync::oneshot;
+
+/// Matches the PINGRESP from the broker with the callers of [`AstarteSdk::ping`](crate::AstarteSdk::ping)
+///
+/// The event loop also sends a PINGREQ to keep the connection alive. The broker answers
+/// them in order, so a caller is woken only by the response to a PINGREQ sent after it
+/// started waiting, not by the one to a keep alive already in flight.
+#[derive(Default)]
+pub(crate) struct PingTracker {
+    // PINGREQ sent and PINGRESP received, counted from the first connection
+    sent: u64,
+    received: u64,
+    // each waiter with the number of PINGREQ sent before it
+    waiters: Vec<(u64, oneshot::Sender<()>)>,
+}
+
+impl PingTracker {
+    /// Wait for the response to the next PINGREQ
+    pub(crate) fn wait(&mut self) -> oneshot::Receiver<()> {
+        let (tx, rx) = oneshot::channel();
+        self.waiters.push((self.sent, tx));
+
+        rx
+    }
+
+    /// Handle an outgoing PINGREQ event
+    pub(crate) fn outgoing(&mut self) {
+        self.sent += 1;
+    }
+
+    /// Handle a PINGRESP from the broker
+    pub(crate) fn responded(&mut self) {
+        self.received += 1;
+
+        let received = self.received;
+        let (ready, pending): (Vec<_>, Vec<_>) = self
+            .waiters
+            .drain(..)
+            .partition(|(sent_before, _)| *sent_before < received);
+        self.waiters = pending;
+
+        for (_, waiter) in ready {
+            // the caller may have already timed out
+            let _ = waiter.send(());
+        }
+    }
+
+    /// Forget the PINGREQ sent on a lost connection, they will never be answered
+    pub(crate) fn connected(&mut self) {
+        self.received = self.sent;
+    }
+
+    /// Remove the waiters whose caller stopped waiting
+    pub(crate) fn remove_closed(&mut self) {
+        self.waiters.retain(|(_, waiter)| !waiter.is_closed());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::PingTracker;
+
+    #[test]
+    fn test_ping_tracker() {
+        let mut tracker = PingTracker::default();
+
+        // a keep alive is in flight when the caller starts waiting
+        tracker.outgoing();
+        let mut rx = tracker.wait();
+        tracker.outgoing();
+
+        tracker.responded();
+        assert!(rx.try_recv().is_err());
+
+        tracker.responded();
+        assert!(rx.try_recv().is_ok());
+
+        // the PINGREQ lost with the connection is never answered
+        let mut rx = tracker.wait();
+        tracker.outgoing();
+        tracker.connected();
+        tracker.outgoing();
+        tracker.responded();
+        assert!(rx.try_recv().is_ok());
+
+        // a caller that timed out is removed
+        let rx = tracker.wait();
+        drop(rx);
+        tracker.remove_closed();
+        assert!(tracker.waiters.is_empty());
+    }
+}
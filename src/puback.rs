@@ -0,0 +1,179 @@
+/*
+ * This file is part of Astarte.
+ *
+ * Copyright 2021 SECO Mind Srl
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *    http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+
+use tokio::sync::oneshot;
+
+/// Matches the publishes sent by the client with the broker acknowledgements
+///
+/// The event loop assigns the packet id when it takes a publish from the requests
+/// channel and reports it with an outgoing publish event. Publishes are taken in
+/// order, so every publish sent by the client must be queued here, even the ones
+/// nobody is waiting for.
+#[derive(Default)]
+pub(crate) struct PubackTracker {
+    queued: VecDeque<Option<oneshot::Sender<u16>>>,
+    // every QoS 1 and 2 publish not acknowledged yet, to recognize its retransmissions
+    inflight: HashMap<u16, Option<oneshot::Sender<u16>>>,
+}
+
+impl PubackTracker {
+    /// Queue a publish sent to the event loop, `waiter` receives the packet id once acknowledged
+    pub(crate) fn queue(&mut self, waiter: Option<oneshot::Sender<u16>>) {
+        self.queued.push_back(waiter);
+    }
+
+    /// Remove the last queued publish, when it couldn't be sent to the event loop
+    pub(crate) fn unqueue(&mut self) {
+        self.queued.pop_back();
+    }
+
+    /// Handle an outgoing publish event
+    pub(crate) fn outgoing(&mut self, pkid: u16) {
+        if self.inflight.contains_key(&pkid) {
+            // retransmission of a publish after a reconnection
+            return;
+        }
+
+        let waiter = self.queued.pop_front().flatten();
+        if pkid != 0 {
+            self.inflight.insert(pkid, waiter);
+        } else if let Some(waiter) = waiter {
+            // QoS 0 publishes are never acknowledged
+            let _ = waiter.send(pkid);
+        }
+    }
+
+    /// Handle a PUBACK or PUBCOMP from the broker
+    pub(crate) fn acked(&mut self, pkid: u16) {
+        if let Some(Some(waiter)) = self.inflight.remove(&pkid) {
+            // the sender may not be waiting anymore
+            let _ = waiter.send(pkid);
+        }
+    }
+}
+
+/// Publish queued in a [`PubackTracker`], removed again if dropped before being sent
+///
+/// Sending to the event loop waits for room in the requests channel, so the send can be
+/// cancelled, e.g. by a timeout, before the publish is handed over.
+pub(crate) struct QueuedPublish<'a> {
+    tracker: &'a Mutex<PubackTracker>,
+    sent: bool,
+}
+
+impl<'a> QueuedPublish<'a> {
+    pub(crate) fn new(
+        tracker: &'a Mutex<PubackTracker>,
+        waiter: Option<oneshot::Sender<u16>>,
+    ) -> Self {
+        tracker.lock().unwrap().queue(waiter);
+
+        QueuedPublish {
+            tracker,
+            sent: false,
+        }
+    }
+
+    /// The publish was handed to the event loop, keep it queued
+    pub(crate) fn sent(mut self) {
+        self.sent = true;
+    }
+}
+
+impl Drop for QueuedPublish<'_> {
+    fn drop(&mut self) {
+        if !self.sent {
+            self.tracker.lock().unwrap().unqueue();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Mutex;
+
+    use tokio::sync::oneshot;
+
+    use super::{PubackTracker, QueuedPublish};
+
+    #[test]
+    fn test_puback_tracker() {
+        let mut tracker = PubackTracker::default();
+
+        let (tx1, mut rx1) = oneshot::channel();
+        let (tx2, mut rx2) = oneshot::channel();
+
+        tracker.queue(Some(tx1));
+        tracker.queue(None);
+        tracker.queue(Some(tx2));
+
+        tracker.outgoing(1);
+        tracker.outgoing(2);
+        tracker.outgoing(3);
+
+        // a retransmission doesn't consume the queued publishes
+        tracker.outgoing(1);
+
+        assert!(rx1.try_recv().is_err());
+        assert!(rx2.try_recv().is_err());
+
+        tracker.acked(3);
+        assert_eq!(rx2.try_recv().unwrap(), 3);
+        assert!(rx1.try_recv().is_err());
+
+        tracker.acked(1);
+        assert_eq!(rx1.try_recv().unwrap(), 1);
+
+        // nobody waits for the publish with id 2, its retransmission must not take the next waiter
+        let (tx3, mut rx3) = oneshot::channel();
+        tracker.queue(Some(tx3));
+        tracker.outgoing(2);
+        tracker.outgoing(4);
+
+        tracker.acked(2);
+        assert!(rx3.try_recv().is_err());
+        tracker.acked(4);
+        assert_eq!(rx3.try_recv().unwrap(), 4);
+    }
+
+    #[test]
+    fn test_queued_publish() {
+        let tracker = Mutex::new(PubackTracker::default());
+
+        let (tx1, mut rx1) = oneshot::channel();
+        let (tx2, _rx2) = oneshot::channel();
+        let (tx3, mut rx3) = oneshot::channel();
+
+        QueuedPublish::new(&tracker, Some(tx1)).sent();
+        // cancelled before being handed to the event loop
+        drop(QueuedPublish::new(&tracker, Some(tx2)));
+        QueuedPublish::new(&tracker, Some(tx3)).sent();
+
+        let mut tracker = tracker.lock().unwrap();
+        tracker.outgoing(1);
+        tracker.outgoing(2);
+
+        tracker.acked(1);
+        assert_eq!(rx1.try_recv().unwrap(), 1);
+        tracker.acked(2);
+        assert_eq!(rx3.try_recv().unwrap(), 2);
+    }
+}
@@ -0,0 +1,76 @@
+/*
+ * This file is part of Astarte.
+ *
+ * Copyright 2021 SECO Mind Srl
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *    http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::path::Path;
+
+use bson::Bson;
+use serde_json::json;
+use tokio::fs::{File, OpenOptions};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Mutex;
+
+use crate::{Aggregation, AstarteError, Clientbound};
+
+/// Appends the data received from Astarte to a file, one json object per line
+#[derive(Debug)]
+pub struct EventRecorder {
+    file: Mutex<File>,
+}
+
+impl EventRecorder {
+    /// Opens the file in append mode, creating it if missing
+    pub async fn open<P: AsRef<Path>>(path: P) -> Result<Self, std::io::Error> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .await?;
+
+        Ok(EventRecorder {
+            file: Mutex::new(file),
+        })
+    }
+
+    /// Appends the event with the current time
+    pub async fn record(&self, event: &Clientbound) -> Result<(), AstarteError> {
+        let value = match &event.data {
+            Aggregation::Individual(value) => Bson::from(value.clone()),
+            Aggregation::Object(object) => Bson::Document(
+                object
+                    .iter()
+                    .map(|(key, value)| (key.clone(), Bson::from(value.clone())))
+                    .collect(),
+            ),
+        };
+
+        let mut line = json!({
+            "timestamp": chrono::Utc::now().to_rfc3339(),
+            "interface": event.interface,
+            "path": event.path,
+            "value": value.into_relaxed_extjson(),
+        })
+        .to_string();
+        line.push('\n');
+
+        let mut file = self.file.lock().await;
+        file.write_all(line.as_bytes()).await?;
+        file.flush().await?;
+
+        Ok(())
+    }
+}
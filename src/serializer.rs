@@ -0,0 +1,392 @@
+/*
+ * This file is part of Astarte.
+ *
+ * Copyright 2021 SECO Mind Srl
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *    http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Encoding of the payloads exchanged with Astarte
+//!
+//! The Astarte MQTT v1 protocol uses BSON, other formats need a broker that supports them.
+//! The properties are always stored in the database as BSON, whatever the format on the wire.
+
+use std::collections::HashMap;
+use std::convert::{TryFrom, TryInto};
+
+use bson::Bson;
+use chrono::{DateTime, TimeZone, Utc};
+use rmpv::Value;
+
+use crate::{types::AstarteType, Aggregation, AstarteError, AstarteSdk};
+
+/// Encodes and decodes the payloads of the messages exchanged with Astarte
+///
+/// Unsetting a property is always an empty payload, so [`AstarteType::Unset`] is never
+/// passed to the serializer.
+pub trait AstarteSerializer: Send + Sync + 'static {
+    fn serialize(
+        &self,
+        value: &Aggregation,
+        timestamp: Option<DateTime<Utc>>,
+    ) -> Result<Vec<u8>, AstarteError>;
+
+    fn deserialize(&self, bytes: &[u8]) -> Result<Aggregation, AstarteError>;
+}
+
+/// BSON payloads of the Astarte MQTT v1 protocol, the default
+#[derive(Debug, Default, Clone, Copy)]
+pub struct BsonSerializer;
+
+impl AstarteSerializer for BsonSerializer {
+    fn serialize(
+        &self,
+        value: &Aggregation,
+        timestamp: Option<DateTime<Utc>>,
+    ) -> Result<Vec<u8>, AstarteError> {
+        match value {
+            Aggregation::Individual(value) => {
+                AstarteSdk::serialize_individual(value.clone(), timestamp)
+            }
+            Aggregation::Object(object) => {
+                let doc = object
+                    .iter()
+                    .map(|(field, value)| (field.clone(), Bson::from(value.clone())))
+                    .collect();
+
+                AstarteSdk::serialize(Bson::Document(doc), timestamp, 0)
+            }
+        }
+    }
+
+    fn deserialize(&self, bytes: &[u8]) -> Result<Aggregation, AstarteError> {
+        AstarteSdk::deserialize(bytes)
+    }
+}
+
+/// MessagePack payloads, with the same `v` and `t` keys of the BSON ones
+///
+/// Date times are MessagePack timestamps. MessagePack doesn't keep the width of the integers,
+/// so the ones that fit in 32 bits are decoded as [`AstarteType::Integer`].
+#[derive(Debug, Default, Clone, Copy)]
+pub struct MsgpackSerializer;
+
+/// Extension type of the MessagePack timestamps
+const TIMESTAMP_EXT: i8 = -1;
+
+impl MsgpackSerializer {
+    fn timestamp(date_time: &DateTime<Utc>) -> Value {
+        // timestamp 96: nanoseconds as u32 and seconds as i64, big endian
+        let mut data = date_time.timestamp_subsec_nanos().to_be_bytes().to_vec();
+        data.extend_from_slice(&date_time.timestamp().to_be_bytes());
+
+        Value::Ext(TIMESTAMP_EXT, data)
+    }
+
+    fn date_time(value: &Value) -> Option<DateTime<Utc>> {
+        let data = match value {
+            Value::Ext(TIMESTAMP_EXT, data) => data,
+            _ => return None,
+        };
+
+        let (nanos, secs) = match data.len() {
+            4 => (0, i64::from(u32::from_be_bytes(data[..].try_into().ok()?))),
+            8 => {
+                let packed = u64::from_be_bytes(data[..].try_into().ok()?);
+                ((packed >> 34) as u32, (packed & 0x3_ffff_ffff) as i64)
+            }
+            12 => (
+                u32::from_be_bytes(data[..4].try_into().ok()?),
+                i64::from_be_bytes(data[4..].try_into().ok()?),
+            ),
+            _ => return None,
+        };
+
+        Utc.timestamp_opt(secs, nanos).single()
+    }
+
+    fn to_value(value: &AstarteType) -> Value {
+        fn array<T>(values: &[T], to_value: impl Fn(&T) -> Value) -> Value {
+            Value::Array(values.iter().map(to_value).collect())
+        }
+
+        match value {
+            AstarteType::Double(d) => Value::F64(*d),
+            AstarteType::Integer(i) => Value::from(*i),
+            AstarteType::Boolean(b) => Value::Boolean(*b),
+            AstarteType::LongInteger(i) => Value::from(*i),
+            AstarteType::String(s) => Value::from(s.as_str()),
+            AstarteType::BinaryBlob(b) => Value::Binary(b.clone()),
+            AstarteType::DateTime(d) => MsgpackSerializer::timestamp(d),
+            AstarteType::DoubleArray(a) => array(a, |d| Value::F64(*d)),
+            AstarteType::IntegerArray(a) => array(a, |i| Value::from(*i)),
+            AstarteType::BooleanArray(a) => array(a, |b| Value::Boolean(*b)),
+            AstarteType::LongIntegerArray(a) => array(a, |i| Value::from(*i)),
+            AstarteType::StringArray(a) => array(a, |s| Value::from(s.as_str())),
+            AstarteType::BinaryBlobArray(a) => array(a, |b| Value::Binary(b.clone())),
+            AstarteType::DateTimeArray(a) => array(a, MsgpackSerializer::timestamp),
+            AstarteType::Unset => Value::Nil,
+        }
+    }
+
+    fn from_value(value: &Value) -> Result<AstarteType, AstarteError> {
+        fn array<T>(
+            values: &[Value],
+            from_value: impl Fn(&Value) -> Option<T>,
+        ) -> Result<Vec<T>, AstarteError> {
+            values
+                .iter()
+                .map(|value| from_value(value).ok_or(AstarteError::DeserializationError))
+                .collect()
+        }
+
+        let value = match value {
+            Value::F32(d) => AstarteType::Double(f64::from(*d)),
+            Value::F64(d) => AstarteType::Double(*d),
+            Value::Boolean(b) => AstarteType::Boolean(*b),
+            Value::Integer(i) => {
+                let i = i.as_i64().ok_or(AstarteError::DeserializationError)?;
+                match i32::try_from(i) {
+                    Ok(i) => AstarteType::Integer(i),
+                    Err(_) => AstarteType::LongInteger(i),
+                }
+            }
+            Value::String(s) => AstarteType::String(
+                s.as_str()
+                    .ok_or(AstarteError::DeserializationError)?
+                    .to_owned(),
+            ),
+            Value::Binary(b) => AstarteType::BinaryBlob(b.clone()),
+            Value::Ext(..) => AstarteType::DateTime(
+                MsgpackSerializer::date_time(value).ok_or(AstarteError::DeserializationError)?,
+            ),
+            Value::Array(values) => match values.first() {
+                Some(Value::F32(_)) | Some(Value::F64(_)) => {
+                    AstarteType::DoubleArray(array(values, |v| v.as_f64())?)
+                }
+                Some(Value::Boolean(_)) => {
+                    AstarteType::BooleanArray(array(values, |v| v.as_bool())?)
+                }
+                Some(Value::Integer(_)) => {
+                    let integers = array(values, |v| v.as_i64())?;
+                    match integers
+                        .iter()
+                        .map(|i| i32::try_from(*i))
+                        .collect::<Result<Vec<_>, _>>()
+                    {
+                        Ok(integers) => AstarteType::IntegerArray(integers),
+                        Err(_) => AstarteType::LongIntegerArray(integers),
+                    }
+                }
+                Some(Value::String(_)) => {
+                    AstarteType::StringArray(array(values, |v| v.as_str().map(str::to_owned))?)
+                }
+                Some(Value::Binary(_)) => {
+                    AstarteType::BinaryBlobArray(array(values, |v| v.as_slice().map(Vec::from))?)
+                }
+                Some(Value::Ext(..)) => {
+                    AstarteType::DateTimeArray(array(values, MsgpackSerializer::date_time)?)
+                }
+                // the type of an empty array is unknown
+                _ => return Err(AstarteError::DeserializationError),
+            },
+            _ => return Err(AstarteError::DeserializationError),
+        };
+
+        Ok(value)
+    }
+}
+
+impl AstarteSerializer for MsgpackSerializer {
+    fn serialize(
+        &self,
+        value: &Aggregation,
+        timestamp: Option<DateTime<Utc>>,
+    ) -> Result<Vec<u8>, AstarteError> {
+        let value = match value {
+            Aggregation::Individual(AstarteType::Unset) => return Ok(Vec::new()),
+            Aggregation::Individual(value) => MsgpackSerializer::to_value(value),
+            Aggregation::Object(object) => Value::Map(
+                object
+                    .iter()
+                    .map(|(field, value)| {
+                        (
+                            Value::from(field.as_str()),
+                            MsgpackSerializer::to_value(value),
+                        )
+                    })
+                    .collect(),
+            ),
+        };
+
+        let mut document = vec![(Value::from("v"), value)];
+        if let Some(timestamp) = timestamp {
+            document.push((Value::from("t"), MsgpackSerializer::timestamp(&timestamp)));
+        }
+
+        let mut buf = Vec::new();
+        rmpv::encode::write_value(&mut buf, &Value::Map(document))
+            .map_err(|err| AstarteError::SendError(format!("msgpack encoding failed: {}", err)))?;
+
+        Ok(buf)
+    }
+
+    fn deserialize(&self, mut bytes: &[u8]) -> Result<Aggregation, AstarteError> {
+        if bytes.is_empty() {
+            return Ok(Aggregation::Individual(AstarteType::Unset));
+        }
+
+        let document =
+            rmpv::decode::read_value(&mut bytes).map_err(|_| AstarteError::DeserializationError)?;
+
+        let value = document
+            .as_map()
+            .and_then(|document| {
+                document
+                    .iter()
+                    .find(|(key, _)| key.as_str() == Some("v"))
+                    .map(|(_, value)| value)
+            })
+            .ok_or(AstarteError::DeserializationError)?;
+
+        match value {
+            Value::Map(object) => {
+                let object = object
+                    .iter()
+                    .map(|(field, value)| {
+                        let field = field
+                            .as_str()
+                            .ok_or(AstarteError::DeserializationError)?
+                            .to_owned();
+
+                        Ok((field, MsgpackSerializer::from_value(value)?))
+                    })
+                    .collect::<Result<HashMap<_, _>, AstarteError>>()?;
+
+                Ok(Aggregation::Object(object))
+            }
+            value => Ok(Aggregation::Individual(MsgpackSerializer::from_value(
+                value,
+            )?)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use chrono::{TimeZone, Utc};
+
+    use super::{AstarteSerializer, BsonSerializer, MsgpackSerializer};
+    use crate::{types::AstarteType, Aggregation, AstarteSdk};
+
+    fn values() -> Vec<AstarteType> {
+        let date_time = Utc.timestamp(1_627_580_808, 123_000_000);
+
+        vec![
+            AstarteType::Double(4.5),
+            AstarteType::Integer(-4),
+            AstarteType::Boolean(true),
+            AstarteType::LongInteger(45_543_543_534),
+            AstarteType::String("hello".into()),
+            AstarteType::BinaryBlob(b"hello".to_vec()),
+            AstarteType::DateTime(date_time),
+            AstarteType::DoubleArray(vec![1.2, 3.4]),
+            AstarteType::IntegerArray(vec![1, 3, 5]),
+            AstarteType::BooleanArray(vec![true, false]),
+            AstarteType::LongIntegerArray(vec![45_543_543_534, 1]),
+            AstarteType::StringArray(vec!["hello".into(), "world".into()]),
+            AstarteType::BinaryBlobArray(vec![b"hello".to_vec(), b"world".to_vec()]),
+            AstarteType::DateTimeArray(vec![date_time, date_time]),
+        ]
+    }
+
+    fn assert_individual(aggregation: Aggregation, expected: &AstarteType) {
+        match aggregation {
+            Aggregation::Individual(value) => assert_eq!(&value, expected),
+            Aggregation::Object(_) => panic!("expected an individual value"),
+        }
+    }
+
+    #[test]
+    fn test_bson_serializer() {
+        let timestamp = Some(Utc::now());
+
+        for value in values() {
+            let buf = BsonSerializer
+                .serialize(&Aggregation::Individual(value.clone()), timestamp)
+                .unwrap();
+            assert_eq!(
+                buf,
+                AstarteSdk::serialize_individual(value.clone(), timestamp).unwrap()
+            );
+            assert_individual(BsonSerializer.deserialize(&buf).unwrap(), &value);
+        }
+
+        let object: HashMap<String, AstarteType> = values()
+            .into_iter()
+            .enumerate()
+            .map(|(i, value)| (format!("field{}", i), value))
+            .collect();
+        let buf = BsonSerializer
+            .serialize(&Aggregation::Object(object.clone()), None)
+            .unwrap();
+        match BsonSerializer.deserialize(&buf).unwrap() {
+            Aggregation::Object(deserialized) => assert_eq!(deserialized, object),
+            Aggregation::Individual(_) => panic!("expected an object"),
+        }
+    }
+
+    #[test]
+    fn test_msgpack_serializer() {
+        let timestamp = Some(Utc::now());
+
+        for value in values() {
+            let buf = MsgpackSerializer
+                .serialize(&Aggregation::Individual(value.clone()), timestamp)
+                .unwrap();
+            assert_individual(MsgpackSerializer.deserialize(&buf).unwrap(), &value);
+        }
+
+        // {"v": true}
+        let buf = MsgpackSerializer
+            .serialize(&Aggregation::Individual(AstarteType::Boolean(true)), None)
+            .unwrap();
+        assert_eq!(buf, [0x81, 0xa1, b'v', 0xc3]);
+
+        let buf = MsgpackSerializer
+            .serialize(&Aggregation::Individual(AstarteType::Unset), None)
+            .unwrap();
+        assert!(buf.is_empty());
+        assert_individual(
+            MsgpackSerializer.deserialize(&buf).unwrap(),
+            &AstarteType::Unset,
+        );
+
+        let object: HashMap<String, AstarteType> = values()
+            .into_iter()
+            .enumerate()
+            .map(|(i, value)| (format!("field{}", i), value))
+            .collect();
+        let buf = MsgpackSerializer
+            .serialize(&Aggregation::Object(object.clone()), timestamp)
+            .unwrap();
+        match MsgpackSerializer.deserialize(&buf).unwrap() {
+            Aggregation::Object(deserialized) => assert_eq!(deserialized, object),
+            Aggregation::Individual(_) => panic!("expected an object"),
+        }
+
+        assert!(MsgpackSerializer.deserialize(b"not msgpack").is_err());
+    }
+}
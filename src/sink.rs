@@ -0,0 +1,140 @@
+/*
+ * This file is part of Astarte.
+ *
+ * Copyright 2021 SECO Mind Srl
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *    http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::pin::Pin;
+use std::sync::Mutex;
+use std::task::{Context, Poll};
+
+use chrono::{DateTime, Utc};
+use futures::future::BoxFuture;
+use futures::{ready, FutureExt, Sink};
+
+use crate::{types::AstarteType, AstarteError, AstarteSdk};
+
+/// Individual value pushed through the [`Sink`]: interface, path, value and timestamp
+pub type SinkItem = (String, String, AstarteType, Option<DateTime<Utc>>);
+
+/// Send in progress of a client used as a [`Sink`]
+///
+/// Every clone of the client is a separate sink, so the send is not cloned with it.
+#[derive(Default)]
+pub(crate) struct SinkState {
+    // behind a mutex only to keep the client Sync, it's always accessed through &mut
+    pending: Mutex<Option<BoxFuture<'static, Result<(), AstarteError>>>>,
+}
+
+impl Clone for SinkState {
+    fn clone(&self) -> Self {
+        SinkState::default()
+    }
+}
+
+impl SinkState {
+    /// Wait for the send in progress to complete
+    fn poll_pending(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), AstarteError>> {
+        let pending = self
+            .pending
+            .get_mut()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+
+        let res = match pending {
+            Some(send) => ready!(send.poll_unpin(cx)),
+            None => Ok(()),
+        };
+        *pending = None;
+
+        Poll::Ready(res)
+    }
+}
+
+/// Sends the values one at a time, like [`AstarteSdk::send_individual`]
+impl Sink<SinkItem> for AstarteSdk {
+    type Error = AstarteError;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.get_mut().sink.poll_pending(cx)
+    }
+
+    fn start_send(self: Pin<&mut Self>, item: SinkItem) -> Result<(), Self::Error> {
+        let this = self.get_mut();
+        let (interface, path, value, timestamp) = item;
+
+        let device = this.clone();
+        let send = async move {
+            device
+                .send_with_timestamp_impl(&interface, &path, value, timestamp)
+                .await
+        };
+
+        *this
+            .sink
+            .pending
+            .get_mut()
+            .unwrap_or_else(|poisoned| poisoned.into_inner()) = Some(send.boxed());
+
+        Ok(())
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.get_mut().sink.poll_pending(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.get_mut().sink.poll_pending(cx)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::Utc;
+    use futures::{stream, SinkExt};
+
+    use crate::builder::AstarteBuilder;
+    use crate::types::AstarteType;
+
+    #[tokio::test]
+    async fn test_sink() {
+        let mut options = AstarteBuilder::new("test", "test", "test", "test");
+        options.add_interface_files("examples/interfaces/").unwrap();
+        let mut device = options.connect_offline().await;
+
+        let items: Vec<_> = (0..5)
+            .map(|i| {
+                Ok::<_, crate::AstarteError>((
+                    "com.test.Everything".to_string(),
+                    "/integer".to_string(),
+                    AstarteType::Integer(i),
+                    Some(Utc::now()),
+                ))
+            })
+            .collect();
+
+        device.send_all(&mut stream::iter(items)).await.unwrap();
+        assert_eq!(device.connection_stats().total_publishes, 5);
+
+        let res = device
+            .send((
+                "com.test.Everything".to_string(),
+                "/integer".to_string(),
+                AstarteType::Boolean(true),
+                Some(Utc::now()),
+            ))
+            .await;
+        assert!(matches!(res, Err(crate::AstarteError::TypeMismatch { .. })));
+    }
+}
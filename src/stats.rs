@@ -0,0 +1,142 @@
+/*
+ * This file is part of Astarte.
+ *
+ * Copyright 2021 SECO Mind Srl
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *    http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::fmt;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+
+/// Cumulative statistics of the mqtt connection
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ConnectionStats {
+    pub total_publishes: u64,
+    pub total_receives: u64,
+    pub reconnect_count: u32,
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+}
+
+impl fmt::Display for ConnectionStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "sent {} messages ({} bytes), received {} messages ({} bytes), {} reconnections",
+            self.total_publishes,
+            self.bytes_sent,
+            self.total_receives,
+            self.bytes_received,
+            self.reconnect_count
+        )
+    }
+}
+
+/// Counters shared by all the clones of the client
+#[derive(Debug, Default)]
+pub(crate) struct StatsCounters {
+    publishes: AtomicU64,
+    receives: AtomicU64,
+    connections: AtomicU32,
+    connected: AtomicBool,
+    bytes_sent: AtomicU64,
+    bytes_received: AtomicU64,
+}
+
+impl StatsCounters {
+    pub(crate) fn published(&self, bytes: usize) {
+        self.publishes.fetch_add(1, Ordering::Relaxed);
+        self.bytes_sent.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn received(&self, bytes: usize) {
+        self.receives.fetch_add(1, Ordering::Relaxed);
+        self.bytes_received
+            .fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn connected(&self) {
+        self.connections.fetch_add(1, Ordering::Relaxed);
+        self.connected.store(true, Ordering::Relaxed);
+    }
+
+    pub(crate) fn disconnected(&self) {
+        self.connected.store(false, Ordering::Relaxed);
+    }
+
+    /// Whether the last connection to the broker is still up
+    pub(crate) fn is_connected(&self) -> bool {
+        self.connected.load(Ordering::Relaxed)
+    }
+
+    /// Zero the counters, the connection state is kept
+    pub(crate) fn reset(&self) {
+        self.publishes.store(0, Ordering::Relaxed);
+        self.receives.store(0, Ordering::Relaxed);
+        // a connection after the reset is still a reconnection
+        self.connections.fetch_min(1, Ordering::Relaxed);
+        self.bytes_sent.store(0, Ordering::Relaxed);
+        self.bytes_received.store(0, Ordering::Relaxed);
+    }
+
+    pub(crate) fn get(&self) -> ConnectionStats {
+        ConnectionStats {
+            total_publishes: self.publishes.load(Ordering::Relaxed),
+            total_receives: self.receives.load(Ordering::Relaxed),
+            // the first connection is not a reconnection
+            reconnect_count: self.connections.load(Ordering::Relaxed).saturating_sub(1),
+            bytes_sent: self.bytes_sent.load(Ordering::Relaxed),
+            bytes_received: self.bytes_received.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ConnectionStats, StatsCounters};
+
+    #[test]
+    fn test_stats_counters() {
+        let counters = StatsCounters::default();
+        assert_eq!(counters.get(), ConnectionStats::default());
+
+        assert!(!counters.is_connected());
+        counters.connected();
+        assert!(counters.is_connected());
+        counters.published(10);
+        counters.published(5);
+        counters.received(7);
+        assert_eq!(counters.get().reconnect_count, 0);
+
+        counters.disconnected();
+        assert!(!counters.is_connected());
+        counters.connected();
+        let stats = counters.get();
+
+        assert_eq!(
+            stats,
+            ConnectionStats {
+                total_publishes: 2,
+                total_receives: 1,
+                reconnect_count: 1,
+                bytes_sent: 15,
+                bytes_received: 7,
+            }
+        );
+        assert_eq!(
+            stats.to_string(),
+            "sent 2 messages (15 bytes), received 1 messages (7 bytes), 1 reconnections"
+        );
+    }
+}
@@ -0,0 +1,129 @@
+/*
+ * This file is part of Astarte.
+ *
+ * Copyright 2021 SECO Mind Srl
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *    http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::pin::Pin;
+use std::sync::Mutex;
+use std::task::{Context, Poll};
+
+use futures::future::BoxFuture;
+use futures::{ready, FutureExt, Stream};
+
+use crate::{AstarteError, AstarteSdk, Clientbound};
+
+/// Poll in progress of a client used as a [`Stream`]
+///
+/// Every clone of the client is a separate stream, so the poll is not cloned with it.
+#[derive(Default)]
+pub(crate) struct StreamState {
+    // behind a mutex only to keep the client Sync, it's always accessed through &mut
+    pending: Mutex<Option<BoxFuture<'static, Result<Clientbound, AstarteError>>>>,
+}
+
+impl Clone for StreamState {
+    fn clone(&self) -> Self {
+        StreamState::default()
+    }
+}
+
+impl StreamState {
+    fn pending(&mut self) -> &mut Option<BoxFuture<'static, Result<Clientbound, AstarteError>>> {
+        self.pending
+            .get_mut()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// Yields the results of [`AstarteSdk::poll`]
+///
+/// The stream never ends: like `poll`, it can be polled again after an error to reconnect.
+impl Stream for AstarteSdk {
+    type Item = Result<Clientbound, AstarteError>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+
+        if this.stream.pending().is_none() {
+            let mut device = this.clone();
+            *this.stream.pending() = Some(async move { device.poll().await }.boxed());
+        }
+
+        let pending = this.stream.pending();
+        let res = match pending {
+            Some(next) => ready!(next.poll_unpin(cx)),
+            None => unreachable!("the poll was just started"),
+        };
+        *pending = None;
+
+        Poll::Ready(Some(res))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use futures::StreamExt;
+
+    use crate::builder::AstarteBuilder;
+    use crate::test::{closed_port, mock_broker};
+    use crate::{AstarteError, AstarteSdk};
+
+    #[tokio::test]
+    async fn test_stream() {
+        let prefix = "test/test/org.astarte-platform.genericsensors.SamplingRate";
+        let port = mock_broker(
+            (1..=2)
+                .map(|i| {
+                    (
+                        format!("{}/{}/enable", prefix, i),
+                        AstarteSdk::serialize_individual(true, None).unwrap(),
+                    )
+                })
+                .collect(),
+        )
+        .await;
+
+        let mut options = AstarteBuilder::new("test", "test", "test", "test");
+        options.add_interface_files("examples/interfaces/").unwrap();
+        let device = options.connect_offline().await;
+        device.eventloop.lock().await.options =
+            rumqttc::MqttOptions::new("test/test", "127.0.0.1", port);
+
+        let paths: Vec<_> = device
+            .take(2)
+            .map(|event| event.unwrap().path)
+            .collect()
+            .await;
+        assert_eq!(paths, ["/1/enable", "/2/enable"]);
+    }
+
+    #[tokio::test]
+    async fn test_stream_errors() {
+        let mut options = AstarteBuilder::new("test", "test", "test", "test");
+        options.add_interface_files("examples/interfaces/").unwrap();
+        let device = options.connect_offline().await;
+        device.eventloop.lock().await.options =
+            rumqttc::MqttOptions::new("test/test", "127.0.0.1", closed_port().await);
+
+        // every poll fails to connect, the stream goes on
+        let events: Vec<_> = device.take(3).collect().await;
+
+        assert_eq!(events.len(), 3);
+        for event in events {
+            assert!(matches!(event, Err(AstarteError::ConnectionError(_))));
+        }
+    }
+}
@@ -0,0 +1,69 @@
+/*
+ * This file is part of Astarte.
+ *
+ * Copyright 2021 SECO Mind Srl
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *    http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Collects the system description published by [`AstarteSdk::publish_system_info`](crate::AstarteSdk::publish_system_info)
+
+use std::convert::TryFrom;
+
+use crate::SystemInfo;
+
+impl SystemInfo {
+    /// Read the description of the running system, the values that can't be read are `None`
+    pub fn collect() -> Self {
+        let hostname = hostname::get()
+            .map_err(|err| debug!("couldn't read the hostname: {}", err))
+            .ok()
+            .and_then(|hostname| hostname.into_string().ok());
+
+        let os_version = match (sys_info::os_type(), sys_info::os_release()) {
+            (Ok(os_type), Ok(release)) => Some(format!("{} {}", os_type, release)),
+            (Ok(os_type), Err(_)) => Some(os_type),
+            (Err(err), _) => {
+                debug!("couldn't read the os version: {}", err);
+                None
+            }
+        };
+
+        // the memory is reported in KiB
+        let total_ram = sys_info::mem_info()
+            .map_err(|err| debug!("couldn't read the memory info: {}", err))
+            .ok()
+            .and_then(|mem| i64::try_from(mem.total).ok())
+            .and_then(|total| total.checked_mul(1024));
+
+        SystemInfo {
+            hostname,
+            os_version,
+            total_ram,
+            cpu_model: cpu_model(),
+        }
+    }
+}
+
+/// Model of the first CPU, read from `/proc/cpuinfo` since sys-info doesn't report it
+fn cpu_model() -> Option<String> {
+    let cpuinfo = std::fs::read_to_string("/proc/cpuinfo").ok()?;
+
+    cpuinfo.lines().find_map(|line| {
+        let (key, value) = line.split_once(':')?;
+        match key.trim() {
+            "model name" | "Model" => Some(value.trim().to_owned()),
+            _ => None,
+        }
+    })
+}
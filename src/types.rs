@@ -19,6 +19,7 @@
 use std::convert::TryInto;
 
 use bson::{Binary, Bson};
+use itertools::Itertools;
 
 use crate::AstarteError;
 
@@ -253,7 +254,112 @@ impl std::convert::TryFrom<Bson> for AstarteType {
     }
 }
 
+/// Formats the value with the syntax of the Astarte query language, the same of JSON except
+/// for binary blobs, written as `0x` followed by their bytes in hex
+impl std::fmt::Display for AstarteType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        fn string(s: &str) -> String {
+            serde_json::Value::from(s).to_string()
+        }
+
+        fn binary(b: &[u8]) -> String {
+            let hex: String = b.iter().map(|b| format!("{:02x}", b)).collect();
+            format!("0x{}", hex)
+        }
+
+        fn date_time(d: &chrono::DateTime<chrono::Utc>) -> String {
+            string(&d.to_rfc3339_opts(chrono::SecondsFormat::AutoSi, true))
+        }
+
+        fn array<T, F: Fn(&T) -> String>(v: &[T], format: F) -> String {
+            format!("[{}]", v.iter().map(format).join(", "))
+        }
+
+        let value = match self {
+            AstarteType::Double(d) => d.to_string(),
+            AstarteType::Integer(i) => i.to_string(),
+            AstarteType::Boolean(b) => b.to_string(),
+            AstarteType::LongInteger(l) => l.to_string(),
+            AstarteType::String(s) => string(s),
+            AstarteType::BinaryBlob(b) => binary(b),
+            AstarteType::DateTime(d) => date_time(d),
+            AstarteType::DoubleArray(v) => array(v, f64::to_string),
+            AstarteType::IntegerArray(v) => array(v, i32::to_string),
+            AstarteType::BooleanArray(v) => array(v, bool::to_string),
+            AstarteType::LongIntegerArray(v) => array(v, i64::to_string),
+            AstarteType::StringArray(v) => array(v, |s| string(s)),
+            AstarteType::BinaryBlobArray(v) => array(v, |b| binary(b)),
+            AstarteType::DateTimeArray(v) => array(v, date_time),
+            AstarteType::Unset => "null".to_owned(),
+        };
+
+        write!(f, "{}", value)
+    }
+}
+
 impl AstarteType {
+    /// Returns the mapping type matching the value, `None` for [AstarteType::Unset]
+    pub(crate) fn mapping_type(&self) -> Option<crate::interface::MappingType> {
+        use crate::interface::MappingType;
+
+        let mapping_type = match self {
+            AstarteType::Double(_) => MappingType::Double,
+            AstarteType::Integer(_) => MappingType::Integer,
+            AstarteType::Boolean(_) => MappingType::Boolean,
+            AstarteType::LongInteger(_) => MappingType::LongInteger,
+            AstarteType::String(_) => MappingType::String,
+            AstarteType::BinaryBlob(_) => MappingType::BinaryBlob,
+            AstarteType::DateTime(_) => MappingType::DateTime,
+            AstarteType::DoubleArray(_) => MappingType::DoubleArray,
+            AstarteType::IntegerArray(_) => MappingType::IntegerArray,
+            AstarteType::BooleanArray(_) => MappingType::BooleanArray,
+            AstarteType::LongIntegerArray(_) => MappingType::LongIntegerArray,
+            AstarteType::StringArray(_) => MappingType::StringArray,
+            AstarteType::BinaryBlobArray(_) => MappingType::BinaryBlobArray,
+            AstarteType::DateTimeArray(_) => MappingType::DateTimeArray,
+            AstarteType::Unset => return None,
+        };
+
+        Some(mapping_type)
+    }
+
+    /// Estimated size in bytes of the value serialized in bson, to pre-allocate buffers
+    ///
+    /// This is an upper bound for the value alone, without the document containing it.
+    pub fn size_hint(&self) -> usize {
+        fn string_size(s: &str) -> usize {
+            // length and null terminator
+            4 + s.len() + 1
+        }
+
+        fn binary_size(b: &[u8]) -> usize {
+            // length and subtype
+            4 + 1 + b.len()
+        }
+
+        fn array_size<I: ExactSizeIterator<Item = usize>>(sizes: I) -> usize {
+            // each element has a type and the index as a null terminated string
+            let key_size = sizes.len().to_string().len() + 1;
+            4 + 1 + sizes.map(|size| 1 + key_size + size).sum::<usize>()
+        }
+
+        match self {
+            AstarteType::Double(_) | AstarteType::LongInteger(_) | AstarteType::DateTime(_) => 8,
+            AstarteType::Integer(_) => 4,
+            AstarteType::Boolean(_) => 1,
+            AstarteType::String(s) => string_size(s),
+            AstarteType::BinaryBlob(b) => binary_size(b),
+            AstarteType::DoubleArray(v) => array_size(v.iter().map(|_| 8)),
+            AstarteType::IntegerArray(v) => array_size(v.iter().map(|_| 4)),
+            AstarteType::BooleanArray(v) => array_size(v.iter().map(|_| 1)),
+            AstarteType::LongIntegerArray(v) => array_size(v.iter().map(|_| 8)),
+            AstarteType::StringArray(v) => array_size(v.iter().map(|s| string_size(s))),
+            AstarteType::BinaryBlobArray(v) => array_size(v.iter().map(|b| binary_size(b))),
+            AstarteType::DateTimeArray(v) => array_size(v.iter().map(|_| 8)),
+            AstarteType::Unset => 0,
+        }
+    }
+
     pub fn from_bson_vec(d: Vec<Bson>) -> Result<Vec<Self>, AstarteError> {
         let vec = d.iter().map(|f| f.clone().try_into());
         vec.collect()
@@ -264,12 +370,12 @@ impl AstarteType {
 
 mod test {
     use std::collections::HashMap;
+    use std::convert::TryFrom;
 
     use crate::{types::AstarteType, Aggregation, AstarteSdk};
 
-    #[test]
-    fn test_individual_serialization() {
-        let alltypes: Vec<AstarteType> = vec![
+    fn all_types() -> Vec<AstarteType> {
+        vec![
             AstarteType::Double(4.5),
             (-4).into(),
             true.into(),
@@ -290,9 +396,30 @@ mod test {
             ]
             .into(),
             AstarteType::Unset,
-        ];
+        ]
+    }
+
+    #[test]
+    fn test_size_hint() {
+        // document length and terminator, type and key of the value
+        const DOCUMENT_SIZE: usize = 4 + 1 + 1 + 2;
+
+        let long_array = AstarteType::IntegerArray((0..1000).collect());
+
+        for ty in all_types().into_iter().chain(std::iter::once(long_array)) {
+            let buf = AstarteSdk::serialize_individual(ty.clone(), None).unwrap();
 
-        for ty in alltypes {
+            assert!(
+                buf.len() <= ty.size_hint() + DOCUMENT_SIZE,
+                "size hint too small for {:?}",
+                ty
+            );
+        }
+    }
+
+    #[test]
+    fn test_individual_serialization() {
+        for ty in all_types() {
             println!("checking {:?}", ty);
 
             let buf = AstarteSdk::serialize_individual(ty.clone(), None).unwrap();
@@ -384,4 +511,71 @@ mod test {
         assert!(AstarteType::String("hello".to_owned()) == "hello");
         assert!(AstarteType::BinaryBlob(vec![1, 2, 3, 4]) == vec![1_u8, 2, 3, 4]);
     }
+
+    #[test]
+    fn test_display() {
+        let expected = vec![
+            "4.5",
+            "-4",
+            "true",
+            "45543543534",
+            "\"hello\"",
+            "0x68656c6c6f",
+            "\"2021-07-29T17:46:48Z\"",
+            "[1.2, 3.4, 5.6, 7.8]",
+            "[1, 3, 5, 7]",
+            "[true, false, true, true]",
+            "[45543543534, 45543543535, 45543543536]",
+            "[\"hello\", \"world\"]",
+            "[0x68656c6c6f, 0x776f726c64]",
+            "[\"2021-07-29T17:46:48Z\", \"2021-07-29T17:46:49Z\", \"2021-07-29T17:46:50Z\"]",
+            "null",
+        ];
+
+        for (ty, expected) in all_types().into_iter().zip(expected) {
+            assert_eq!(ty.to_string(), expected);
+
+            match ty {
+                AstarteType::BinaryBlob(_) | AstarteType::BinaryBlobArray(_) => {}
+                _ => {
+                    serde_json::from_str::<serde_json::Value>(expected).unwrap();
+                }
+            }
+        }
+
+        let special = AstarteType::String("quote \" backslash \\ newline \n tab \t".to_owned());
+        let formatted = special.to_string();
+        assert_eq!(formatted, r#""quote \" backslash \\ newline \n tab \t""#);
+        assert_eq!(
+            serde_json::from_str::<String>(&formatted).unwrap(),
+            "quote \" backslash \\ newline \n tab \t"
+        );
+    }
+
+    #[test]
+    fn test_from() {
+        assert_eq!(AstarteType::from(42_i32), AstarteType::Integer(42));
+        assert_eq!(AstarteType::from(42_i64), AstarteType::LongInteger(42));
+        assert_eq!(AstarteType::from(true), AstarteType::Boolean(true));
+        assert_eq!(
+            AstarteType::from("hello".to_owned()),
+            AstarteType::String("hello".to_owned())
+        );
+        assert_eq!(
+            AstarteType::from("hello"),
+            AstarteType::String("hello".to_owned())
+        );
+        assert_eq!(
+            AstarteType::from(vec![1_u8, 2, 3]),
+            AstarteType::BinaryBlob(vec![1, 2, 3])
+        );
+
+        // floats can't be NaN or infinite
+        assert_eq!(
+            AstarteType::try_from(4.5_f64).unwrap(),
+            AstarteType::Double(4.5)
+        );
+        assert!(AstarteType::try_from(f64::NAN).is_err());
+        assert!(AstarteType::try_from(f64::INFINITY).is_err());
+    }
 }